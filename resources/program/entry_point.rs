@@ -9,7 +9,7 @@ use solana_program::{
 };
 
 // Set by cargo-solana
-const NAME: &str = "PROGNAME";
+const NAME: &str = {{ crate_name | format_literal }};
 
 entrypoint!(entry_point);
 pub fn entry_point(
@@ -10,15 +10,10 @@ use thiserror::Error;
 #[derive(Debug, Error, FromPrimitive)]
 #[error("...")]
 pub enum CustomProgramError {
-    // Throw this error when attempting to
-    // initialize account that is already
-    // initialized
-    #[error("Account Already Initialized")]
-    AccountAlreadyInitializedError,
-    // Throw this error when there is a
-    // data version error detected
-    #[error("Data version mismatch")]
-    DataVersionMismatchError,
+    {% for error in errors %}
+    #[error({{ error.message | format_literal }})]
+    {{ error.name }},
+    {% endfor %}
     // Add custom errors here
 }
 
@@ -44,12 +39,11 @@ impl PrintProgramError for CustomProgramError {
         E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
     {
         match self {
-            CustomProgramError::AccountAlreadyInitializedError => {
-                println!("ERROR: Account Already Initialized")
-            }
-            CustomProgramError::DataVersionMismatchError => {
-                println!("ERROR: Data version mismatch")
+            {% for error in errors %}
+            CustomProgramError::{{ error.name }} => {
+                println!({{ ("ERROR: " ~ error.message) | format_literal }})
             }
+            {% endfor %}
         }
     }
 }
@@ -6,18 +6,24 @@ use solana_program::{borsh::try_from_slice_unchecked, program_error::ProgramErro
 #[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq)]
 /// All custom program instructions
 pub enum ProgramInstruction {
-    InitializeAccount,
-    SetContent(u8),
+    {% for ix in instructions %}
+    {% for line in ix.docs %}
+    /// {{ line }}
+    {% endfor %}
+    {{ ix.name }}{% if ix.fields %}({{ ix.fields }}){% endif %},
+    {% endfor %}
 }
 
 impl ProgramInstruction {
     /// Unpack inbound buffer to associated Instruction
     /// The expected format for input is a Borsh serialized vector
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let payload = try_from_slice_unchecked::<ProgramInstruction>(input).unwrap();
+        let payload = try_from_slice_unchecked::<ProgramInstruction>(input)
+            .map_err(|_| ProgramError::InvalidInstructionData)?;
         match payload {
-            ProgramInstruction::InitializeAccount => Ok(payload),
-            ProgramInstruction::SetContent(_) => Ok(payload),
+            {% for ix in instructions %}
+            ProgramInstruction::{{ ix.name }}{% if ix.fields %}(..){% endif %} => Ok(payload),
+            {% endfor %}
         }
     }
 }
@@ -1,4 +1,8 @@
 //! @brief lib
+{% if program_id %}
+
+solana_program::declare_id!("{{ program_id }}");
+{% endif %}
 
 mod entry_point;
 pub mod error;
@@ -0,0 +1,39 @@
+//! @brief Program entry point
+
+use crate::{error::CustomProgramError, process::process};
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+// Name of the program library, as cargo builds it
+const NAME: &str = env!("CARGO_CRATE_NAME");
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // This is expensive, delete when satisfied
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Normal processing
+    if let Err(error) = process(program_id, accounts, instruction_data) {
+        // catch the error so we can print it
+        error.print::<CustomProgramError>();
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+}
@@ -0,0 +1,16 @@
+//! Program core processing module
+
+use crate::instruction::ProgramInstruction;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey};
+
+/// Main processing entry point dispatches to specific
+/// instruction handlers
+pub fn process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // Unpack the inbound data, mapping instruction to appropriate function
+    match ProgramInstruction::unpack(instruction_data)? {
+    }
+}
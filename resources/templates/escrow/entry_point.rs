@@ -0,0 +1,197 @@
+//! @brief Program entry point
+
+// References program error and core processor
+use crate::{error::CustomProgramError, process::process};
+// Solana standard program crates
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+// Set by cargo-solana
+const NAME: &str = {{ crate_name | format_literal }};
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // This is expensive, delete when satisfied
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Normal processing
+    if let Err(error) = process(program_id, accounts, instruction_data) {
+        // catch the error so we can print it
+        error.print::<CustomProgramError>();
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::{instruction::ProgramInstruction, state::ESCROW_SEED};
+
+    use super::*;
+    use assert_matches::*;
+
+    use solana_program::{
+        instruction::{AccountMeta, Instruction},
+        system_program,
+    };
+    use solana_program_test::{processor, tokio, BanksClient, BanksClientError, ProgramTest};
+    use solana_sdk::{
+        account::Account, signature::Keypair, signer::Signer, transaction::Transaction,
+    };
+
+    /// Lamports of the taker wallet
+    const TAKER_LAMPORTS: u64 = 1_000_000_000;
+
+    /// Sets up the Program test with a funded taker besides the payer
+    async fn setup(program_id: &Pubkey, taker: &Pubkey) -> (BanksClient, Keypair) {
+        let mut program_test = ProgramTest::new(NAME, *program_id, processor!(entry_point));
+        program_test.add_account(
+            *taker,
+            Account {
+                lamports: TAKER_LAMPORTS,
+                ..Account::default()
+            },
+        );
+        let (banks_client, payer, _) = program_test.start().await;
+        (banks_client, payer)
+    }
+
+    /// Address of the escrow of `initializer`
+    fn escrow_address(program_id: &Pubkey, initializer: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[ESCROW_SEED, initializer.as_ref()], program_id).0
+    }
+
+    /// Offers `amount` against `expected`
+    fn initialize_ix(
+        program_id: &Pubkey,
+        initializer: &Pubkey,
+        amount: u64,
+        expected: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            *program_id,
+            &ProgramInstruction::InitializeEscrow(amount, expected),
+            vec![
+                AccountMeta::new(*initializer, true),
+                AccountMeta::new(escrow_address(program_id, initializer), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    }
+
+    /// Takes the escrow of `initializer`
+    fn exchange_ix(program_id: &Pubkey, taker: &Pubkey, initializer: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            *program_id,
+            &ProgramInstruction::Exchange,
+            vec![
+                AccountMeta::new(*taker, true),
+                AccountMeta::new(*initializer, false),
+                AccountMeta::new(escrow_address(program_id, initializer), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    }
+
+    /// Cancels the escrow of `initializer`, signed by `signer`
+    fn cancel_ix(program_id: &Pubkey, signer: &Pubkey, initializer: &Pubkey) -> Instruction {
+        Instruction::new_with_borsh(
+            *program_id,
+            &ProgramInstruction::Cancel,
+            vec![
+                AccountMeta::new(*signer, true),
+                AccountMeta::new(escrow_address(program_id, initializer), false),
+            ],
+        )
+    }
+
+    /// Submits the instructions signed by the payer and `signers`
+    async fn submit(
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        signers: &[&Keypair],
+        ix: &[Instruction],
+    ) -> Result<(), BanksClientError> {
+        let recent_blockhash = banks_client.get_latest_blockhash().await?;
+        let mut transaction = Transaction::new_with_payer(ix, Some(&payer.pubkey()));
+        let mut all_signers = vec![payer];
+        all_signers.extend_from_slice(signers);
+        transaction.sign(&all_signers, recent_blockhash);
+        banks_client.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_exchange_pass() {
+        let program_id = Pubkey::new_unique();
+        let taker = Keypair::new();
+        let (mut banks_client, payer) = setup(&program_id, &taker.pubkey()).await;
+        let initializer = payer.pubkey();
+        let escrow = escrow_address(&program_id, &initializer);
+
+        let ix = [initialize_ix(&program_id, &initializer, 1_000_000, 500_000)];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        assert!(banks_client.get_account(escrow).await.unwrap().is_some());
+
+        // The payer pays the fees so the taker balance only moves by the terms
+        let ix = [exchange_ix(&program_id, &taker.pubkey(), &initializer)];
+        assert_matches!(
+            submit(&mut banks_client, &payer, &[&taker], &ix).await,
+            Ok(())
+        );
+        assert_eq!(
+            banks_client.get_balance(taker.pubkey()).await.unwrap(),
+            TAKER_LAMPORTS - 500_000 + 1_000_000
+        );
+        assert!(banks_client.get_account(escrow).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pass() {
+        let program_id = Pubkey::new_unique();
+        let taker = Keypair::new();
+        let (mut banks_client, payer) = setup(&program_id, &taker.pubkey()).await;
+        let initializer = payer.pubkey();
+
+        let ix = [
+            initialize_ix(&program_id, &initializer, 1_000_000, 500_000),
+            cancel_ix(&program_id, &initializer, &initializer),
+        ];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        let escrow = escrow_address(&program_id, &initializer);
+        assert!(banks_client.get_account(escrow).await.unwrap().is_none());
+
+        // Nothing left to take
+        let ix = [exchange_ix(&program_id, &taker.pubkey(), &initializer)];
+        assert!(submit(&mut banks_client, &payer, &[&taker], &ix)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_not_initializer_fail() {
+        let program_id = Pubkey::new_unique();
+        let taker = Keypair::new();
+        let (mut banks_client, payer) = setup(&program_id, &taker.pubkey()).await;
+        let initializer = payer.pubkey();
+
+        let ix = [initialize_ix(&program_id, &initializer, 1_000_000, 500_000)];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        let ix = [cancel_ix(&program_id, &taker.pubkey(), &initializer)];
+        assert!(submit(&mut banks_client, &payer, &[&taker], &ix)
+            .await
+            .is_err());
+    }
+}
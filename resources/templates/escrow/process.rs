@@ -0,0 +1,155 @@
+//! Program core processing module
+
+use crate::{
+    error::CustomProgramError,
+    instruction::ProgramInstruction,
+    state::{EscrowState, ESCROW_SEED},
+};
+
+use solana_program::{
+    account_info::next_account_info,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
+};
+
+/// Checks `escrow` is the PDA of `initializer` owned by our program and
+/// returns its state
+fn escrow_state(
+    program_id: &Pubkey,
+    initializer: &AccountInfo,
+    escrow: &AccountInfo,
+) -> Result<EscrowState, ProgramError> {
+    if escrow.owner != program_id {
+        msg!(
+            "Fail: Escrow owner is {} and it should be {}.",
+            escrow.owner,
+            program_id
+        );
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // Just use unpack and it will check to see if initialized and fail if not
+    let escrow_state = EscrowState::unpack(&escrow.data.borrow())?;
+    if escrow_state.initializer() != initializer.key {
+        return Err(CustomProgramError::NotEscrowInitializer.into());
+    }
+    Ok(escrow_state)
+}
+
+/// Closes the escrow, moving its remaining lamports to `recipient`
+fn close_escrow(escrow: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+    **recipient.try_borrow_mut_lamports()? += escrow.lamports();
+    **escrow.try_borrow_mut_lamports()? = 0;
+    escrow.data.borrow_mut().fill(0);
+    Ok(())
+}
+
+/// Creates the escrow at the PDA of the initializer, funded with rent and
+/// the offered amount
+fn initialize_escrow(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    expected: u64,
+) -> ProgramResult {
+    msg!("Initialize escrow of {} for {}", amount, expected);
+    let account_info_iter = &mut accounts.iter();
+    let initializer = next_account_info(account_info_iter)?;
+    let escrow = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (address, bump) =
+        Pubkey::find_program_address(&[ESCROW_SEED, initializer.key.as_ref()], program_id);
+    if address != *escrow.key {
+        return Err(CustomProgramError::InvalidEscrowAddress.into());
+    }
+    if !escrow.data_is_empty() {
+        return Err(CustomProgramError::AccountAlreadyInitializedError.into());
+    }
+    // The program signs for the escrow address to create it
+    let lamports = Rent::get()?
+        .minimum_balance(EscrowState::LEN)
+        .checked_add(amount)
+        .ok_or(ProgramError::InvalidArgument)?;
+    invoke_signed(
+        &system_instruction::create_account(
+            initializer.key,
+            escrow.key,
+            lamports,
+            EscrowState::LEN as u64,
+            program_id,
+        ),
+        &[initializer.clone(), escrow.clone(), system_program.clone()],
+        &[&[ESCROW_SEED, initializer.key.as_ref(), &[bump]]],
+    )?;
+    let mut escrow_state = EscrowState::unpack_unchecked(&escrow.data.borrow())?;
+    escrow_state.set_initialized(*initializer.key, amount, expected);
+    EscrowState::pack(escrow_state, &mut escrow.data.borrow_mut())
+}
+
+/// Pays the expected amount to the initializer and releases the escrow,
+/// the offered amount to the taker and the rent to the initializer
+fn exchange(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let taker = next_account_info(account_info_iter)?;
+    let initializer = next_account_info(account_info_iter)?;
+    let escrow = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    if !taker.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let escrow_state = escrow_state(program_id, initializer, escrow)?;
+    invoke(
+        &system_instruction::transfer(taker.key, initializer.key, escrow_state.expected()),
+        &[taker.clone(), initializer.clone(), system_program.clone()],
+    )?;
+    **escrow.try_borrow_mut_lamports()? -= escrow_state.amount();
+    **taker.try_borrow_mut_lamports()? += escrow_state.amount();
+    close_escrow(escrow, initializer)?;
+    msg!(
+        "Exchanged {} for {}",
+        escrow_state.amount(),
+        escrow_state.expected()
+    );
+    Ok(())
+}
+
+/// Closes the escrow, returning all of its lamports to the initializer
+fn cancel(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let initializer = next_account_info(account_info_iter)?;
+    let escrow = next_account_info(account_info_iter)?;
+    if !initializer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    escrow_state(program_id, initializer, escrow)?;
+    close_escrow(escrow, initializer)?;
+    msg!("Escrow cancelled");
+    Ok(())
+}
+
+/// Main processing entry point dispatches to specific
+/// instruction handlers
+pub fn process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // Unpack the inbound data, mapping instruction to appropriate function
+    match ProgramInstruction::unpack(instruction_data)? {
+        ProgramInstruction::InitializeEscrow(amount, expected) => {
+            initialize_escrow(program_id, accounts, amount, expected)
+        }
+        ProgramInstruction::Exchange => exchange(program_id, accounts),
+        ProgramInstruction::Cancel => cancel(program_id, accounts),
+    }
+}
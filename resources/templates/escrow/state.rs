@@ -0,0 +1,105 @@
+//! @brief Escrow account state management
+
+use crate::error::CustomProgramError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    borsh::try_from_slice_unchecked,
+    msg,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+use std::io::BufWriter;
+
+/// Seed of escrow addresses, followed by the initializer
+pub const ESCROW_SEED: &[u8] = b"escrow";
+
+/// Maintains the terms of the escrow, the offered amount is held as
+/// lamports of the account
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct EscrowState {
+    is_initialized: bool,
+    data_version: u8,
+    initializer: Pubkey,
+    amount: u64,
+    expected: u64,
+}
+
+impl EscrowState {
+    /// Signal initialized with the terms of the escrow
+    pub fn set_initialized(&mut self, initializer: Pubkey, amount: u64, expected: u64) {
+        self.is_initialized = true;
+        self.initializer = initializer;
+        self.amount = amount;
+        self.expected = expected;
+    }
+    /// Gets the current data version
+    pub fn version(&self) -> u8 {
+        self.data_version
+    }
+    /// Get the escrow initializer
+    pub fn initializer(&self) -> &Pubkey {
+        &self.initializer
+    }
+    /// Get the amount offered to the taker
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+    /// Get the amount expected from the taker
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+}
+
+/// Declaration of the current data version.
+const DATA_VERSION: u8 = 1;
+
+/// Need size for escrow state
+/// 1 byte for 'is_initialized'
+/// 1 byte for 'data_version`
+/// 32 bytes for `initializer`
+/// 8 bytes for `amount`
+/// 8 bytes for `expected`
+const ESCROW_STATE_SPACE: usize = 50;
+
+/// Implement Sealed trait for EscrowState
+/// to satisfy Pack trait constraints
+impl Sealed for EscrowState {}
+
+/// Implement IsInitialized trait for EscrowState
+/// to satisfy Pack trait constraints
+impl IsInitialized for EscrowState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for EscrowState {
+    const LEN: usize = ESCROW_STATE_SPACE;
+
+    /// Store 'state' of account to its data area
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut bw = BufWriter::new(dst);
+        self.serialize(&mut bw).unwrap();
+    }
+
+    /// Retrieve 'state' of account from account data area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let initialized = src[0] != 0;
+        // Check initialized
+        if initialized {
+            // Version check
+            if src[1] == DATA_VERSION {
+                Ok(try_from_slice_unchecked::<EscrowState>(src)?)
+            } else {
+                msg!("Incoherrent data version detected");
+                Err(CustomProgramError::DataVersionMismatchError.into())
+            }
+        } else {
+            Ok(EscrowState {
+                data_version: DATA_VERSION,
+                ..EscrowState::default()
+            })
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! @brief lib
+{% if program_id %}
+
+solana_program::declare_id!("{{ program_id }}");
+{% endif %}
+
+// Solana standard program crates
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg, pubkey::Pubkey,
+};
+
+// Set by cargo-solana
+const NAME: &str = {{ crate_name | format_literal }};
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Your program logic goes here
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use assert_matches::*;
+
+    use solana_program::instruction::Instruction;
+    use solana_program_test::{processor, tokio, ProgramTest};
+    use solana_sdk::{signer::Signer, transaction::Transaction};
+
+    #[tokio::test]
+    async fn test_entry_point_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer, recent_blockhash) =
+            ProgramTest::new(NAME, program_id, processor!(entry_point))
+                .start()
+                .await;
+
+        // Any instruction data is accepted
+        let ix = [Instruction::new_with_bytes(program_id, &[1, 2, 3], vec![])];
+        let mut transaction = Transaction::new_with_payer(&ix, Some(&payer.pubkey()));
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+    }
+}
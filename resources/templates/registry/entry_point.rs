@@ -0,0 +1,237 @@
+//! @brief Program entry point
+
+// References program error and core processor
+use crate::{error::CustomProgramError, process::process};
+// Solana standard program crates
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+// Set by cargo-solana
+const NAME: &str = {{ crate_name | format_literal }};
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // This is expensive, delete when satisfied
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Normal processing
+    if let Err(error) = process(program_id, accounts, instruction_data) {
+        // catch the error so we can print it
+        error.print::<CustomProgramError>();
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::{
+        instruction::ProgramInstruction,
+        state::{EntryState, RegistryState, ENTRY_SEED, REGISTRY_SEED},
+    };
+
+    use super::*;
+    use assert_matches::*;
+
+    use solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program_pack::Pack,
+        system_program,
+    };
+    use solana_program_test::{processor, tokio, BanksClient, BanksClientError, ProgramTest};
+    use solana_sdk::{
+        account::Account, signature::Keypair, signer::Signer, transaction::Transaction,
+    };
+
+    /// Sets up the Program test with funded `wallets` besides the payer
+    async fn setup(program_id: &Pubkey, wallets: &[Pubkey]) -> (BanksClient, Keypair) {
+        let mut program_test = ProgramTest::new(NAME, *program_id, processor!(entry_point));
+        for wallet in wallets {
+            program_test.add_account(
+                *wallet,
+                Account {
+                    lamports: 1_000_000_000,
+                    ..Account::default()
+                },
+            );
+        }
+        let (banks_client, payer, _) = program_test.start().await;
+        (banks_client, payer)
+    }
+
+    /// Address of the registry of `authority`
+    fn registry_address(program_id: &Pubkey, authority: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[REGISTRY_SEED, authority.as_ref()], program_id).0
+    }
+
+    /// Address of entry `index` of `registry`
+    fn entry_address(program_id: &Pubkey, registry: &Pubkey, index: u32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[ENTRY_SEED, registry.as_ref(), &index.to_le_bytes()],
+            program_id,
+        )
+        .0
+    }
+
+    /// Label padded with zeroes
+    fn label(text: &str) -> [u8; 32] {
+        let mut label = [0_u8; 32];
+        label[..text.len()].copy_from_slice(text.as_bytes());
+        label
+    }
+
+    /// Builds an instruction on the registry of `authority` signed by
+    /// `signer`, on entry `index` if any
+    fn registry_instruction(
+        program_id: &Pubkey,
+        instruction: ProgramInstruction,
+        signer: &Pubkey,
+        authority: &Pubkey,
+        index: Option<u32>,
+    ) -> Instruction {
+        let registry = registry_address(program_id, authority);
+        let mut macc = vec![
+            AccountMeta::new(*signer, true),
+            AccountMeta::new(registry, false),
+        ];
+        if let Some(index) = index {
+            macc.push(AccountMeta::new(
+                entry_address(program_id, &registry, index),
+                false,
+            ));
+        }
+        if !matches!(instruction, ProgramInstruction::RemoveEntry(_)) {
+            macc.push(AccountMeta::new_readonly(system_program::id(), false));
+        }
+        Instruction::new_with_borsh(*program_id, &instruction, macc)
+    }
+
+    /// Submits the instructions signed by the payer and `signers`
+    async fn submit(
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        signers: &[&Keypair],
+        ix: &[Instruction],
+    ) -> Result<(), BanksClientError> {
+        let recent_blockhash = banks_client.get_latest_blockhash().await?;
+        let mut transaction = Transaction::new_with_payer(ix, Some(&payer.pubkey()));
+        let mut all_signers = vec![payer];
+        all_signers.extend_from_slice(signers);
+        transaction.sign(&all_signers, recent_blockhash);
+        banks_client.process_transaction(transaction).await
+    }
+
+    #[tokio::test]
+    async fn test_add_entries_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer) = setup(&program_id, &[]).await;
+        let authority = payer.pubkey();
+        let ix = [
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::InitializeRegistry,
+                &authority,
+                &authority,
+                None,
+            ),
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::AddEntry(label("first")),
+                &authority,
+                &authority,
+                Some(0),
+            ),
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::AddEntry(label("second")),
+                &authority,
+                &authority,
+                Some(1),
+            ),
+        ];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+
+        let registry = registry_address(&program_id, &authority);
+        let account = banks_client.get_account(registry).await.unwrap().unwrap();
+        assert_eq!(RegistryState::unpack(&account.data).unwrap().count(), 2);
+        let entry = entry_address(&program_id, &registry, 1);
+        let account = banks_client.get_account(entry).await.unwrap().unwrap();
+        let entry_state = EntryState::unpack(&account.data).unwrap();
+        assert_eq!(entry_state.index(), 1);
+        assert_eq!(entry_state.label(), &label("second"));
+    }
+
+    #[tokio::test]
+    async fn test_remove_entry_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer) = setup(&program_id, &[]).await;
+        let authority = payer.pubkey();
+        let ix = [
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::InitializeRegistry,
+                &authority,
+                &authority,
+                None,
+            ),
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::AddEntry(label("first")),
+                &authority,
+                &authority,
+                Some(0),
+            ),
+            registry_instruction(
+                &program_id,
+                ProgramInstruction::RemoveEntry(0),
+                &authority,
+                &authority,
+                Some(0),
+            ),
+        ];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        let registry = registry_address(&program_id, &authority);
+        let entry = entry_address(&program_id, &registry, 0);
+        assert!(banks_client.get_account(entry).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_entry_not_authority_fail() {
+        let program_id = Pubkey::new_unique();
+        let intruder = Keypair::new();
+        let (mut banks_client, payer) = setup(&program_id, &[intruder.pubkey()]).await;
+        let authority = payer.pubkey();
+        let ix = [registry_instruction(
+            &program_id,
+            ProgramInstruction::InitializeRegistry,
+            &authority,
+            &authority,
+            None,
+        )];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+
+        let ix = [registry_instruction(
+            &program_id,
+            ProgramInstruction::AddEntry(label("intruder")),
+            &intruder.pubkey(),
+            &authority,
+            Some(0),
+        )];
+        assert!(submit(&mut banks_client, &payer, &[&intruder], &ix)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Program core processing module
+
+use crate::{
+    error::CustomProgramError,
+    instruction::ProgramInstruction,
+    state::{EntryState, RegistryState, ENTRY_SEED, REGISTRY_SEED},
+};
+
+use solana_program::{
+    account_info::next_account_info, account_info::AccountInfo, entrypoint::ProgramResult, msg,
+    program::invoke_signed, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey,
+    rent::Rent, system_instruction, sysvar::Sysvar,
+};
+
+/// Checks each account to confirm it is owned by our program
+fn check_account_ownership(program_id: &Pubkey, accounts: &[&AccountInfo]) -> ProgramResult {
+    for account in accounts {
+        if account.owner != program_id {
+            msg!(
+                "Fail: Account owner is {} and it should be {}.",
+                account.owner,
+                program_id
+            );
+            return Err(ProgramError::IncorrectProgramId);
+        }
+    }
+    Ok(())
+}
+
+/// Creates a program account of `space` bytes at the PDA of `seeds`, paid
+/// by `payer`
+fn create_pda_account<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    if !account.data_is_empty() {
+        return Err(CustomProgramError::AccountAlreadyInitializedError.into());
+    }
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            account.key,
+            Rent::get()?.minimum_balance(space),
+            space as u64,
+            program_id,
+        ),
+        &[payer.clone(), account.clone(), system_program.clone()],
+        &[seeds],
+    )
+}
+
+/// Unpacks the registry, checking `authority` manages it and signed
+fn registry_state(
+    authority: &AccountInfo,
+    registry: &AccountInfo,
+) -> Result<RegistryState, ProgramError> {
+    // Just use unpack and it will check to see if initialized and fail if not
+    let registry_state = RegistryState::unpack(&registry.data.borrow())?;
+    if !authority.is_signer || registry_state.authority() != authority.key {
+        return Err(CustomProgramError::NotRegistryAuthority.into());
+    }
+    Ok(registry_state)
+}
+
+/// Creates the registry at the PDA of the authority
+fn initialize_registry(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Initialize registry");
+    let account_info_iter = &mut accounts.iter();
+    let authority = next_account_info(account_info_iter)?;
+    let registry = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    if !authority.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (address, bump) =
+        Pubkey::find_program_address(&[REGISTRY_SEED, authority.key.as_ref()], program_id);
+    if address != *registry.key {
+        return Err(CustomProgramError::InvalidRegistryAddress.into());
+    }
+    create_pda_account(
+        program_id,
+        authority,
+        registry,
+        system_program,
+        RegistryState::LEN,
+        &[REGISTRY_SEED, authority.key.as_ref(), &[bump]],
+    )?;
+    let mut registry_state = RegistryState::unpack_unchecked(&registry.data.borrow())?;
+    registry_state.set_initialized(*authority.key);
+    RegistryState::pack(registry_state, &mut registry.data.borrow_mut())
+}
+
+/// Registers `label` in a new entry at the next index of the registry
+fn add_entry(program_id: &Pubkey, accounts: &[AccountInfo], label: [u8; 32]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let authority = next_account_info(account_info_iter)?;
+    let registry = next_account_info(account_info_iter)?;
+    let entry = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    check_account_ownership(program_id, &[registry])?;
+    let mut registry_state = registry_state(authority, registry)?;
+    let index = registry_state.add_entry();
+    let index_bytes = index.to_le_bytes();
+    let (address, bump) = Pubkey::find_program_address(
+        &[ENTRY_SEED, registry.key.as_ref(), &index_bytes],
+        program_id,
+    );
+    if address != *entry.key {
+        return Err(CustomProgramError::InvalidEntryAddress.into());
+    }
+    create_pda_account(
+        program_id,
+        authority,
+        entry,
+        system_program,
+        EntryState::LEN,
+        &[ENTRY_SEED, registry.key.as_ref(), &index_bytes, &[bump]],
+    )?;
+    let mut entry_state = EntryState::unpack_unchecked(&entry.data.borrow())?;
+    entry_state.set_initialized(*registry.key, index, label);
+    EntryState::pack(entry_state, &mut entry.data.borrow_mut())?;
+    RegistryState::pack(registry_state, &mut registry.data.borrow_mut())?;
+    msg!("Added entry {}", index);
+    Ok(())
+}
+
+/// Closes the entry at `index`, returning its rent to the authority
+fn remove_entry(program_id: &Pubkey, accounts: &[AccountInfo], index: u32) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let authority = next_account_info(account_info_iter)?;
+    let registry = next_account_info(account_info_iter)?;
+    let entry = next_account_info(account_info_iter)?;
+    check_account_ownership(program_id, &[registry, entry])?;
+    registry_state(authority, registry)?;
+    let entry_state = EntryState::unpack(&entry.data.borrow())?;
+    if entry_state.registry() != registry.key || entry_state.index() != index {
+        return Err(CustomProgramError::InvalidEntryAddress.into());
+    }
+    **authority.try_borrow_mut_lamports()? += entry.lamports();
+    **entry.try_borrow_mut_lamports()? = 0;
+    entry.data.borrow_mut().fill(0);
+    msg!("Removed entry {}", index);
+    Ok(())
+}
+
+/// Main processing entry point dispatches to specific
+/// instruction handlers
+pub fn process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // Unpack the inbound data, mapping instruction to appropriate function
+    match ProgramInstruction::unpack(instruction_data)? {
+        ProgramInstruction::InitializeRegistry => initialize_registry(program_id, accounts),
+        ProgramInstruction::AddEntry(label) => add_entry(program_id, accounts, label),
+        ProgramInstruction::RemoveEntry(index) => remove_entry(program_id, accounts, index),
+    }
+}
@@ -0,0 +1,184 @@
+//! @brief Registry and entry account state management
+
+use crate::error::CustomProgramError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    borsh::try_from_slice_unchecked,
+    msg,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+use std::io::BufWriter;
+
+/// Seed of registry addresses, followed by the authority
+pub const REGISTRY_SEED: &[u8] = b"registry";
+/// Seed of entry addresses, followed by the registry and the entry index
+pub const ENTRY_SEED: &[u8] = b"entry";
+
+/// Maintains the registry, the authority managing it and the number of
+/// entries added
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct RegistryState {
+    is_initialized: bool,
+    data_version: u8,
+    authority: Pubkey,
+    count: u32,
+}
+
+impl RegistryState {
+    /// Signal initialized for `authority`
+    pub fn set_initialized(&mut self, authority: Pubkey) {
+        self.is_initialized = true;
+        self.authority = authority;
+    }
+    /// Gets the current data version
+    pub fn version(&self) -> u8 {
+        self.data_version
+    }
+    /// Get the registry authority
+    pub fn authority(&self) -> &Pubkey {
+        &self.authority
+    }
+    /// Get the number of entries added, the index of the next entry
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    /// Count a new entry, returning its index
+    pub fn add_entry(&mut self) -> u32 {
+        self.count += 1;
+        self.count - 1
+    }
+}
+
+/// Maintains a registered label
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct EntryState {
+    is_initialized: bool,
+    data_version: u8,
+    registry: Pubkey,
+    index: u32,
+    label: [u8; 32],
+}
+
+impl EntryState {
+    /// Signal initialized as entry `index` of `registry`
+    pub fn set_initialized(&mut self, registry: Pubkey, index: u32, label: [u8; 32]) {
+        self.is_initialized = true;
+        self.registry = registry;
+        self.index = index;
+        self.label = label;
+    }
+    /// Gets the current data version
+    pub fn version(&self) -> u8 {
+        self.data_version
+    }
+    /// Get the registry of the entry
+    pub fn registry(&self) -> &Pubkey {
+        &self.registry
+    }
+    /// Get the index of the entry in the registry
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    /// Get the registered label
+    pub fn label(&self) -> &[u8; 32] {
+        &self.label
+    }
+}
+
+/// Declaration of the current data version.
+const DATA_VERSION: u8 = 1;
+
+/// Need size for registry state
+/// 1 byte for 'is_initialized'
+/// 1 byte for 'data_version`
+/// 32 bytes for `authority`
+/// 4 bytes for `count`
+const REGISTRY_STATE_SPACE: usize = 38;
+
+/// Need size for entry state
+/// 1 byte for 'is_initialized'
+/// 1 byte for 'data_version`
+/// 32 bytes for `registry`
+/// 4 bytes for `index`
+/// 32 bytes for `label`
+const ENTRY_STATE_SPACE: usize = 70;
+
+/// Retrieve the state of an initialized account when its data version is
+/// current, `None` when not initialized
+fn unpack_versioned<T: BorshDeserialize>(src: &[u8]) -> Result<Option<T>, ProgramError> {
+    let initialized = src[0] != 0;
+    // Check initialized
+    if initialized {
+        // Version check
+        if src[1] == DATA_VERSION {
+            Ok(Some(try_from_slice_unchecked::<T>(src)?))
+        } else {
+            msg!("Incoherrent data version detected");
+            Err(CustomProgramError::DataVersionMismatchError.into())
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// Implement Sealed trait for RegistryState
+/// to satisfy Pack trait constraints
+impl Sealed for RegistryState {}
+
+/// Implement IsInitialized trait for RegistryState
+/// to satisfy Pack trait constraints
+impl IsInitialized for RegistryState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for RegistryState {
+    const LEN: usize = REGISTRY_STATE_SPACE;
+
+    /// Store 'state' of account to its data area
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut bw = BufWriter::new(dst);
+        self.serialize(&mut bw).unwrap();
+    }
+
+    /// Retrieve 'state' of account from account data area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        Ok(unpack_versioned(src)?.unwrap_or(RegistryState {
+            data_version: DATA_VERSION,
+            ..RegistryState::default()
+        }))
+    }
+}
+
+/// Implement Sealed trait for EntryState
+/// to satisfy Pack trait constraints
+impl Sealed for EntryState {}
+
+/// Implement IsInitialized trait for EntryState
+/// to satisfy Pack trait constraints
+impl IsInitialized for EntryState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for EntryState {
+    const LEN: usize = ENTRY_STATE_SPACE;
+
+    /// Store 'state' of account to its data area
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut bw = BufWriter::new(dst);
+        self.serialize(&mut bw).unwrap();
+    }
+
+    /// Retrieve 'state' of account from account data area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        Ok(unpack_versioned(src)?.unwrap_or(EntryState {
+            data_version: DATA_VERSION,
+            ..EntryState::default()
+        }))
+    }
+}
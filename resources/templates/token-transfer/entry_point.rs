@@ -0,0 +1,218 @@
+//! @brief Program entry point
+
+// References program error and core processor
+use crate::{error::CustomProgramError, process::process};
+// Solana standard program crates
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+// Set by cargo-solana
+const NAME: &str = {{ crate_name | format_literal }};
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // This is expensive, delete when satisfied
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Normal processing
+    if let Err(error) = process(program_id, accounts, instruction_data) {
+        // catch the error so we can print it
+        error.print::<CustomProgramError>();
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::{instruction::ProgramInstruction, process::AUTHORITY_SEED};
+
+    use super::*;
+    use assert_matches::*;
+
+    use solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program_pack::Pack,
+    };
+    use solana_program_test::{processor, tokio, BanksClient, BanksClientError, ProgramTest};
+    use solana_sdk::{
+        account::Account, signature::Keypair, signer::Signer, transaction::Transaction,
+    };
+    use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+    /// Token accounts of the tests, all of a single mint
+    struct Tokens {
+        wallet: Pubkey,
+        program: Pubkey,
+        destination: Pubkey,
+    }
+
+    /// Builds an account holding packed token program `state`
+    fn token_program_account<T: Pack>(state: T) -> Account {
+        let mut data = vec![0_u8; T::LEN];
+        T::pack(state, &mut data).unwrap();
+        Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: spl_token::id(),
+            ..Account::default()
+        }
+    }
+
+    /// Token account of `mint` owned by `owner` holding `amount`
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+        token_program_account(TokenAccount {
+            mint,
+            owner,
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        })
+    }
+
+    /// Sets up the Program test with token accounts owned by the payer and
+    /// by the program PDA, each holding 1000 tokens, and an empty destination
+    async fn setup(program_id: &Pubkey) -> (BanksClient, Keypair, Tokens) {
+        let mut program_test = ProgramTest::new(NAME, *program_id, processor!(entry_point));
+        // The payer owns the wallet tokens so it is made before the test starts
+        let payer = Keypair::new();
+        program_test.add_account(
+            payer.pubkey(),
+            Account {
+                lamports: 1_000_000_000,
+                ..Account::default()
+            },
+        );
+        let authority = Pubkey::find_program_address(&[AUTHORITY_SEED], program_id).0;
+        let mint = Pubkey::new_unique();
+        let tokens = Tokens {
+            wallet: Pubkey::new_unique(),
+            program: Pubkey::new_unique(),
+            destination: Pubkey::new_unique(),
+        };
+        program_test.add_account(
+            mint,
+            token_program_account(Mint {
+                supply: 2_000,
+                is_initialized: true,
+                ..Mint::default()
+            }),
+        );
+        program_test.add_account(tokens.wallet, token_account(mint, payer.pubkey(), 1_000));
+        program_test.add_account(tokens.program, token_account(mint, authority, 1_000));
+        program_test.add_account(
+            tokens.destination,
+            token_account(mint, Pubkey::new_unique(), 0),
+        );
+        let (banks_client, _, _) = program_test.start().await;
+        (banks_client, payer, tokens)
+    }
+
+    /// Builds a transfer instruction from `source`
+    fn transfer_ix(
+        program_id: &Pubkey,
+        instruction: ProgramInstruction,
+        source: &Pubkey,
+        destination: &Pubkey,
+        authority: &Pubkey,
+    ) -> Instruction {
+        let is_signer = matches!(instruction, ProgramInstruction::Transfer(_));
+        Instruction::new_with_borsh(
+            *program_id,
+            &instruction,
+            vec![
+                AccountMeta::new(*source, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(*authority, is_signer),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        )
+    }
+
+    /// Submits the instructions signed by the payer
+    async fn submit(
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        ix: &[Instruction],
+    ) -> Result<(), BanksClientError> {
+        let recent_blockhash = banks_client.get_latest_blockhash().await?;
+        let mut transaction = Transaction::new_with_payer(ix, Some(&payer.pubkey()));
+        transaction.sign(&[payer], recent_blockhash);
+        banks_client.process_transaction(transaction).await
+    }
+
+    /// Token amount held by the token account at `address`
+    async fn token_amount(banks_client: &mut BanksClient, address: Pubkey) -> u64 {
+        let account = banks_client.get_account(address).await.unwrap().unwrap();
+        TokenAccount::unpack(&account.data).unwrap().amount
+    }
+
+    #[tokio::test]
+    async fn test_transfer_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer, tokens) = setup(&program_id).await;
+        let ix = [transfer_ix(
+            &program_id,
+            ProgramInstruction::Transfer(300),
+            &tokens.wallet,
+            &tokens.destination,
+            &payer.pubkey(),
+        )];
+        assert_matches!(submit(&mut banks_client, &payer, &ix).await, Ok(()));
+        assert_eq!(token_amount(&mut banks_client, tokens.wallet).await, 700);
+        assert_eq!(
+            token_amount(&mut banks_client, tokens.destination).await,
+            300
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transfer_from_pda_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer, tokens) = setup(&program_id).await;
+        let authority = Pubkey::find_program_address(&[AUTHORITY_SEED], &program_id).0;
+        let ix = [transfer_ix(
+            &program_id,
+            ProgramInstruction::TransferFromPda(250),
+            &tokens.program,
+            &tokens.destination,
+            &authority,
+        )];
+        assert_matches!(submit(&mut banks_client, &payer, &ix).await, Ok(()));
+        assert_eq!(token_amount(&mut banks_client, tokens.program).await, 750);
+        assert_eq!(
+            token_amount(&mut banks_client, tokens.destination).await,
+            250
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transfer_from_pda_not_owned_fail() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer, tokens) = setup(&program_id).await;
+        let authority = Pubkey::find_program_address(&[AUTHORITY_SEED], &program_id).0;
+        // The program PDA cannot move the tokens of the wallet
+        let ix = [transfer_ix(
+            &program_id,
+            ProgramInstruction::TransferFromPda(250),
+            &tokens.wallet,
+            &tokens.destination,
+            &authority,
+        )];
+        assert!(submit(&mut banks_client, &payer, &ix).await.is_err());
+        assert_eq!(token_amount(&mut banks_client, tokens.wallet).await, 1_000);
+    }
+}
@@ -0,0 +1,10 @@
+//! @brief lib
+{% if program_id %}
+
+solana_program::declare_id!("{{ program_id }}");
+{% endif %}
+
+mod entry_point;
+pub mod error;
+pub mod instruction;
+pub mod process;
@@ -0,0 +1,101 @@
+//! Program core processing module
+
+use crate::{error::CustomProgramError, instruction::ProgramInstruction};
+
+use solana_program::{
+    account_info::next_account_info,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    pubkey::Pubkey,
+};
+
+/// Seed of the program authority address
+pub const AUTHORITY_SEED: &[u8] = b"authority";
+
+/// Checks the account is the SPL token program
+fn check_token_program(token_program: &AccountInfo) -> ProgramResult {
+    if *token_program.key != spl_token::id() {
+        return Err(CustomProgramError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// Transfers tokens, the source owner signing the instruction
+fn transfer(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let source = next_account_info(account_info_iter)?;
+    let destination = next_account_info(account_info_iter)?;
+    let owner = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    check_token_program(token_program)?;
+    // The signature of the owner carries over to the token program
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            owner.key,
+            &[],
+            amount,
+        )?,
+        &[
+            source.clone(),
+            destination.clone(),
+            owner.clone(),
+            token_program.clone(),
+        ],
+    )?;
+    msg!("Transferred {} tokens", amount);
+    Ok(())
+}
+
+/// Transfers tokens owned by the program PDA, the program signing for it
+fn transfer_from_pda(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let source = next_account_info(account_info_iter)?;
+    let destination = next_account_info(account_info_iter)?;
+    let authority = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    check_token_program(token_program)?;
+    let (address, bump) = Pubkey::find_program_address(&[AUTHORITY_SEED], program_id);
+    if address != *authority.key {
+        return Err(CustomProgramError::InvalidAuthority.into());
+    }
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+        )?,
+        &[
+            source.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        &[&[AUTHORITY_SEED, &[bump]]],
+    )?;
+    msg!("Transferred {} tokens from the program", amount);
+    Ok(())
+}
+
+/// Main processing entry point dispatches to specific
+/// instruction handlers
+pub fn process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // Unpack the inbound data, mapping instruction to appropriate function
+    match ProgramInstruction::unpack(instruction_data)? {
+        ProgramInstruction::Transfer(amount) => transfer(accounts, amount),
+        ProgramInstruction::TransferFromPda(amount) => {
+            transfer_from_pda(program_id, accounts, amount)
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! @brief Program entry point
+
+// References program error and core processor
+use crate::{error::CustomProgramError, process::process};
+// Solana standard program crates
+use solana_program::{
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, msg,
+    program_error::PrintProgramError, pubkey::Pubkey,
+};
+
+// Set by cargo-solana
+const NAME: &str = {{ crate_name | format_literal }};
+
+entrypoint!(entry_point);
+pub fn entry_point(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // This is expensive, delete when satisfied
+    msg!(
+        "Program {} id: {} accounts: {} data: {:?}",
+        NAME,
+        program_id,
+        accounts.len(),
+        instruction_data
+    );
+    // Normal processing
+    if let Err(error) = process(program_id, accounts, instruction_data) {
+        // catch the error so we can print it
+        error.print::<CustomProgramError>();
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+    use crate::{
+        instruction::ProgramInstruction,
+        state::{VaultState, VAULT_SEED},
+    };
+
+    use super::*;
+    use assert_matches::*;
+
+    use solana_program::{
+        instruction::{AccountMeta, Instruction},
+        program_pack::Pack,
+        system_program,
+    };
+    use solana_program_test::{processor, tokio, BanksClient, BanksClientError, ProgramTest};
+    use solana_sdk::{
+        account::Account, signature::Keypair, signer::Signer, transaction::Transaction,
+    };
+
+    /// Sets up the Program test with funded `wallets` besides the payer
+    async fn setup(program_id: &Pubkey, wallets: &[Pubkey]) -> (BanksClient, Keypair) {
+        let mut program_test = ProgramTest::new(NAME, *program_id, processor!(entry_point));
+        for wallet in wallets {
+            program_test.add_account(
+                *wallet,
+                Account {
+                    lamports: 1_000_000_000,
+                    ..Account::default()
+                },
+            );
+        }
+        let (banks_client, payer, _) = program_test.start().await;
+        (banks_client, payer)
+    }
+
+    /// Address of the vault of `owner`
+    fn vault_address(program_id: &Pubkey, owner: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[VAULT_SEED, owner.as_ref()], program_id).0
+    }
+
+    /// Builds an instruction with the signer and the vault of `owner`
+    fn vault_instruction(
+        program_id: &Pubkey,
+        instruction: ProgramInstruction,
+        signer: &Pubkey,
+        owner: &Pubkey,
+    ) -> Instruction {
+        let mut macc = vec![
+            AccountMeta::new(*signer, true),
+            AccountMeta::new(vault_address(program_id, owner), false),
+        ];
+        if !matches!(instruction, ProgramInstruction::Withdraw(_)) {
+            macc.push(AccountMeta::new_readonly(system_program::id(), false));
+        }
+        Instruction::new_with_borsh(*program_id, &instruction, macc)
+    }
+
+    /// Submits the instructions signed by the payer and `signers`
+    async fn submit(
+        banks_client: &mut BanksClient,
+        payer: &Keypair,
+        signers: &[&Keypair],
+        ix: &[Instruction],
+    ) -> Result<(), BanksClientError> {
+        let recent_blockhash = banks_client.get_latest_blockhash().await?;
+        let mut transaction = Transaction::new_with_payer(ix, Some(&payer.pubkey()));
+        let mut all_signers = vec![payer];
+        all_signers.extend_from_slice(signers);
+        transaction.sign(&all_signers, recent_blockhash);
+        banks_client.process_transaction(transaction).await
+    }
+
+    /// Lamports held by the account at `address`
+    async fn lamports(banks_client: &mut BanksClient, address: Pubkey) -> u64 {
+        banks_client.get_balance(address).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_deposit_withdraw_pass() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer) = setup(&program_id, &[]).await;
+        let owner = payer.pubkey();
+        let vault = vault_address(&program_id, &owner);
+        let rent = banks_client.get_rent().await.unwrap();
+        let minimum = rent.minimum_balance(VaultState::LEN);
+
+        let ix = [
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::InitializeVault,
+                &owner,
+                &owner,
+            ),
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::Deposit(1_000_000),
+                &owner,
+                &owner,
+            ),
+        ];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        assert_eq!(
+            lamports(&mut banks_client, vault).await,
+            minimum + 1_000_000
+        );
+
+        let ix = [vault_instruction(
+            &program_id,
+            ProgramInstruction::Withdraw(400_000),
+            &owner,
+            &owner,
+        )];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+        assert_eq!(lamports(&mut banks_client, vault).await, minimum + 600_000);
+    }
+
+    #[tokio::test]
+    async fn test_double_initialize_fail() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer) = setup(&program_id, &[]).await;
+        let owner = payer.pubkey();
+        let ix = [
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::InitializeVault,
+                &owner,
+                &owner,
+            ),
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::InitializeVault,
+                &owner,
+                &owner,
+            ),
+        ];
+        assert!(submit(&mut banks_client, &payer, &[], &ix).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_below_rent_fail() {
+        let program_id = Pubkey::new_unique();
+        let (mut banks_client, payer) = setup(&program_id, &[]).await;
+        let owner = payer.pubkey();
+        let ix = [
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::InitializeVault,
+                &owner,
+                &owner,
+            ),
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::Deposit(1_000),
+                &owner,
+                &owner,
+            ),
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::Withdraw(2_000),
+                &owner,
+                &owner,
+            ),
+        ];
+        assert!(submit(&mut banks_client, &payer, &[], &ix).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_not_owner_fail() {
+        let program_id = Pubkey::new_unique();
+        let thief = Keypair::new();
+        let (mut banks_client, payer) = setup(&program_id, &[thief.pubkey()]).await;
+        let owner = payer.pubkey();
+        let ix = [
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::InitializeVault,
+                &owner,
+                &owner,
+            ),
+            vault_instruction(
+                &program_id,
+                ProgramInstruction::Deposit(1_000_000),
+                &owner,
+                &owner,
+            ),
+        ];
+        assert_matches!(submit(&mut banks_client, &payer, &[], &ix).await, Ok(()));
+
+        // Anyone may deposit but only the owner withdraws
+        let ix = [vault_instruction(
+            &program_id,
+            ProgramInstruction::Deposit(1_000),
+            &thief.pubkey(),
+            &owner,
+        )];
+        assert_matches!(
+            submit(&mut banks_client, &payer, &[&thief], &ix).await,
+            Ok(())
+        );
+        let ix = [vault_instruction(
+            &program_id,
+            ProgramInstruction::Withdraw(1_000),
+            &thief.pubkey(),
+            &owner,
+        )];
+        assert!(submit(&mut banks_client, &payer, &[&thief], &ix)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Program core processing module
+
+use crate::{
+    error::CustomProgramError,
+    instruction::ProgramInstruction,
+    state::{VaultState, VAULT_SEED},
+};
+
+use solana_program::{
+    account_info::next_account_info,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
+};
+
+/// Checks the vault account is owned by our program
+fn check_vault_ownership(program_id: &Pubkey, vault: &AccountInfo) -> ProgramResult {
+    if vault.owner != program_id {
+        msg!(
+            "Fail: Vault owner is {} and it should be {}.",
+            vault.owner,
+            program_id
+        );
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Creates the vault at the PDA of the owner, which is the first in
+/// accounts and pays for it
+fn initialize_vault(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    msg!("Initialize vault");
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account_info(account_info_iter)?;
+    let vault = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let (address, bump) =
+        Pubkey::find_program_address(&[VAULT_SEED, owner.key.as_ref()], program_id);
+    if address != *vault.key {
+        return Err(CustomProgramError::InvalidVaultAddress.into());
+    }
+    if !vault.data_is_empty() {
+        return Err(CustomProgramError::AccountAlreadyInitializedError.into());
+    }
+    // The program signs for the vault address to create it
+    invoke_signed(
+        &system_instruction::create_account(
+            owner.key,
+            vault.key,
+            Rent::get()?.minimum_balance(VaultState::LEN),
+            VaultState::LEN as u64,
+            program_id,
+        ),
+        &[owner.clone(), vault.clone(), system_program.clone()],
+        &[&[VAULT_SEED, owner.key.as_ref(), &[bump]]],
+    )?;
+    let mut vault_state = VaultState::unpack_unchecked(&vault.data.borrow())?;
+    vault_state.set_initialized(*owner.key, bump);
+    VaultState::pack(vault_state, &mut vault.data.borrow_mut())
+}
+
+/// Transfers lamports from the depositor into the vault
+fn deposit(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let depositor = next_account_info(account_info_iter)?;
+    let vault = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    check_vault_ownership(program_id, vault)?;
+    // Just use unpack and it will check to see if initialized and fail if not
+    VaultState::unpack(&vault.data.borrow())?;
+    invoke(
+        &system_instruction::transfer(depositor.key, vault.key, amount),
+        &[depositor.clone(), vault.clone(), system_program.clone()],
+    )?;
+    msg!("Deposited {} lamports", amount);
+    Ok(())
+}
+
+/// Transfers lamports from the vault to its owner. The program owns the
+/// vault so it debits the lamports directly
+fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account_info(account_info_iter)?;
+    let vault = next_account_info(account_info_iter)?;
+    check_vault_ownership(program_id, vault)?;
+    let vault_state = VaultState::unpack(&vault.data.borrow())?;
+    if !owner.is_signer || owner.key != vault_state.owner() {
+        return Err(CustomProgramError::NotVaultOwner.into());
+    }
+    // The vault has to stay rent exempt
+    let minimum = Rent::get()?.minimum_balance(vault.data_len());
+    let remaining = vault
+        .lamports()
+        .checked_sub(amount)
+        .filter(|r| *r >= minimum)
+        .ok_or(CustomProgramError::InsufficientFunds)?;
+    **vault.try_borrow_mut_lamports()? = remaining;
+    **owner.try_borrow_mut_lamports()? += amount;
+    msg!("Withdrew {} lamports", amount);
+    Ok(())
+}
+
+/// Main processing entry point dispatches to specific
+/// instruction handlers
+pub fn process(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // Unpack the inbound data, mapping instruction to appropriate function
+    match ProgramInstruction::unpack(instruction_data)? {
+        ProgramInstruction::InitializeVault => initialize_vault(program_id, accounts),
+        ProgramInstruction::Deposit(amount) => deposit(program_id, accounts, amount),
+        ProgramInstruction::Withdraw(amount) => withdraw(program_id, accounts, amount),
+    }
+}
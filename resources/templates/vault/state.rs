@@ -0,0 +1,97 @@
+//! @brief Vault account state management
+
+use crate::error::CustomProgramError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    borsh::try_from_slice_unchecked,
+    msg,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+use std::io::BufWriter;
+
+/// Seed of vault addresses, followed by the owner
+pub const VAULT_SEED: &[u8] = b"vault";
+
+/// Maintains vault data, the funds are the lamports of the account
+#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]
+pub struct VaultState {
+    is_initialized: bool,
+    data_version: u8,
+    owner: Pubkey,
+    bump: u8,
+}
+
+impl VaultState {
+    /// Signal initialized for `owner` with the bump of the vault address
+    pub fn set_initialized(&mut self, owner: Pubkey, bump: u8) {
+        self.is_initialized = true;
+        self.owner = owner;
+        self.bump = bump;
+    }
+    /// Gets the current data version
+    pub fn version(&self) -> u8 {
+        self.data_version
+    }
+    /// Get the vault owner
+    pub fn owner(&self) -> &Pubkey {
+        &self.owner
+    }
+    /// Get the bump of the vault address
+    pub fn bump(&self) -> u8 {
+        self.bump
+    }
+}
+
+/// Declaration of the current data version.
+const DATA_VERSION: u8 = 1;
+
+/// Need size for vault state
+/// 1 byte for 'is_initialized'
+/// 1 byte for 'data_version`
+/// 32 bytes for `owner`
+/// 1 byte for `bump`
+const VAULT_STATE_SPACE: usize = 35;
+
+/// Implement Sealed trait for VaultState
+/// to satisfy Pack trait constraints
+impl Sealed for VaultState {}
+
+/// Implement IsInitialized trait for VaultState
+/// to satisfy Pack trait constraints
+impl IsInitialized for VaultState {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for VaultState {
+    const LEN: usize = VAULT_STATE_SPACE;
+
+    /// Store 'state' of account to its data area
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut bw = BufWriter::new(dst);
+        self.serialize(&mut bw).unwrap();
+    }
+
+    /// Retrieve 'state' of account from account data area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let initialized = src[0] != 0;
+        // Check initialized
+        if initialized {
+            // Version check
+            if src[1] == DATA_VERSION {
+                Ok(try_from_slice_unchecked::<VaultState>(src)?)
+            } else {
+                msg!("Incoherrent data version detected");
+                Err(CustomProgramError::DataVersionMismatchError.into())
+            }
+        } else {
+            Ok(VaultState {
+                data_version: DATA_VERSION,
+                ..VaultState::default()
+            })
+        }
+    }
+}
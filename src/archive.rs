@@ -0,0 +1,312 @@
+//! Templates from git repositories and tarballs
+//!
+//! `--template ../starters.git#v3` reads the template from revision `v3` of
+//! a local git repository and `--template starter.tar.gz` from a tarball.
+//! Both are read once into memory and pinned, to a commit id or to the
+//! sha256 of the tarball, which generated programs record in their
+//! `[package.metadata.cargo-solana]`.
+
+use crate::{
+    descriptor::{Archive, TemplateSource},
+    error::{CargoResult, ProgramError},
+};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Read,
+    path::{Component, Path},
+    process::Command,
+};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+/// Extensions of gzip compressed tarballs
+const TARBALL_EXTENSIONS: [&str; 2] = [".tar.gz", ".tgz"];
+
+/// Separates a git repository from the revision to read
+const REVISION_SEPARATOR: char = '#';
+
+/// Table of `[package.metadata]` recording where a program came from
+const METADATA_TABLE: &str = "cargo-solana";
+
+/// Reads the regular files of a tar stream as (path, content), refusing
+/// paths which would leave the template
+fn read_tar(origin: &str, reader: impl Read) -> CargoResult<Vec<(String, Vec<u8>)>> {
+    let fetch_error =
+        |e: std::io::Error| ProgramError::TemplateFetchError(origin.into(), e.to_string());
+    let mut archive = tar::Archive::new(reader);
+    let mut files = vec![];
+    for entry in archive.entries().map_err(fetch_error)? {
+        let mut entry = entry.map_err(fetch_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(fetch_error)?.into_owned();
+        let mut parts = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+                Component::CurDir => {}
+                _ => {
+                    return Err(ProgramError::TemplateFetchError(
+                        origin.into(),
+                        format!("entry {} leaves the template", path.display()),
+                    ))
+                }
+            }
+        }
+        let mut content = vec![];
+        entry.read_to_end(&mut content).map_err(fetch_error)?;
+        files.push((parts.join("/"), content));
+    }
+    Ok(files)
+}
+
+/// Drops the folder every file is in, as tarballs usually hold a single
+/// `name-version/` folder
+fn strip_common_folder(files: &mut [(String, Vec<u8>)]) {
+    let folder = match files.first().and_then(|(n, _)| n.split_once('/')) {
+        Some((folder, _)) => format!("{}/", folder),
+        None => return,
+    };
+    if files.iter().all(|(n, _)| n.starts_with(&folder)) {
+        for (name, _) in files.iter_mut() {
+            name.drain(..folder.len());
+        }
+    }
+}
+
+/// Runs git in `repo`, returning its output
+fn git(origin: &str, repo: &Path, args: &[&str]) -> CargoResult<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .map_err(|e| ProgramError::TemplateFetchError(origin.into(), e.to_string()))?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(ProgramError::TemplateFetchError(
+            origin.into(),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// Reads the template at `revision` of the git repository `repo`, a branch,
+/// tag or commit, pinned to its commit id
+fn load_git(origin: &str, repo: &Path, revision: &str) -> CargoResult<Archive> {
+    if !repo.is_dir() {
+        return Err(ProgramError::TemplateNotFound(origin.into()));
+    }
+    let revision = match revision.is_empty() {
+        true => "HEAD",
+        false => revision,
+    };
+    let commit = git(
+        origin,
+        repo,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", revision)],
+    )?;
+    let commit = String::from_utf8_lossy(&commit).trim().to_string();
+    let tar = git(origin, repo, &["archive", "--format=tar", &commit])?;
+    Ok(Archive {
+        source: origin.into(),
+        revision: commit,
+        files: read_tar(origin, tar.as_slice())?,
+    })
+}
+
+/// Reads the template in a gzip compressed tarball, pinned to its sha256
+fn load_tarball(origin: &str, path: &Path) -> CargoResult<Archive> {
+    if !path.is_file() {
+        return Err(ProgramError::TemplateNotFound(origin.into()));
+    }
+    let bytes = fs::read(path)?;
+    let mut files = read_tar(origin, GzDecoder::new(bytes.as_slice()))?;
+    strip_common_folder(&mut files);
+    let digest = Sha256::digest(&bytes);
+    Ok(Archive {
+        source: origin.into(),
+        revision: format!(
+            "sha256:{}",
+            digest
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        ),
+        files,
+    })
+}
+
+/// Reads a template given as `repo#revision` or as a tarball, relative to
+/// `base`. `None` for any other template, including a folder whose name
+/// holds the revision separator
+pub fn archive_template(template: &str, base: &Path) -> CargoResult<Option<TemplateSource>> {
+    if base.join(template).is_dir() {
+        return Ok(None);
+    }
+    if TARBALL_EXTENSIONS.iter().any(|e| template.ends_with(e)) {
+        let path = base.join(template);
+        let archive = load_tarball(&path.display().to_string(), &path)?;
+        return Ok(Some(TemplateSource::Archive(archive)));
+    }
+    match template.rsplit_once(REVISION_SEPARATOR) {
+        Some((repo, revision)) => {
+            let repo = base.join(repo);
+            let origin = format!("{}{}{}", repo.display(), REVISION_SEPARATOR, revision);
+            let archive = load_git(&origin, &repo, revision)?;
+            Ok(Some(TemplateSource::Archive(archive)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Records the template and revision a program was generated from in
+/// `[package.metadata.cargo-solana]` of its manifest
+pub fn record_template(doc: &mut DocumentMut, archive: &Archive) {
+    let package = match doc.get_mut("package").and_then(Item::as_table_mut) {
+        Some(package) => package,
+        None => return,
+    };
+    let metadata = package.entry("metadata").or_insert_with(|| {
+        let mut metadata = Table::new();
+        metadata.set_implicit(true);
+        Item::Table(metadata)
+    });
+    if let Some(metadata) = metadata.as_table_mut() {
+        let mut record = Table::new();
+        record.insert("template", value(&archive.source));
+        record.insert("revision", value(&archive.revision));
+        metadata.insert(METADATA_TABLE, Item::Table(record));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use tempfile::tempdir;
+
+    /// Runs git in `dir` for a test repository
+    fn git_in(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn git_template_pass() {
+        let base = tempdir().unwrap();
+        let repo = base.path().join("starters");
+        fs::create_dir_all(repo.join("src")).unwrap();
+        git_in(&repo, &["init", "--quiet"]);
+        fs::write(repo.join("src/lib.rs"), "// v3\n").unwrap();
+        git_in(&repo, &["add", "."]);
+        git_in(&repo, &["commit", "--quiet", "-m", "v3"]);
+        git_in(&repo, &["tag", "v3"]);
+        let v3 = git_in(&repo, &["rev-parse", "HEAD"]);
+        // Later changes are not seen through the tag
+        fs::write(repo.join("src/lib.rs"), "// v4\n").unwrap();
+        git_in(&repo, &["commit", "--quiet", "-am", "v4"]);
+        let source = archive_template("starters#v3", base.path())
+            .unwrap()
+            .unwrap();
+        let TemplateSource::Archive(archive) = &source else {
+            panic!("expected an archive, got {:?}", source);
+        };
+        assert_eq!(archive.revision, v3);
+        assert_eq!(archive.source, format!("{}#v3", repo.display()));
+        assert_eq!(source.files().unwrap(), vec!["src/lib.rs"]);
+        assert_eq!(
+            source.read("src/lib.rs").unwrap(),
+            Some(b"// v3\n".to_vec())
+        );
+        let TemplateSource::Archive(head) =
+            archive_template("starters#", base.path()).unwrap().unwrap()
+        else {
+            panic!()
+        };
+        assert_ne!(head.revision, v3);
+        assert!(archive_template("starters", base.path()).unwrap().is_none());
+        // A folder named with the separator is no revision of a repository
+        fs::create_dir(base.path().join("starters#v3")).unwrap();
+        assert!(archive_template("starters#v3", base.path())
+            .unwrap()
+            .is_none());
+        fs::create_dir(base.path().join("my#starters")).unwrap();
+        git_in(&base.path().join("my#starters"), &["init", "--quiet"]);
+        assert!(matches!(
+            archive_template("my#starters#v3", base.path()),
+            Err(ProgramError::TemplateFetchError(..))
+        ));
+    }
+
+    #[test]
+    fn git_template_fail() {
+        let base = tempdir().unwrap();
+        assert!(matches!(
+            archive_template("missing#v3", base.path()),
+            Err(ProgramError::TemplateNotFound(_))
+        ));
+        git_in(base.path(), &["init", "--quiet"]);
+        assert!(matches!(
+            archive_template(".#v3", base.path()),
+            Err(ProgramError::TemplateFetchError(..))
+        ));
+    }
+
+    #[test]
+    fn tarball_template_pass() {
+        let base = tempdir().unwrap();
+        let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        for (name, content) in [
+            ("starter-1.0/template.toml", b"[template]\n" as &[u8]),
+            ("starter-1.0/src/lib.rs", b"// lib\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, content).unwrap();
+        }
+        let bytes = tar.into_inner().unwrap().finish().unwrap();
+        fs::write(base.path().join("starter.tar.gz"), &bytes).unwrap();
+        let source = archive_template("starter.tar.gz", base.path())
+            .unwrap()
+            .unwrap();
+        assert_eq!(source.files().unwrap(), vec!["src/lib.rs", "template.toml"]);
+        let TemplateSource::Archive(archive) = source else {
+            panic!()
+        };
+        assert_eq!(archive.revision.len(), "sha256:".len() + 64);
+        assert!(matches!(
+            archive_template("other.tgz", base.path()),
+            Err(ProgramError::TemplateNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn record_template_pass() {
+        let mut doc = "[package]\nname = \"foo\"\n\n[dependencies]\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let archive = Archive {
+            source: "../starters.git#v3".to_string(),
+            revision: "0123abcd".to_string(),
+            files: vec![],
+        };
+        record_template(&mut doc, &archive);
+        assert_eq!(
+            doc.to_string(),
+            "[package]\nname = \"foo\"\n\n[package.metadata.cargo-solana]\n\
+             template = \"../starters.git#v3\"\nrevision = \"0123abcd\"\n\n[dependencies]\n"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Capturing a program as a template
+//!
+//! The reverse of generation: the files of an existing program are copied
+//! into a template folder, with the program name turned back into the
+//! template variables which produce it.
+
+use crate::{
+    descriptor::{Contents, DESCRIPTOR_FILE, MANIFEST_DEST},
+    error::{CargoResult, ProgramError},
+    template::templatize,
+    workspace::inline_workspace_entries,
+};
+use std::{fs, path::Path};
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Entries of a program folder which are not part of its sources
+const SKIPPED: [&str; 3] = [".git", "Cargo.lock", "target"];
+
+/// Template file holding the program manifest, which cargo does not mistake
+/// for a package
+const MANIFEST_SOURCE: &str = "prog.cargo.toml";
+
+/// Lists the program files by their path relative to `from`, leaving out
+/// build output and version control
+fn program_files(from: &Path) -> CargoResult<Vec<String>> {
+    let mut files = vec![];
+    let mut pending = vec![from.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .is_some_and(|f| SKIPPED.iter().any(|s| f == *s))
+            {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(from).unwrap_or(&path);
+            files.push(
+                relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            );
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Manifest of the workspace `from` belongs to, the closest one above it
+/// declaring `[workspace]`, including the program manifest itself
+fn workspace_manifest(from: &Path) -> CargoResult<Option<DocumentMut>> {
+    let from = from.canonicalize()?;
+    for dir in from.ancestors() {
+        let manifest = dir.join(MANIFEST_DEST);
+        if !manifest.is_file() {
+            continue;
+        }
+        let doc = fs::read_to_string(manifest)?.parse::<DocumentMut>()?;
+        if doc.contains_key("workspace") {
+            return Ok(Some(doc));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the program manifest in `from`, returning the package name and the
+/// manifest with the entries inherited from its workspace spelled out so
+/// the template does not depend on that workspace
+fn program_manifest(from: &Path) -> CargoResult<(String, DocumentMut)> {
+    let manifest_path = from.join(MANIFEST_DEST);
+    if !manifest_path.is_file() {
+        return Err(ProgramError::InvalidManifestPath(
+            manifest_path.display().to_string(),
+        ));
+    }
+    let mut doc = fs::read_to_string(&manifest_path)?.parse::<DocumentMut>()?;
+    let name = doc
+        .get("package")
+        .and_then(|p| p.get("name"))
+        .and_then(Item::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| ProgramError::ManifestFormatError("package.name".to_string()))?;
+    if let Some(root) = workspace_manifest(from)? {
+        inline_workspace_entries(&mut doc, &root);
+    }
+    doc.remove("workspace");
+    Ok((name, doc))
+}
+
+/// Writes the descriptor of a captured template, one file entry per
+/// (source, destination, binary) file
+fn descriptor_text(template: &str, program: &str, files: &[(String, String, bool)]) -> String {
+    let mut doc = DocumentMut::new();
+    let mut header = Table::new();
+    header.insert("name", value(template));
+    header.insert(
+        "description",
+        value(format!("Captured from program {}", program)),
+    );
+    header
+        .decor_mut()
+        .set_prefix("# Descriptor captured by `cargo solana template save`.\n\n");
+    doc.insert("template", Item::Table(header));
+    let mut entries = ArrayOfTables::new();
+    for (source, dest, binary) in files {
+        let mut entry = Table::new();
+        entry.insert("source", value(source));
+        entry.insert("dest", value(dest));
+        if *binary {
+            entry.insert("binary", value(true));
+        }
+        entries.push(entry);
+    }
+    doc.insert("files", Item::ArrayOfTables(entries));
+    doc.to_string()
+}
+
+/// Captures the program in `from` as the files of template `template`, its
+/// descriptor included, by path relative to the template folder. Text files
+/// and their destinations have the program name replaced by variables,
+/// other files are kept as binary
+pub fn capture_program(from: &Path, template: &str) -> CargoResult<Vec<(String, Contents)>> {
+    let (program, manifest) = program_manifest(from)?;
+    let mut captured = vec![];
+    let mut entries = vec![];
+    for file in program_files(from)? {
+        if file == DESCRIPTOR_FILE || file == MANIFEST_SOURCE {
+            return Err(ProgramError::TemplateFormatError(
+                from.display().to_string(),
+                format!("{} is reserved for templates", file),
+            ));
+        }
+        let rust = file.ends_with(".rs");
+        let dest = templatize(&file, &program, rust);
+        if file == MANIFEST_DEST {
+            let text = templatize(&manifest.to_string(), &program, false);
+            captured.push((MANIFEST_SOURCE.to_string(), Contents::Text(text)));
+            entries.push((MANIFEST_SOURCE.to_string(), dest, false));
+            continue;
+        }
+        match String::from_utf8(fs::read(from.join(&file))?) {
+            Ok(text) => {
+                captured.push((
+                    file.clone(),
+                    Contents::Text(templatize(&text, &program, rust)),
+                ));
+                entries.push((file, dest, false));
+            }
+            Err(e) => {
+                captured.push((file.clone(), Contents::Binary(e.into_bytes())));
+                entries.push((file, dest, true));
+            }
+        }
+    }
+    let descriptor = descriptor_text(template, &program, &entries);
+    captured.insert(0, (DESCRIPTOR_FILE.to_string(), Contents::Text(descriptor)));
+    Ok(captured)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        descriptor::{Descriptor, TemplateSource},
+        template::TemplateVars,
+    };
+    use tempfile::tempdir;
+
+    /// Lays out program `name` in `dir`, inheriting from a workspace
+    fn write_program(dir: &Path, name: &str) {
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion.workspace = true\n\n\
+                 [dependencies]\nborsh = {{ workspace = true, features = [\"derive\"] }}\n\
+                 solana-program = {{ workspace = true }}\n",
+                name
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("src/lib.rs"),
+            "//! my_vault program\npub struct MyVaultState;\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/my_vault.rs"), "pub fn f() {}\n").unwrap();
+        fs::write(dir.join("logo.png"), [0x89, 0x50, 0xff]).unwrap();
+        fs::write(dir.join("target/debug/out"), "").unwrap();
+        fs::write(dir.join("Cargo.lock"), "").unwrap();
+    }
+
+    #[test]
+    fn capture_program_pass() {
+        let root = tempdir().unwrap();
+        fs::write(
+            root.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"programs/my-vault\"]\n\n\
+             [workspace.package]\nversion = \"0.2.0\"\n\n\
+             [workspace.dependencies]\nborsh = { version = \"0.10.3\", features = [\"std\"] }\n\
+             solana-program = \"1.18.0\"\n",
+        )
+        .unwrap();
+        let from = root.path().join("programs/my-vault");
+        write_program(&from, "my-vault");
+        let captured = capture_program(&from, "house").unwrap();
+        let names = captured.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "template.toml",
+                "prog.cargo.toml",
+                "logo.png",
+                "src/lib.rs",
+                "src/my_vault.rs"
+            ]
+        );
+        assert_eq!(
+            captured[1].1,
+            Contents::Text(
+                "[package]\nname = \"{{ name }}\"\nversion = \"0.2.0\"\n\n\
+                 [dependencies]\nborsh = { version = \"0.10.3\", features = [\"std\", \"derive\"] }\n\
+                 solana-program = \"1.18.0\"\n"
+                    .to_string()
+            )
+        );
+        assert!(matches!(&captured[2].1, Contents::Binary(b) if b == &[0x89, 0x50, 0xff]));
+        // Written out, the capture is a template rendering another program
+        let template = root.path().join("house");
+        for (name, contents) in &captured {
+            let path = template.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            match contents {
+                Contents::Text(text) => fs::write(path, text).unwrap(),
+                Contents::Binary(bytes) => fs::write(path, bytes).unwrap(),
+            }
+        }
+        let source = TemplateSource::Folder(template);
+        let descriptor = Descriptor::load(&source).unwrap();
+        assert_eq!(descriptor.name, "house");
+        let rendered = descriptor
+            .render_files(&source, &TemplateVars::new("escrow", "1.18.0"))
+            .unwrap();
+        let dests = rendered.iter().map(|(d, _)| d.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            dests,
+            vec!["Cargo.toml", "logo.png", "src/lib.rs", "src/escrow.rs"]
+        );
+        assert_eq!(
+            rendered[2].1,
+            Contents::Text("//! escrow program\npub struct EscrowState;\n".to_string())
+        );
+    }
+
+    #[test]
+    fn capture_program_fail() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            capture_program(dir.path(), "house"),
+            Err(ProgramError::InvalidManifestPath(_))
+        ));
+        fs::write(dir.path().join("Cargo.toml"), "[workspace]\n").unwrap();
+        assert!(matches!(
+            capture_program(dir.path(), "house"),
+            Err(ProgramError::ManifestFormatError(_))
+        ));
+        write_program(dir.path(), "my-vault");
+        fs::write(dir.path().join("template.toml"), "").unwrap();
+        assert!(matches!(
+            capture_program(dir.path(), "house"),
+            Err(ProgramError::TemplateFormatError(..))
+        ));
+    }
+}
@@ -1,18 +1,46 @@
 //! Command line parsing and Connfiguration building
 
-use crate::utils::{build_program_manifest, project_template_as_manifest};
+use crate::{
+    descriptor::TemplateSource,
+    error::{CargoResult, ProgramError},
+    scaffold::{check_type_name, parse_account, parse_arg, parse_field, AccountSpec, FieldSpec},
+    template::{validate_program_id, validate_program_name},
+    utils::resolve_template,
+    version::{resolve_solana_version, resolve_workspace_solana_version, SOLANA_VERSION_ENV},
+};
 
 use {
     cargo_toml::Manifest,
-    clap::{command, AppSettings, Arg, Command},
-    std::env,
+    clap::{command, AppSettings, Arg, ArgMatches, Command},
+    dirs::config_dir,
+    std::{
+        env,
+        io::IsTerminal,
+        path::{Component, Path, PathBuf},
+    },
+    toml_edit::{DocumentMut, Item},
 };
 
+/// User configuration file below the platform configuration folder
+const CONFIG_FILE: &str = "cargo-solana/config.toml";
+
+/// User templates folder below the platform configuration folder
+const USER_TEMPLATES: &str = "cargo-solana/templates";
+
 /// Enum for process flow control
 #[derive(Debug)]
 pub enum ExecutionCommand {
     Create,
     Init,
+    Upgrade,
+    TemplateList,
+    TemplateShow,
+    TemplateRender,
+    TemplateSave,
+    AddInstruction,
+    AddError,
+    AddState,
+    Generate,
 }
 
 /// Configuration contains populated fields
@@ -21,15 +49,82 @@ pub enum ExecutionCommand {
 pub struct Configuration {
     pub progname: String,
     pub command: ExecutionCommand,
-    pub init_manifest: Option<Manifest>,
-    pub program_manifest_template: Manifest,
-    pub project_manifest_template: Manifest,
+    /// Directory the project is created in (create) or the
+    /// workspace root holding the manifest (init)
+    pub target_dir: PathBuf,
+    /// Program folder relative to the workspace root
+    pub program_dir: String,
+    /// Folder to relocate a root package into when making it a workspace
+    pub move_package: Option<String>,
+    /// Declare shared dependency versions in `[workspace.dependencies]`
+    pub workspace_deps: bool,
+    /// Apply an upgrade across incompatible Solana versions, or regenerate
+    /// handlers whose signatures the spec changes
+    pub force: bool,
+    /// Template used instead of the default built-in one
+    pub template: Option<TemplateSource>,
+    /// Folder holding the user templates, one folder each
+    pub user_templates: Option<PathBuf>,
+    /// Template variables given as `--var KEY=VALUE`
+    pub vars: Vec<(String, String)>,
+    /// Ask for template variables which were not given
+    pub interactive: bool,
+    /// Run the hooks of the template without asking
+    pub allow_hooks: bool,
+    /// Print the planned changes instead of writing them
+    pub dry_run: bool,
+    /// Solana version for program dependencies, or the upgrade target
+    pub solana_version: String,
+    /// Program author for templates, `Name <email>`
+    pub author: Option<String>,
+    /// SPDX license expression for templates
+    pub license: Option<String>,
+    /// Base58 program id for templates
+    pub program_id: Option<String>,
+    /// Typed fields given as `NAME:TYPE`, the arguments of an added instruction
+    /// or the fields of added state
+    pub fields: Vec<FieldSpec>,
+    /// Accounts of an added instruction
+    pub accounts: Vec<AccountSpec>,
+    /// Message of an added error
+    pub message: Option<String>,
+    /// Existing variant to add an error before, instead of after the last
+    pub before: Option<String>,
+    /// Program spec to generate the sources from
+    pub spec: Option<PathBuf>,
 }
 
 impl Configuration {
+    /// Configuration of `command` with every option unset
+    fn for_command(command: ExecutionCommand) -> Self {
+        Configuration {
+            progname: String::new(),
+            command,
+            target_dir: PathBuf::new(),
+            program_dir: String::new(),
+            move_package: None,
+            workspace_deps: false,
+            force: false,
+            template: None,
+            user_templates: None,
+            vars: vec![],
+            interactive: false,
+            allow_hooks: false,
+            dry_run: false,
+            solana_version: String::new(),
+            author: None,
+            license: None,
+            program_id: None,
+            fields: vec![],
+            accounts: vec![],
+            message: None,
+            before: None,
+            spec: None,
+        }
+    }
+
     /// Instantiate a new Configuration object by parsing the
-    /// command line and loading particular Cargo manifests either
-    /// from disk (init_manifest) or from this packages resources
+    /// command line and reading the user configuration file
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
         // If run normally, the args passed are 'cargo-solana', '<args>'.  However, if run as
         // a cargo subcommand (i.e. cargo solana <target dir>), then cargo injects a new arg:
@@ -54,36 +149,305 @@ impl Configuration {
             Err(e) => e.exit(),
             _ => {
                 let cmd_match = matches?;
-                let (cmd, name, manifest) = match cmd_match.subcommand() {
-                    Some(("create", s)) => (
-                        ExecutionCommand::Create,
-                        s.value_of("projprogname").unwrap(),
-                        None,
-                    ),
-                    Some(("init", s)) => (
-                        ExecutionCommand::Init,
-                        s.value_of("progname").unwrap(),
-                        Some(Manifest::from_path("./Cargo.toml")?),
-                    ),
+                // Template subcommands take their arguments one level down
+                let sub_match = match cmd_match.subcommand().unwrap() {
+                    ("template" | "add", t) => t.subcommand().unwrap().1,
+                    (_, s) => s,
+                };
+                let env_version = env::var(SOLANA_VERSION_ENV).ok();
+                let env_version = env_version.as_deref();
+                // Program name, folders and Solana version of each subcommand,
+                // completed below with the flags and the user configuration
+                let mut config = match cmd_match.subcommand() {
+                    Some(("create", s)) => Configuration {
+                        progname: s.value_of("projprogname").unwrap().to_string(),
+                        target_dir: PathBuf::from(s.value_of("path").unwrap()),
+                        program_dir: s.value_of("dir").unwrap().to_string(),
+                        solana_version: resolve_solana_version(
+                            s.value_of("solana-version"),
+                            env_version,
+                        )?,
+                        ..Configuration::for_command(ExecutionCommand::Create)
+                    },
+                    Some(("init", s)) => {
+                        let manifest_path = PathBuf::from(s.value_of("manifest-path").unwrap());
+                        // Validate any existing manifest before planning any change,
+                        // a missing one is created as a new workspace
+                        if manifest_path.exists() {
+                            Manifest::from_path(&manifest_path)?;
+                        }
+                        let root = manifest_root(&manifest_path)?;
+                        // Align with solana versions the workspace already uses
+                        let solana_version = resolve_workspace_solana_version(
+                            s.value_of("solana-version"),
+                            env_version,
+                            &root,
+                        )?;
+                        Configuration {
+                            progname: s.value_of("progname").unwrap().to_string(),
+                            target_dir: root,
+                            program_dir: s.value_of("dir").unwrap().to_string(),
+                            move_package: s.value_of("move-package").map(str::to_string),
+                            solana_version,
+                            ..Configuration::for_command(ExecutionCommand::Init)
+                        }
+                    }
+                    Some(("upgrade", s)) => {
+                        let manifest_path = PathBuf::from(s.value_of("manifest-path").unwrap());
+                        // The workspace to upgrade must exist
+                        Manifest::from_path(&manifest_path)?;
+                        Configuration {
+                            target_dir: manifest_root(&manifest_path)?,
+                            solana_version: resolve_solana_version(s.value_of("to"), env_version)?,
+                            ..Configuration::for_command(ExecutionCommand::Upgrade)
+                        }
+                    }
+                    Some(("generate", s)) => {
+                        let manifest_path = PathBuf::from(s.value_of("manifest-path").unwrap());
+                        Configuration {
+                            target_dir: manifest_root(&manifest_path)?,
+                            ..Configuration::for_command(ExecutionCommand::Generate)
+                        }
+                    }
+                    Some(("template", t)) => match t.subcommand() {
+                        Some(("list", _)) => {
+                            Configuration::for_command(ExecutionCommand::TemplateList)
+                        }
+                        Some(("show", _)) => {
+                            Configuration::for_command(ExecutionCommand::TemplateShow)
+                        }
+                        Some(("render", s)) => {
+                            let (parent, dir) =
+                                output_folder(Path::new(s.value_of("out").unwrap()))?;
+                            Configuration {
+                                progname: s.value_of("progname").unwrap_or(&dir).to_string(),
+                                target_dir: parent,
+                                program_dir: dir,
+                                solana_version: resolve_solana_version(
+                                    s.value_of("solana-version"),
+                                    env_version,
+                                )?,
+                                ..Configuration::for_command(ExecutionCommand::TemplateRender)
+                            }
+                        }
+                        Some(("save", s)) => Configuration {
+                            progname: s.value_of("name").unwrap().to_string(),
+                            target_dir: PathBuf::from(s.value_of("from").unwrap()),
+                            ..Configuration::for_command(ExecutionCommand::TemplateSave)
+                        },
+                        _ => unreachable!(),
+                    },
+                    Some(("add", a)) => {
+                        let (command, s) = match a.subcommand() {
+                            Some(("instruction", s)) => (ExecutionCommand::AddInstruction, s),
+                            Some(("error", s)) => (ExecutionCommand::AddError, s),
+                            Some(("state", s)) => (ExecutionCommand::AddState, s),
+                            _ => unreachable!(),
+                        };
+                        let manifest_path = PathBuf::from(s.value_of("manifest-path").unwrap());
+                        Configuration {
+                            progname: s.value_of("name").unwrap().to_string(),
+                            target_dir: manifest_root(&manifest_path)?,
+                            ..Configuration::for_command(command)
+                        }
+                    }
                     _ => unreachable!(),
                 };
+                config.user_templates = config_dir().map(|d| d.join(USER_TEMPLATES));
 
-                // Complete configuration with
-                // Preformatted program manifest
-                // Project manifest
-                Configuration {
-                    command: cmd,
-                    init_manifest: manifest,
-                    program_manifest_template: build_program_manifest(name.to_string())?,
-                    project_manifest_template: project_template_as_manifest()?,
-                    progname: name.to_string(),
+                // Flags only defined for some subcommands
+                let user_templates = config.user_templates.clone();
+                let user_templates = user_templates.as_deref();
+                match config.command {
+                    ExecutionCommand::Upgrade => {
+                        config.force = sub_match.is_present("force");
+                        config.dry_run = sub_match.is_present("dry-run");
+                    }
+                    ExecutionCommand::TemplateList => {}
+                    ExecutionCommand::TemplateSave => {
+                        config.dry_run = sub_match.is_present("dry-run");
+                    }
+                    ExecutionCommand::AddInstruction => {
+                        config.dry_run = sub_match.is_present("dry-run");
+                        config.fields = parse_values(sub_match, "arg", parse_arg)?;
+                        config.accounts = parse_values(sub_match, "account", parse_account)?;
+                    }
+                    ExecutionCommand::AddError => {
+                        config.dry_run = sub_match.is_present("dry-run");
+                        config.message = sub_match.value_of("message").map(str::to_string);
+                        config.before = sub_match.value_of("before").map(str::to_string);
+                    }
+                    ExecutionCommand::AddState => {
+                        config.dry_run = sub_match.is_present("dry-run");
+                        config.fields = parse_values(sub_match, "field", parse_field)?;
+                    }
+                    ExecutionCommand::Generate => {
+                        config.force = sub_match.is_present("force");
+                        config.dry_run = sub_match.is_present("dry-run");
+                        config.spec = sub_match.value_of("spec").map(PathBuf::from);
+                    }
+                    ExecutionCommand::TemplateShow => {
+                        config.template = Some(resolve_template(
+                            sub_match.value_of("template").unwrap(),
+                            Path::new(""),
+                            user_templates,
+                        )?);
+                    }
+                    _ => {
+                        config.dry_run = sub_match.is_present("dry-run");
+                        if let ExecutionCommand::Create | ExecutionCommand::Init = config.command {
+                            config.workspace_deps = sub_match.is_present("workspace-deps");
+                        }
+                        let config_file = config_dir().map(|d| d.join(CONFIG_FILE));
+                        let config_file = config_file.as_deref();
+                        config.template = match sub_match.value_of("template") {
+                            Some(template) => {
+                                Some(resolve_template(template, Path::new(""), user_templates)?)
+                            }
+                            None => config_file
+                                .map_or(Ok(None), |f| template_from_config(f, user_templates))?,
+                        };
+                        config.license = match sub_match.value_of("license") {
+                            Some(license) => Some(license.to_string()),
+                            None => config_file.map_or(Ok(None), |f| config_value(f, "license"))?,
+                        };
+                        config.author = match config_file {
+                            Some(f) => config_value(f, "author")?,
+                            None => None,
+                        }
+                        .or_else(default_author);
+                        config.vars = sub_match
+                            .values_of("var")
+                            .map(|vars| vars.filter_map(|v| parse_var(v).ok()).collect())
+                            .unwrap_or_default();
+                        config.interactive = std::io::stdin().is_terminal();
+                        config.allow_hooks = sub_match.is_present("allow-hooks");
+                        validate_program_name(&config.progname)?;
+                        if let Some(id) = sub_match.value_of("program-id") {
+                            validate_program_id(id)?;
+                            config.program_id = Some(id.to_string());
+                        }
+                    }
                 }
+                config
             }
         };
         Ok(config)
     }
 }
 
+/// Splits a `KEY=VALUE` template variable
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    match var.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got {}", var)),
+    }
+}
+
+/// Parses every value of a repeated option, failing on the first invalid one
+fn parse_values<T>(
+    matches: &ArgMatches,
+    name: &str,
+    parse: fn(&str) -> Result<T, String>,
+) -> CargoResult<Vec<T>> {
+    matches
+        .values_of(name)
+        .into_iter()
+        .flatten()
+        .map(|value| parse(value).map_err(|e| ProgramError::InvalidArgument(name.to_string(), e)))
+        .collect::<Result<Vec<_>, _>>()
+}
+
+/// Splits the output folder of `template render` into the folder it is
+/// created in and its name, rendered like a program folder of the former
+fn output_folder(out: &Path) -> CargoResult<(PathBuf, String)> {
+    let dir = out
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| ProgramError::InvalidOutput(out.display().to_string()))?;
+    let parent = out
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    Ok((parent.to_path_buf(), dir.to_string()))
+}
+
+/// Checks a folder is given relative to the workspace and stays within it
+fn relative_dir(dir: &str) -> Result<(), String> {
+    match Path::new(dir)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        true => Ok(()),
+        false => Err(format!("{} must be a folder within the workspace", dir)),
+    }
+}
+
+/// Reads a string setting of a configuration file, if present
+fn config_value(config_file: &Path, key: &str) -> CargoResult<Option<String>> {
+    if !config_file.exists() {
+        return Ok(None);
+    }
+    let doc = std::fs::read_to_string(config_file)?.parse::<DocumentMut>()?;
+    match doc.get(key) {
+        None => Ok(None),
+        Some(Item::Value(toml_edit::Value::String(v))) => Ok(Some(v.value().to_string())),
+        Some(_) => Err(ProgramError::ManifestFormatError(format!(
+            "{} in {}",
+            key,
+            config_file.display()
+        ))),
+    }
+}
+
+/// Reads the `template` setting of a configuration file, a built-in
+/// template, a folder or a user template. A relative folder is taken from
+/// the configuration file's folder
+fn template_from_config(
+    config_file: &Path,
+    user_templates: Option<&Path>,
+) -> CargoResult<Option<TemplateSource>> {
+    let base = config_file.parent().unwrap_or(Path::new("."));
+    config_value(config_file, "template")?
+        .map(|t| resolve_template(&t, base, user_templates))
+        .transpose()
+}
+
+/// Author in the way `cargo new` finds one, from `CARGO_NAME` and
+/// `CARGO_EMAIL` or else the git user
+fn default_author() -> Option<String> {
+    let git = |key: &str| {
+        std::process::Command::new("git")
+            .args(["config", "--get", key])
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let name = env::var("CARGO_NAME").ok().or_else(|| git("user.name"))?;
+    match env::var("CARGO_EMAIL").ok().or_else(|| git("user.email")) {
+        Some(email) => Some(format!("{} <{}>", name, email)),
+        None => Some(name),
+    }
+}
+
+/// Resolves the workspace root directory from a path to its Cargo.toml
+fn manifest_root(manifest_path: &std::path::Path) -> Result<PathBuf, ProgramError> {
+    match manifest_path.file_name() {
+        Some(f) if f == "Cargo.toml" => match manifest_path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => Ok(p.to_path_buf()),
+            _ => Ok(PathBuf::from(".")),
+        },
+        _ => Err(ProgramError::InvalidManifestPath(
+            manifest_path.display().to_string(),
+        )),
+    }
+}
+
 /// Builds command line argument parser using rs-clap/clap
 fn build_command_line_parser() -> Command<'static> {
     command!()
@@ -100,11 +464,76 @@ fn build_command_line_parser() -> Command<'static> {
                         .required(true)
                         .takes_value(true)
                         .help("Project's Program name (required)"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Directory to create the project in"),
+                )
+                .arg(
+                    Arg::new("workspace-deps")
+                        .long("workspace-deps")
+                        .help("Declare shared dependency versions in [workspace.dependencies]"),
+                )
+                .arg(
+                    Arg::new("solana-version")
+                        .long("solana-version")
+                        .takes_value(true)
+                        .help("Solana version for dependencies, overrides SOLANA_VERSION and the install"),
+                )
+                .arg(
+                    Arg::new("license")
+                        .long("license")
+                        .takes_value(true)
+                        .help("License of the program package, e.g. Apache-2.0"),
+                )
+                .arg(
+                    Arg::new("program-id")
+                        .long("program-id")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .help("Program id to declare in the program sources"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .takes_value(true)
+                        .value_name("TEMPLATE")
+                        .help("Built-in template name or template folder, see the README"),
+                )
+                .arg(
+                    Arg::new("var")
+                        .long("var")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .value_name("KEY=VALUE")
+                        .validator(parse_var)
+                        .help("Value of a variable declared by the template, may be repeated"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value("program")
+                        .validator(relative_dir)
+                        .help("Program folder relative to the workspace, e.g. programs/<name>"),
+                )
+                .arg(
+                    Arg::new("allow-hooks")
+                        .long("allow-hooks")
+                        .help("Run the commands the template lists as hooks without asking"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the planned files and manifest changes without writing"),
                 ),
         )
         .subcommand(
             Command::new("init")
-                .about("Add Solana program to current folder")
+                .about("Add Solana program to existing workspace")
                 .arg(
                     Arg::new("progname")
                         .long("program-name")
@@ -112,6 +541,357 @@ fn build_command_line_parser() -> Command<'static> {
                         .required(true)
                         .takes_value(true)
                         .help("Program name"),
+                )
+                .arg(
+                    Arg::new("manifest-path")
+                        .long("manifest-path")
+                        .takes_value(true)
+                        .default_value("./Cargo.toml")
+                        .help("Path to the workspace Cargo.toml"),
+                )
+                .arg(
+                    Arg::new("workspace-deps")
+                        .long("workspace-deps")
+                        .help("Declare shared dependency versions in [workspace.dependencies]"),
+                )
+                .arg(
+                    Arg::new("solana-version")
+                        .long("solana-version")
+                        .takes_value(true)
+                        .help("Solana version for dependencies, overrides SOLANA_VERSION and the install"),
+                )
+                .arg(
+                    Arg::new("move-package")
+                        .long("move-package")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .validator(relative_dir)
+                        .help(
+                            "Move the root package into DIR and make the root a virtual workspace",
+                        ),
+                )
+                .arg(
+                    Arg::new("license")
+                        .long("license")
+                        .takes_value(true)
+                        .help("License of the program package, e.g. Apache-2.0"),
+                )
+                .arg(
+                    Arg::new("program-id")
+                        .long("program-id")
+                        .takes_value(true)
+                        .value_name("PUBKEY")
+                        .help("Program id to declare in the program sources"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .takes_value(true)
+                        .value_name("TEMPLATE")
+                        .help("Built-in template name or template folder, see the README"),
+                )
+                .arg(
+                    Arg::new("var")
+                        .long("var")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .value_name("KEY=VALUE")
+                        .validator(parse_var)
+                        .help("Value of a variable declared by the template, may be repeated"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value("program")
+                        .validator(relative_dir)
+                        .help("Program folder relative to the workspace, e.g. programs/<name>"),
+                )
+                .arg(
+                    Arg::new("allow-hooks")
+                        .long("allow-hooks")
+                        .help("Run the commands the template lists as hooks without asking"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the planned files and manifest changes without writing"),
+                ),
+        )
+        .subcommand(
+            Command::new("upgrade")
+                .about("Upgrade the Solana crates of the workspace programs")
+                .arg(
+                    Arg::new("manifest-path")
+                        .long("manifest-path")
+                        .takes_value(true)
+                        .default_value("./Cargo.toml")
+                        .help("Path to the workspace Cargo.toml"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("VERSION")
+                        .help("Solana version to upgrade to, defaults to SOLANA_VERSION or the install"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Upgrade even across a major version or to an older version"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the planned manifest changes without writing"),
+                ),
+        )
+        .subcommand(
+            Command::new("generate")
+                .about("Generate the program sources from a TOML or YAML spec")
+                .arg(
+                    Arg::new("spec")
+                        .long("spec")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .help("Spec of the program accounts, instructions and errors"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Keep the handler bodies whose signatures the spec changes and remove the handwritten handlers it drops"),
+                )
+                .arg(
+                    Arg::new("manifest-path")
+                        .long("manifest-path")
+                        .takes_value(true)
+                        .default_value("./Cargo.toml")
+                        .help("Path to the program Cargo.toml"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .help("Print the source changes without writing"),
+                ),
+        )
+        .subcommand(
+            Command::new("template")
+                .about("Inspect and render program templates")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(Command::new("list").about("List built-in and user templates"))
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the files, variables and hooks of a template")
+                        .arg(
+                            Arg::new("template")
+                                .required(true)
+                                .value_name("TEMPLATE")
+                                .help("Built-in or user template name, or template folder"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("render")
+                        .about("Render a template into a new folder, outside of any workspace")
+                        .arg(
+                            Arg::new("template")
+                                .required(true)
+                                .value_name("TEMPLATE")
+                                .help("Built-in or user template name, or template folder"),
+                        )
+                        .arg(
+                            Arg::new("out")
+                                .long("out")
+                                .required(true)
+                                .takes_value(true)
+                                .value_name("DIR")
+                                .help("Folder to render into, it must not exist (required)"),
+                        )
+                        .arg(
+                            Arg::new("progname")
+                                .long("program-name")
+                                .short('n')
+                                .takes_value(true)
+                                .help("Program name, defaults to the output folder name"),
+                        )
+                        .arg(
+                            Arg::new("solana-version")
+                                .long("solana-version")
+                                .takes_value(true)
+                                .help("Solana version for dependencies, overrides SOLANA_VERSION and the install"),
+                        )
+                        .arg(
+                            Arg::new("license")
+                                .long("license")
+                                .takes_value(true)
+                                .help("License of the program package, e.g. Apache-2.0"),
+                        )
+                        .arg(
+                            Arg::new("program-id")
+                                .long("program-id")
+                                .takes_value(true)
+                                .value_name("PUBKEY")
+                                .help("Program id to declare in the program sources"),
+                        )
+                        .arg(
+                            Arg::new("var")
+                                .long("var")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .value_name("KEY=VALUE")
+                                .validator(parse_var)
+                                .help("Value of a variable declared by the template, may be repeated"),
+                        )
+                        .arg(
+                            Arg::new("allow-hooks")
+                                .long("allow-hooks")
+                                .help("Run the commands the template lists as hooks without asking"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the planned files without writing"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("save")
+                        .about("Save an existing program as a user template")
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .required(true)
+                                .takes_value(true)
+                                .value_name("DIR")
+                                .help("Program folder holding its Cargo.toml (required)"),
+                        )
+                        .arg(
+                            Arg::new("name")
+                                .long("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Name of the user template to write (required)"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the template files without writing"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Add code to an existing program")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("instruction")
+                        .about("Add an instruction with its handler and a test")
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .value_name("NAME")
+                                .validator(check_type_name)
+                                .help("Instruction variant name, e.g. Transfer"),
+                        )
+                        .arg(
+                            Arg::new("arg")
+                                .long("arg")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .value_name("NAME:TYPE")
+                                .validator(parse_arg)
+                                .help("Instruction argument, may be repeated"),
+                        )
+                        .arg(
+                            Arg::new("account")
+                                .long("account")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .value_name("NAME[:mut][:signer]")
+                                .validator(parse_account)
+                                .help("Account the instruction takes, in order, may be repeated"),
+                        )
+                        .arg(
+                            Arg::new("manifest-path")
+                                .long("manifest-path")
+                                .takes_value(true)
+                                .default_value("./Cargo.toml")
+                                .help("Path to the program Cargo.toml"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the source changes without writing"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("error")
+                        .about("Add a custom error variant and its print arm")
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .value_name("NAME")
+                                .validator(check_type_name)
+                                .help("Error variant name, e.g. NotVaultOwner"),
+                        )
+                        .arg(
+                            Arg::new("message")
+                                .long("message")
+                                .takes_value(true)
+                                .help("Error message, defaults to the name in words"),
+                        )
+                        .arg(
+                            Arg::new("before")
+                                .long("before")
+                                .takes_value(true)
+                                .value_name("VARIANT")
+                                .help("Add before this variant, which renumbers those following"),
+                        )
+                        .arg(
+                            Arg::new("manifest-path")
+                                .long("manifest-path")
+                                .takes_value(true)
+                                .default_value("./Cargo.toml")
+                                .help("Path to the program Cargo.toml"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the source changes without writing"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("state")
+                        .about("Add an account state struct with its Pack impl")
+                        .arg(
+                            Arg::new("name")
+                                .required(true)
+                                .value_name("NAME")
+                                .validator(check_type_name)
+                                .help("State name, e.g. Vault for VaultState"),
+                        )
+                        .arg(
+                            Arg::new("field")
+                                .long("field")
+                                .takes_value(true)
+                                .multiple_occurrences(true)
+                                .value_name("NAME:TYPE[:MAX]")
+                                .validator(parse_field)
+                                .help("State field, MAX bounds a String or Vec, may be repeated"),
+                        )
+                        .arg(
+                            Arg::new("manifest-path")
+                                .long("manifest-path")
+                                .takes_value(true)
+                                .default_value("./Cargo.toml")
+                                .help("Path to the program Cargo.toml"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .help("Print the source changes without writing"),
+                        ),
                 ),
         )
 }
@@ -165,6 +945,426 @@ mod tests {
         }
     }
     #[test]
+    fn cmdline_create_path_pass() {
+        let args = vec![
+            "cargo-solana",
+            "create",
+            "-n",
+            "foo",
+            "--path",
+            "./onchain/",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("path"), Some("./onchain/"));
+    }
+    #[test]
+    fn cmdline_init_dry_run_pass() {
+        let args = vec!["cargo-solana", "init", "-n", "foo", "--dry-run"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert!(s.is_present("dry-run"));
+    }
+    #[test]
+    fn cmdline_init_dir_pass() {
+        let args = vec!["cargo-solana", "init", "-n", "foo", "--dir", "programs/foo"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("dir"), Some("programs/foo"));
+    }
+    #[test]
+    fn cmdline_init_move_package_pass() {
+        let args = vec!["cargo-solana", "init", "-n", "foo", "--move-package", "app"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("move-package"), Some("app"));
+    }
+    #[test]
+    fn cmdline_init_dir_fail() {
+        for (arg, dir) in [
+            ("--dir", "/tmp/foo"),
+            ("--dir", "../foo"),
+            ("--dir", "programs/../../foo"),
+            ("--move-package", ".."),
+        ] {
+            let args = vec!["cargo-solana", "init", "-n", "foo", arg, dir];
+            let mut cmdline = build_command_line_parser();
+            let matches = cmdline.try_get_matches_from_mut(args);
+            match matches {
+                Err(e) => assert_eq!(e.kind(), clap::ErrorKind::ValueValidation),
+                _ => panic!("{} {} accepted", arg, dir),
+            }
+        }
+    }
+    #[test]
+    fn cmdline_create_solana_version_pass() {
+        let args = vec![
+            "cargo-solana",
+            "create",
+            "-n",
+            "foo",
+            "--solana-version",
+            "1.14.17",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("solana-version"), Some("1.14.17"));
+    }
+    #[test]
+    fn cmdline_init_workspace_deps_pass() {
+        let args = vec!["cargo-solana", "init", "-n", "foo", "--workspace-deps"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert!(s.is_present("workspace-deps"));
+    }
+    #[test]
+    fn cmdline_upgrade_pass() {
+        let args = vec!["cargo-solana", "upgrade", "--to", "1.16.0", "--force"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (name, s) = matches.subcommand().unwrap();
+        assert_eq!(name, "upgrade");
+        assert_eq!(s.value_of("to"), Some("1.16.0"));
+        assert_eq!(s.value_of("manifest-path"), Some("./Cargo.toml"));
+        assert!(s.is_present("force"));
+    }
+    #[test]
+    fn cmdline_create_template_pass() {
+        let args = vec!["cargo-solana", "create", "-n", "foo", "--template", "house"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("template"), Some("house"));
+    }
+    #[test]
+    fn cmdline_init_template_vars_pass() {
+        let args = vec![
+            "cargo-solana",
+            "init",
+            "-n",
+            "foo",
+            "--license",
+            "MIT",
+            "--program-id",
+            "11111111111111111111111111111111",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(s.value_of("license"), Some("MIT"));
+        assert_eq!(
+            s.value_of("program-id"),
+            Some("11111111111111111111111111111111")
+        );
+    }
+    #[test]
+    fn cmdline_template_var_pass() {
+        let args = vec![
+            "cargo-solana",
+            "create",
+            "-n",
+            "foo",
+            "--var",
+            "account=Vault",
+            "--var",
+            "seeds=a=b",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, s) = matches.subcommand().unwrap();
+        assert_eq!(
+            s.values_of("var").unwrap().collect::<Vec<_>>(),
+            vec!["account=Vault", "seeds=a=b"]
+        );
+        assert_eq!(
+            parse_var("seeds=a=b"),
+            Ok(("seeds".to_string(), "a=b".to_string()))
+        );
+        let args = vec!["cargo-solana", "create", "-n", "foo", "--var", "account"];
+        let mut cmdline = build_command_line_parser();
+        assert!(cmdline.try_get_matches_from_mut(args).is_err());
+    }
+    #[test]
+    fn cmdline_template_commands_pass() {
+        let args = vec!["cargo-solana", "template", "list"];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (name, t) = matches.subcommand().unwrap();
+        assert_eq!(name, "template");
+        assert_eq!(t.subcommand_name(), Some("list"));
+        let args = vec![
+            "cargo-solana",
+            "template",
+            "render",
+            "escrow",
+            "--out",
+            "/tmp/escrow",
+            "--var",
+            "account=Vault",
+            "--dry-run",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, t) = matches.subcommand().unwrap();
+        let (name, s) = t.subcommand().unwrap();
+        assert_eq!(name, "render");
+        assert_eq!(s.value_of("template"), Some("escrow"));
+        assert_eq!(s.value_of("out"), Some("/tmp/escrow"));
+        assert!(s.is_present("dry-run"));
+        let args = vec![
+            "cargo-solana",
+            "template",
+            "save",
+            "--from",
+            "program/",
+            "--name",
+            "our-base",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, t) = matches.subcommand().unwrap();
+        let (name, s) = t.subcommand().unwrap();
+        assert_eq!(name, "save");
+        assert_eq!(s.value_of("from"), Some("program/"));
+        assert_eq!(s.value_of("name"), Some("our-base"));
+    }
+    #[test]
+    fn output_folder_pass() {
+        assert_eq!(
+            output_folder(Path::new("/tmp/escrow")).unwrap(),
+            (PathBuf::from("/tmp"), "escrow".to_string())
+        );
+        assert_eq!(
+            output_folder(Path::new("escrow/")).unwrap(),
+            (PathBuf::from("."), "escrow".to_string())
+        );
+        for out in [".", "..", "/", "escrow/.."] {
+            assert!(matches!(
+                output_folder(Path::new(out)),
+                Err(ProgramError::InvalidOutput(o)) if o == out
+            ));
+        }
+    }
+    #[test]
+    fn cmdline_template_commands_fail() {
+        for args in [
+            vec!["cargo-solana", "template", "show"],
+            vec!["cargo-solana", "template", "render", "escrow"],
+            vec!["cargo-solana", "template", "save", "--from", "program"],
+        ] {
+            let mut cmdline = build_command_line_parser();
+            match cmdline.try_get_matches_from_mut(args) {
+                Err(e) => assert_eq!(e.kind(), clap::ErrorKind::MissingRequiredArgument),
+                _ => panic!(),
+            }
+        }
+    }
+    #[test]
+    fn cmdline_add_instruction_pass() {
+        let args = vec![
+            "cargo-solana",
+            "add",
+            "instruction",
+            "Transfer",
+            "--arg",
+            "amount:u64",
+            "--account",
+            "source:mut:signer",
+            "--account",
+            "destination:mut",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (name, a) = matches.subcommand().unwrap();
+        assert_eq!(name, "add");
+        let (name, s) = a.subcommand().unwrap();
+        assert_eq!(name, "instruction");
+        assert_eq!(s.value_of("name"), Some("Transfer"));
+        assert_eq!(
+            s.values_of("arg").unwrap().collect::<Vec<_>>(),
+            vec!["amount:u64"]
+        );
+        assert_eq!(
+            s.values_of("account").unwrap().collect::<Vec<_>>(),
+            vec!["source:mut:signer", "destination:mut"]
+        );
+        assert_eq!(s.value_of("manifest-path"), Some("./Cargo.toml"));
+        let accounts = parse_values(s, "account", parse_account).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert!(accounts[0].signer && !accounts[1].signer);
+        assert_eq!(parse_values(s, "arg", parse_arg).unwrap()[0].ty, "u64");
+        // Values that skipped the validator fail instead of being dropped
+        let matches = Command::new("add")
+            .arg(Arg::new("arg").long("arg").takes_value(true))
+            .try_get_matches_from(vec!["add", "--arg", "amount"])
+            .unwrap();
+        assert!(matches!(
+            parse_values(&matches, "arg", parse_arg),
+            Err(ProgramError::InvalidArgument(name, _)) if name == "arg"
+        ));
+    }
+    #[test]
+    fn cmdline_add_instruction_fail() {
+        for args in [
+            vec!["cargo-solana", "add", "instruction"],
+            vec!["cargo-solana", "add", "instruction", "transfer"],
+            vec![
+                "cargo-solana",
+                "add",
+                "instruction",
+                "Transfer",
+                "--arg",
+                "amount",
+            ],
+            vec![
+                "cargo-solana",
+                "add",
+                "instruction",
+                "Transfer",
+                "--arg",
+                "amount:u 64",
+            ],
+            vec![
+                "cargo-solana",
+                "add",
+                "instruction",
+                "Transfer",
+                "--account",
+                "source:owner",
+            ],
+        ] {
+            let mut cmdline = build_command_line_parser();
+            assert!(cmdline.try_get_matches_from_mut(args).is_err());
+        }
+    }
+    #[test]
+    fn cmdline_add_error_pass() {
+        let args = vec![
+            "cargo-solana",
+            "add",
+            "error",
+            "NotVaultOwner",
+            "--message",
+            "Signer does not own the vault",
+            "--before",
+            "InsufficientFunds",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, a) = matches.subcommand().unwrap();
+        let (name, s) = a.subcommand().unwrap();
+        assert_eq!(name, "error");
+        assert_eq!(s.value_of("name"), Some("NotVaultOwner"));
+        assert_eq!(s.value_of("message"), Some("Signer does not own the vault"));
+        assert_eq!(s.value_of("before"), Some("InsufficientFunds"));
+        let args = vec!["cargo-solana", "add", "error", "not_owner"];
+        let mut cmdline = build_command_line_parser();
+        assert!(cmdline.try_get_matches_from_mut(args).is_err());
+    }
+    #[test]
+    fn cmdline_add_state_pass() {
+        let args = vec![
+            "cargo-solana",
+            "add",
+            "state",
+            "Vault",
+            "--field",
+            "owner:Pubkey",
+            "--field",
+            "tags:[u8;16]",
+            "--field",
+            "label:String:32",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (_, a) = matches.subcommand().unwrap();
+        let (name, s) = a.subcommand().unwrap();
+        assert_eq!(name, "state");
+        assert_eq!(s.value_of("name"), Some("Vault"));
+        assert_eq!(
+            s.values_of("field").unwrap().collect::<Vec<_>>(),
+            vec!["owner:Pubkey", "tags:[u8;16]", "label:String:32"]
+        );
+        let fields = parse_values(s, "field", parse_field).unwrap();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[2].max, Some(32));
+        let args = vec!["cargo-solana", "add", "state", "Vault", "--field", "owner"];
+        let mut cmdline = build_command_line_parser();
+        assert!(cmdline.try_get_matches_from_mut(args).is_err());
+    }
+    #[test]
+    fn cmdline_generate_pass() {
+        let args = vec![
+            "cargo-solana",
+            "generate",
+            "--spec",
+            "vault.toml",
+            "--force",
+            "--dry-run",
+        ];
+        let mut cmdline = build_command_line_parser();
+        let matches = cmdline.try_get_matches_from_mut(args).unwrap();
+        let (name, s) = matches.subcommand().unwrap();
+        assert_eq!(name, "generate");
+        assert_eq!(s.value_of("spec"), Some("vault.toml"));
+        assert_eq!(s.value_of("manifest-path"), Some("./Cargo.toml"));
+        assert!(s.is_present("force"));
+        assert!(s.is_present("dry-run"));
+        let args = vec!["cargo-solana", "generate"];
+        let mut cmdline = build_command_line_parser();
+        assert!(cmdline.try_get_matches_from_mut(args).is_err());
+    }
+    #[test]
+    fn config_value_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.toml");
+        std::fs::write(&config_file, "author = \"Jo\"\n").unwrap();
+        assert_eq!(
+            config_value(&config_file, "author").unwrap(),
+            Some("Jo".to_string())
+        );
+        assert_eq!(config_value(&config_file, "license").unwrap(), None);
+    }
+    #[test]
+    fn template_from_config_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.toml");
+        assert_eq!(template_from_config(&config_file, None).unwrap(), None);
+        std::fs::write(&config_file, "template = \"house\"\n").unwrap();
+        assert_eq!(
+            template_from_config(&config_file, None).unwrap(),
+            Some(TemplateSource::Folder(dir.path().join("house")))
+        );
+        std::fs::write(&config_file, "template = \"escrow\"\n").unwrap();
+        assert!(matches!(
+            template_from_config(&config_file, None).unwrap(),
+            Some(TemplateSource::Embedded(_))
+        ));
+        std::fs::write(&config_file, "template = 1\n").unwrap();
+        assert!(template_from_config(&config_file, None).is_err());
+    }
+    #[test]
+    fn manifest_root_pass() {
+        assert_eq!(
+            manifest_root(&PathBuf::from("./onchain/Cargo.toml")).unwrap(),
+            PathBuf::from("./onchain")
+        );
+        assert_eq!(
+            manifest_root(&PathBuf::from("Cargo.toml")).unwrap(),
+            PathBuf::from(".")
+        );
+    }
+    #[test]
+    fn manifest_root_fail() {
+        assert!(manifest_root(&PathBuf::from("./onchain/")).is_err());
+    }
+    #[test]
     fn cargo_read_pass() {
         let man = Manifest::from_path("./Cargo.toml");
         assert!(man.is_ok());
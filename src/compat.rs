@@ -0,0 +1,130 @@
+//! Dependency versions matched to the Solana release
+//!
+//! Program dependencies such as borsh have to agree with the versions the
+//! Solana crates build against. The embedded `resources/compat.toml` lists
+//! known good versions for each Solana release line.
+
+use crate::error::{CargoResult, ProgramError};
+use semver::Version;
+use std::str;
+use toml_edit::{DocumentMut, Item};
+
+/// Table of release lines in the compatibility resource
+const RELEASES: &str = "releases";
+/// Stands for the resolved Solana version in the compatibility resource
+const SOLANA_PLACEHOLDER: &str = "solana";
+
+/// Loads the compatibility table from resources
+fn compat_table() -> CargoResult<DocumentMut> {
+    Ok(str::from_utf8(include_bytes!("../resources/compat.toml"))
+        .unwrap()
+        .parse::<DocumentMut>()?)
+}
+
+/// Parses a release line key such as `1.14` into major and minor
+fn release_line(key: &str) -> Option<(u64, u64)> {
+    let (major, minor) = key.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Returns the `(crate, version)` pairs known to build with `solana`.
+/// A release line missing from the table uses the newest older line, and
+/// a version older than every line gets no pairs. Both are warned about
+pub fn compatible_versions(solana: &str) -> CargoResult<Vec<(String, String)>> {
+    let version =
+        Version::parse(solana).map_err(|_| ProgramError::InvalidSolanaVersion(solana.into()))?;
+    let table = compat_table()?;
+    let releases = table
+        .get(RELEASES)
+        .and_then(Item::as_table)
+        .ok_or_else(|| ProgramError::ManifestFormatError(RELEASES.to_string()))?;
+    let wanted = (version.major, version.minor);
+    let line = releases
+        .iter()
+        .filter_map(|(key, item)| release_line(key).map(|l| (l, key, item)))
+        .filter(|(l, _, _)| *l <= wanted)
+        .max_by_key(|(l, _, _)| *l);
+    let (key, entry) = match line {
+        Some((l, key, entry)) => {
+            if l.0 != wanted.0 {
+                println!(
+                    "Warning: Solana {} is newer than the compatibility table, using dependency \
+                     versions for {} which the templates are written for, they may not build",
+                    solana, key
+                );
+            } else if l != wanted {
+                println!(
+                    "Warning: Solana {} is not in the compatibility table, \
+                     using dependency versions for {}",
+                    solana, key
+                );
+            }
+            (key, entry)
+        }
+        None => {
+            println!(
+                "Warning: Solana {} is older than the compatibility table, \
+                 dependency versions may not build",
+                solana
+            );
+            return Ok(vec![]);
+        }
+    };
+    let entry = entry
+        .as_table()
+        .ok_or_else(|| ProgramError::ManifestFormatError(format!("{}.{}", RELEASES, key)))?;
+    Ok(entry
+        .iter()
+        .filter_map(|(krate, v)| {
+            v.as_str().map(|v| match v {
+                SOLANA_PLACEHOLDER => (krate.to_string(), solana.to_string()),
+                _ => (krate.to_string(), v.to_string()),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compat_table_pass() {
+        let table = compat_table().unwrap();
+        for (key, entry) in table[RELEASES].as_table().unwrap() {
+            assert!(release_line(key).is_some(), "bad release line {}", key);
+            for krate in [
+                "borsh",
+                "num-derive",
+                "thiserror",
+                "solana-program-test",
+                "spl-token",
+            ] {
+                assert!(entry.get(krate).is_some(), "{} missing {}", key, krate);
+            }
+        }
+    }
+
+    #[test]
+    fn compatible_versions_pass() {
+        let versions = compatible_versions("1.16.3").unwrap();
+        assert!(versions.contains(&("borsh".to_string(), "0.10.3".to_string())));
+        assert!(versions.contains(&("solana-program-test".to_string(), "1.16.3".to_string())));
+        // 1.12 falls back to the 1.11 line
+        let versions = compatible_versions("1.12.0").unwrap();
+        assert!(versions.contains(&("borsh".to_string(), "0.9.3".to_string())));
+        // Newer than the table uses the newest line
+        let versions = compatible_versions("2.0.3").unwrap();
+        assert!(versions.contains(&("borsh".to_string(), "0.10.3".to_string())));
+        assert!(versions.contains(&("solana-program-test".to_string(), "2.0.3".to_string())));
+    }
+
+    #[test]
+    fn compatible_versions_unknown_pass() {
+        assert!(compatible_versions("1.8.16").unwrap().is_empty());
+        assert!(matches!(
+            compatible_versions("1.8"),
+            Err(ProgramError::InvalidSolanaVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,710 @@
+//! Template descriptors
+//!
+//! A template ships a `template.toml` listing the files to generate and
+//! where they land, the variables they take and the hooks to run once the
+//! files are written. A template folder without one has every file
+//! rendered into the program `src` folder.
+
+use crate::{
+    error::{CargoResult, ProgramError},
+    template::{is_true, render, render_file, TemplateVars},
+};
+use minijinja::Value;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, Write},
+    path::{Component, Path, PathBuf},
+    str,
+};
+use toml_edit::{DocumentMut, Item, Table};
+
+/// Name of the descriptor in a template
+pub const DESCRIPTOR_FILE: &str = "template.toml";
+/// Destination of the program manifest
+pub const MANIFEST_DEST: &str = "Cargo.toml";
+/// Regex Substitution variable kept for templates predating variables
+const PROG_IDENTIFIER: &str = r"PROGNAME";
+/// Keys allowed in a `[variables.<name>]` table
+const VARIABLE_KEYS: [&str; 5] = ["description", "default", "prompt", "pattern", "choices"];
+/// Keys allowed in a `[[files]]` entry
+const FILE_KEYS: [&str; 5] = ["source", "dest", "when", "verbatim", "binary"];
+
+/// Files compiled into cargo-solana as (name, content)
+pub type EmbeddedFiles = &'static [(&'static str, &'static [u8])];
+
+/// Where the files of a template are read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateSource {
+    /// Files compiled into cargo-solana
+    Embedded(EmbeddedFiles),
+    /// A template folder on disk
+    Folder(PathBuf),
+    /// Files read from a git revision or a tarball
+    Archive(Archive),
+}
+
+/// Template files read from a git revision or a tarball, pinned to the
+/// revision they were read at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    /// The template as given, such as `../starters.git#v3`
+    pub source: String,
+    /// Commit id of a git revision, sha256 of a tarball
+    pub revision: String,
+    /// Files as (path relative to the template, content)
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl TemplateSource {
+    /// Reads a template file, `None` if the template has no such file
+    pub fn read(&self, name: &str) -> CargoResult<Option<Vec<u8>>> {
+        match self {
+            TemplateSource::Embedded(files) => Ok(files
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, content)| content.to_vec())),
+            TemplateSource::Folder(dir) => {
+                let path = dir.join(name);
+                match path.is_file() {
+                    true => Ok(Some(fs::read(path)?)),
+                    false => Ok(None),
+                }
+            }
+            TemplateSource::Archive(archive) => Ok(archive
+                .files
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, content)| content.clone())),
+        }
+    }
+
+    /// Lists all template files by their path relative to the template
+    pub fn files(&self) -> CargoResult<Vec<String>> {
+        let mut files = match self {
+            TemplateSource::Embedded(files) => files.iter().map(|(n, _)| n.to_string()).collect(),
+            TemplateSource::Folder(dir) => {
+                if !dir.is_dir() {
+                    return Err(ProgramError::TemplateNotFound(dir.display().to_string()));
+                }
+                let mut files = vec![];
+                let mut pending = vec![dir.to_path_buf()];
+                while let Some(folder) = pending.pop() {
+                    for entry in fs::read_dir(folder)? {
+                        let path = entry?.path();
+                        if path.is_dir() {
+                            pending.push(path);
+                            continue;
+                        }
+                        let relative = path.strip_prefix(dir).unwrap_or(&path);
+                        files.push(
+                            relative
+                                .components()
+                                .map(|c| c.as_os_str().to_string_lossy())
+                                .collect::<Vec<_>>()
+                                .join("/"),
+                        );
+                    }
+                }
+                files
+            }
+            TemplateSource::Archive(archive) => {
+                archive.files.iter().map(|(n, _)| n.clone()).collect()
+            }
+        };
+        files.sort();
+        Ok(files)
+    }
+
+    /// Name used for the template in messages
+    pub fn origin(&self) -> String {
+        match self {
+            TemplateSource::Embedded(_) => "built-in template".to_string(),
+            TemplateSource::Folder(dir) => dir.display().to_string(),
+            TemplateSource::Archive(archive) => archive.source.clone(),
+        }
+    }
+}
+
+/// A variable declared by the descriptor
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub description: Option<String>,
+    pub default: Option<Value>,
+    /// Question asked for the value when run interactively
+    pub prompt: Option<String>,
+    /// Regular expression the whole value must match
+    pub pattern: Option<Regex>,
+    /// Allowed values
+    pub choices: Vec<String>,
+}
+
+impl Variable {
+    /// Checks a value given on the command line or at a prompt
+    fn validate(&self, value: &str) -> CargoResult<()> {
+        let invalid =
+            |reason: String| ProgramError::InvalidVariable(self.name.clone(), value.into(), reason);
+        if !self.choices.is_empty() && !self.choices.iter().any(|c| c == value) {
+            return Err(invalid(format!(
+                "expected one of {}",
+                self.choices.join(", ")
+            )));
+        }
+        match &self.pattern {
+            Some(re) if !re.is_match(value) => {
+                Err(invalid(format!("expected to match {}", re.as_str())))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A file the descriptor generates
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    /// Path of the file in the template
+    pub source: String,
+    /// Path below the program folder, itself rendered as a template
+    pub dest: String,
+    /// Condition expression, the file is only generated when true
+    pub when: Option<String>,
+    /// Copied as is without rendering
+    pub verbatim: bool,
+    /// Copied as bytes, implies verbatim
+    pub binary: bool,
+}
+
+/// Content of a generated file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Contents {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// A parsed `template.toml`
+#[derive(Debug, Clone, Default)]
+pub struct Descriptor {
+    pub name: String,
+    pub description: String,
+    pub variables: Vec<Variable>,
+    pub files: Vec<FileEntry>,
+    /// Commands run in the program folder after generation, as arguments
+    pub hooks: Vec<Vec<String>>,
+}
+
+/// Arguments of a hook, a list of them or a command line without quotes,
+/// which is split on whitespace
+fn hook_arguments(hook: &toml_edit::Value) -> Result<Vec<String>, String> {
+    let arguments = match hook {
+        toml_edit::Value::String(command) => {
+            let command = command.value();
+            if command.contains(['"', '\'', '\\']) {
+                return Err(format!(
+                    "hook `{}` quotes its arguments, give them as a list instead",
+                    command
+                ));
+            }
+            command.split_whitespace().map(str::to_string).collect()
+        }
+        toml_edit::Value::Array(arguments) => arguments
+            .iter()
+            .map(|a| a.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| "hook arguments must be strings".to_string())?,
+        _ => return Err("hooks.post must be a list of commands".to_string()),
+    };
+    match arguments.is_empty() {
+        true => Err("hooks.post has an empty command".to_string()),
+        false => Ok(arguments),
+    }
+}
+
+/// Converts a TOML value into a template value
+fn toml_value(item: &Item) -> Value {
+    fn from_value(v: &toml_edit::Value) -> Value {
+        match v {
+            toml_edit::Value::String(s) => Value::from(s.value().as_str()),
+            toml_edit::Value::Integer(i) => Value::from(*i.value()),
+            toml_edit::Value::Float(f) => Value::from(*f.value()),
+            toml_edit::Value::Boolean(b) => Value::from(*b.value()),
+            toml_edit::Value::Datetime(d) => Value::from(d.value().to_string()),
+            toml_edit::Value::Array(a) => Value::from_iter(a.iter().map(from_value)),
+            toml_edit::Value::InlineTable(t) => {
+                Value::from_iter(t.iter().map(|(k, v)| (k.to_string(), from_value(v))))
+            }
+        }
+    }
+    fn from_table(t: &Table) -> Value {
+        Value::from_iter(t.iter().map(|(k, v)| (k.to_string(), toml_value(v))))
+    }
+    match item {
+        Item::Value(v) => from_value(v),
+        Item::Table(t) => from_table(t),
+        Item::ArrayOfTables(a) => Value::from_iter(a.iter().map(from_table)),
+        Item::None => Value::UNDEFINED,
+    }
+}
+
+/// True for a relative path which does not leave its folder
+fn is_below(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Checks a destination stays below the program folder
+fn check_dest(origin: &str, dest: &str) -> CargoResult<()> {
+    match is_below(dest) {
+        true => Ok(()),
+        false => Err(ProgramError::TemplateFormatError(
+            origin.to_string(),
+            format!("destination {} must stay below the program folder", dest),
+        )),
+    }
+}
+
+impl Descriptor {
+    /// Parses descriptor text, `origin` names the template in errors
+    pub fn parse(origin: &str, text: &str) -> CargoResult<Self> {
+        let format_error = |what: String| ProgramError::TemplateFormatError(origin.into(), what);
+        let doc = text.parse::<DocumentMut>()?;
+        let string = |item: Option<&Item>, what: &str| -> CargoResult<Option<String>> {
+            match item {
+                None => Ok(None),
+                Some(i) => i
+                    .as_str()
+                    .map(|s| Some(s.to_string()))
+                    .ok_or_else(|| format_error(format!("{} must be a string", what))),
+            }
+        };
+        let flag = |item: Option<&Item>, what: &str| -> CargoResult<bool> {
+            match item {
+                None => Ok(false),
+                Some(i) => i
+                    .as_bool()
+                    .ok_or_else(|| format_error(format!("{} must be true or false", what))),
+            }
+        };
+        let mut descriptor = Descriptor::default();
+        if let Some(template) = doc.get("template") {
+            descriptor.name = string(template.get("name"), "template.name")?.unwrap_or_default();
+            descriptor.description =
+                string(template.get("description"), "template.description")?.unwrap_or_default();
+        }
+        if let Some(variables) = doc.get("variables") {
+            let variables = variables
+                .as_table_like()
+                .ok_or_else(|| format_error("variables must be a table".into()))?;
+            for (name, spec) in variables.iter() {
+                let what = format!("variables.{}", name);
+                let spec = spec
+                    .as_table_like()
+                    .ok_or_else(|| format_error(format!("{} must be a table", what)))?;
+                if let Some((key, _)) = spec.iter().find(|(k, _)| !VARIABLE_KEYS.contains(k)) {
+                    return Err(format_error(format!("unknown key {}.{}", what, key)));
+                }
+                let pattern = match string(spec.get("pattern"), &what)? {
+                    Some(p) => Some(
+                        Regex::new(&format!("^(?:{})$", p))
+                            .map_err(|e| format_error(format!("{}.pattern: {}", what, e)))?,
+                    ),
+                    None => None,
+                };
+                let choices = match spec.get("choices") {
+                    None => vec![],
+                    Some(c) => c
+                        .as_array()
+                        .map(|a| a.iter().filter_map(|v| v.as_str().map(str::to_string)))
+                        .map(Iterator::collect)
+                        .ok_or_else(|| format_error(format!("{}.choices must be a list", what)))?,
+                };
+                descriptor.variables.push(Variable {
+                    name: name.to_string(),
+                    description: string(spec.get("description"), &what)?,
+                    default: spec.get("default").map(toml_value),
+                    prompt: string(spec.get("prompt"), &what)?,
+                    pattern,
+                    choices,
+                });
+            }
+        }
+        if let Some(files) = doc.get("files") {
+            let files = files
+                .as_array_of_tables()
+                .ok_or_else(|| format_error("files must be a list of [[files]] tables".into()))?;
+            for file in files.iter() {
+                if let Some((key, _)) = file.iter().find(|(k, _)| !FILE_KEYS.contains(k)) {
+                    return Err(format_error(format!("unknown key files.{}", key)));
+                }
+                let source = string(file.get("source"), "files.source")?
+                    .ok_or_else(|| format_error("files entry without source".into()))?;
+                if !is_below(&source) {
+                    return Err(format_error(format!(
+                        "source {} must stay within the template",
+                        source
+                    )));
+                }
+                let dest = string(file.get("dest"), "files.dest")?.unwrap_or(source.clone());
+                let binary = flag(file.get("binary"), "files.binary")?;
+                descriptor.files.push(FileEntry {
+                    source,
+                    dest,
+                    when: string(file.get("when"), "files.when")?,
+                    verbatim: binary || flag(file.get("verbatim"), "files.verbatim")?,
+                    binary,
+                });
+            }
+        }
+        if let Some(hooks) = doc.get("hooks").and_then(|h| h.get("post")) {
+            let hooks = hooks
+                .as_array()
+                .ok_or_else(|| format_error("hooks.post must be a list of commands".into()))?;
+            for hook in hooks {
+                descriptor
+                    .hooks
+                    .push(hook_arguments(hook).map_err(format_error)?);
+            }
+        }
+        Ok(descriptor)
+    }
+
+    /// Loads the descriptor of a template. Without one every file of the
+    /// template is rendered into `src`
+    pub fn load(source: &TemplateSource) -> CargoResult<Self> {
+        let origin = source.origin();
+        if let Some(text) = source.read(DESCRIPTOR_FILE)? {
+            let text = str::from_utf8(&text).map_err(|_| {
+                ProgramError::TemplateFormatError(origin.clone(), "not UTF-8".into())
+            })?;
+            return Descriptor::parse(&origin, text);
+        }
+        let files = source.files()?;
+        if files.is_empty() {
+            return Err(ProgramError::TemplateEmpty(origin));
+        }
+        Ok(Descriptor {
+            files: files
+                .into_iter()
+                .map(|f| FileEntry {
+                    dest: format!("src/{}", f),
+                    source: f,
+                    when: None,
+                    verbatim: false,
+                    binary: false,
+                })
+                .collect(),
+            ..Descriptor::default()
+        })
+    }
+
+    /// Resolves the declared variables from `given` name and value pairs,
+    /// else by asking on `input` for those with a prompt, else from their
+    /// default. Values given or entered are validated
+    pub fn resolve_variables(
+        &self,
+        given: &[(String, String)],
+        mut input: Option<&mut dyn BufRead>,
+    ) -> CargoResult<BTreeMap<String, Value>> {
+        if let Some((name, value)) = given
+            .iter()
+            .find(|(n, _)| !self.variables.iter().any(|v| &v.name == n))
+        {
+            return Err(ProgramError::InvalidVariable(
+                name.clone(),
+                value.clone(),
+                "not declared by the template".to_string(),
+            ));
+        }
+        let mut values = BTreeMap::new();
+        for variable in &self.variables {
+            let mut value = given
+                .iter()
+                .rev()
+                .find(|(n, _)| n == &variable.name)
+                .map(|(_, v)| v.clone());
+            if let (None, Some(prompt), Some(input)) = (&value, &variable.prompt, input.as_mut()) {
+                if let Some(description) = &variable.description {
+                    println!("{}", description);
+                }
+                match &variable.default {
+                    Some(default) => print!("{} [{}]: ", prompt, default),
+                    None => print!("{}: ", prompt),
+                }
+                std::io::stdout().flush()?;
+                let mut line = String::new();
+                input.read_line(&mut line)?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    value = Some(line.to_string());
+                }
+            }
+            let value = match (value, &variable.default) {
+                (Some(v), _) => {
+                    variable.validate(&v)?;
+                    Value::from(v)
+                }
+                (None, Some(default)) => default.clone(),
+                (None, None) => return Err(ProgramError::MissingVariable(variable.name.clone())),
+            };
+            values.insert(variable.name.clone(), value);
+        }
+        Ok(values)
+    }
+
+    /// Renders the files selected by the descriptor as (destination, contents)
+    pub fn render_files(
+        &self,
+        source: &TemplateSource,
+        vars: &TemplateVars,
+    ) -> CargoResult<Vec<(String, Contents)>> {
+        let origin = source.origin();
+        let legacy = Regex::new(PROG_IDENTIFIER).unwrap();
+        let crate_name = vars.name.replace('-', "_");
+        let mut rendered = vec![];
+        for file in &self.files {
+            if let Some(when) = &file.when {
+                if !is_true(when, vars)? {
+                    continue;
+                }
+            }
+            let dest = render(&file.dest, &file.dest, vars)?;
+            check_dest(&origin, &dest)?;
+            let bytes = source.read(&file.source)?.ok_or_else(|| {
+                ProgramError::TemplateFormatError(
+                    origin.clone(),
+                    format!("missing file {}", file.source),
+                )
+            })?;
+            if file.binary {
+                rendered.push((dest, Contents::Binary(bytes)));
+                continue;
+            }
+            let text = String::from_utf8(bytes).map_err(|_| {
+                ProgramError::TemplateFormatError(
+                    origin.clone(),
+                    format!("{} is not UTF-8, mark it binary", file.source),
+                )
+            })?;
+            if file.verbatim {
+                rendered.push((dest, Contents::Text(text)));
+                continue;
+            }
+            if let Some(text) = render_file(&file.source, &text, vars)? {
+                let text = legacy.replace_all(&text, crate_name.as_str()).to_string();
+                rendered.push((dest, Contents::Text(text)));
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTOR: &str = r#"
+[template]
+name = "house"
+description = "Our house program"
+
+[variables.account]
+prompt = "Account struct name"
+default = "Vault"
+pattern = "[A-Z][A-Za-z0-9]*"
+
+[variables.size]
+choices = ["small", "large"]
+
+[[files]]
+source = "lib.rs"
+dest = "src/{{ name_snake }}.rs"
+
+[[files]]
+source = "id.rs"
+dest = "src/id.rs"
+when = "program_id"
+
+[[files]]
+source = "logo.png"
+dest = "assets/logo.png"
+binary = true
+
+[[files]]
+source = "raw.txt"
+verbatim = true
+
+[hooks]
+post = ["cargo fmt", ["sh", "-c", "echo 'rendered program'"]]
+"#;
+
+    /// Writes the descriptor and its files to a template folder
+    fn template_folder() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(DESCRIPTOR_FILE), DESCRIPTOR).unwrap();
+        fs::write(dir.path().join("lib.rs"), "pub struct {{ account }};\n").unwrap();
+        fs::write(
+            dir.path().join("id.rs"),
+            "declare_id!(\"{{ program_id }}\");\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("logo.png"), [0x89, 0x50, 0xff]).unwrap();
+        fs::write(dir.path().join("raw.txt"), "{{ kept }}").unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_descriptor_pass() {
+        let descriptor = Descriptor::parse("t", DESCRIPTOR).unwrap();
+        assert_eq!(descriptor.name, "house");
+        assert_eq!(descriptor.variables.len(), 2);
+        assert_eq!(descriptor.files.len(), 4);
+        assert_eq!(descriptor.files[3].dest, "raw.txt");
+        assert!(descriptor.files[2].verbatim);
+        assert_eq!(
+            descriptor.hooks,
+            vec![
+                vec!["cargo", "fmt"],
+                vec!["sh", "-c", "echo 'rendered program'"]
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_descriptor_fail() {
+        for bad in [
+            "[[files]]\ndest = \"x\"\n",
+            "[[files]]\nsource = \"x\"\ncopy = true\n",
+            "[variables.x]\npattern = \"(\"\n",
+            "[variables.x]\ndefualt = 1\n",
+            "[hooks]\npost = \"cargo fmt\"\n",
+            "[hooks]\npost = [\"sh -c 'cargo fmt'\"]\n",
+            "[hooks]\npost = [[\"cargo\", 1]]\n",
+            "[hooks]\npost = [[]]\n",
+        ] {
+            assert!(
+                matches!(
+                    Descriptor::parse("t", bad),
+                    Err(ProgramError::TemplateFormatError(_, _))
+                ),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_variables_pass() {
+        let descriptor = Descriptor::parse("t", DESCRIPTOR).unwrap();
+        let given = vec![("size".to_string(), "large".to_string())];
+        let values = descriptor.resolve_variables(&given, None).unwrap();
+        assert_eq!(values["account"].as_str(), Some("Vault"));
+        assert_eq!(values["size"].as_str(), Some("large"));
+        // Prompted values replace the default
+        let mut input = "Escrow\n".as_bytes();
+        let values = descriptor
+            .resolve_variables(&given, Some(&mut input))
+            .unwrap();
+        assert_eq!(values["account"].as_str(), Some("Escrow"));
+    }
+
+    #[test]
+    fn resolve_variables_fail() {
+        let descriptor = Descriptor::parse("t", DESCRIPTOR).unwrap();
+        assert!(matches!(
+            descriptor.resolve_variables(&[], None),
+            Err(ProgramError::MissingVariable(name)) if name == "size"
+        ));
+        let given = |k: &str, v: &str| vec![(k.to_string(), v.to_string())];
+        for bad in [
+            given("size", "medium"),
+            given("unknown", "x"),
+            vec![
+                ("size".to_string(), "small".to_string()),
+                ("account".to_string(), "vault".to_string()),
+            ],
+        ] {
+            assert!(matches!(
+                descriptor.resolve_variables(&bad, None),
+                Err(ProgramError::InvalidVariable(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn render_files_pass() {
+        let dir = template_folder();
+        let source = TemplateSource::Folder(dir.path().to_path_buf());
+        let descriptor = Descriptor::load(&source).unwrap();
+        let mut vars = TemplateVars::new("my-prog", "1.16.0");
+        vars.extra = descriptor
+            .resolve_variables(&[("size".to_string(), "small".to_string())], None)
+            .unwrap();
+        let files = descriptor.render_files(&source, &vars).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "src/my_prog.rs".to_string(),
+                    Contents::Text("pub struct Vault;\n".to_string())
+                ),
+                (
+                    "assets/logo.png".to_string(),
+                    Contents::Binary(vec![0x89, 0x50, 0xff])
+                ),
+                (
+                    "raw.txt".to_string(),
+                    Contents::Text("{{ kept }}".to_string())
+                ),
+            ]
+        );
+        vars.program_id = Some("11111111111111111111111111111111".to_string());
+        let files = descriptor.render_files(&source, &vars).unwrap();
+        assert_eq!(files[1].0, "src/id.rs");
+    }
+
+    #[test]
+    fn render_files_dest_fail() {
+        let source = TemplateSource::Embedded(&[("a.rs", b"")]);
+        let descriptor =
+            Descriptor::parse("t", "[[files]]\nsource = \"a.rs\"\ndest = \"../a.rs\"\n");
+        assert!(matches!(
+            descriptor
+                .unwrap()
+                .render_files(&source, &TemplateVars::new("foo", "1.16.0")),
+            Err(ProgramError::TemplateFormatError(_, _))
+        ));
+    }
+
+    #[test]
+    fn parse_source_fail() {
+        for source in ["../../.ssh/id_rsa", "/etc/passwd", "src/../../a.rs", ""] {
+            let text = format!("[[files]]\nsource = {:?}\ndest = \"src/a.rs\"\n", source);
+            assert!(
+                matches!(
+                    Descriptor::parse("t", &text),
+                    Err(ProgramError::TemplateFormatError(_, _))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn load_without_descriptor_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("state")).unwrap();
+        fs::write(dir.path().join("lib.rs"), "").unwrap();
+        fs::write(dir.path().join("state/mod.rs"), "").unwrap();
+        let descriptor = Descriptor::load(&TemplateSource::Folder(dir.path().into())).unwrap();
+        let dests = descriptor
+            .files
+            .iter()
+            .map(|f| f.dest.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(dests, vec!["src/lib.rs", "src/state/mod.rs"]);
+    }
+}
@@ -0,0 +1,331 @@
+//! Program source editing
+//!
+//! Sources are parsed with syn to find where changes go, then edited as
+//! text so the formatting and comments of the original are preserved.
+
+use crate::error::{CargoResult, ProgramError};
+use proc_macro2::{LineColumn, Span};
+use std::path::{Path, PathBuf};
+use syn::{spanned::Spanned, Item, ItemUse, UseTree};
+
+/// A parsed source file and the changes planned into it
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    pub file: syn::File,
+    /// Byte ranges replaced by text, empty for insertions
+    changes: Vec<(usize, usize, String)>,
+}
+
+impl SourceFile {
+    /// Reads and parses the source file at `path`
+    pub fn read(path: &Path) -> CargoResult<Self> {
+        if !path.is_file() {
+            return Err(ProgramError::SourceError(
+                path.display().to_string(),
+                "no such file".to_string(),
+            ));
+        }
+        Self::parse(path, std::fs::read_to_string(path)?)
+    }
+
+    /// Parses `text` as the source file at `path`
+    pub fn parse(path: &Path, text: String) -> CargoResult<Self> {
+        let file = syn::parse_file(&text).map_err(|e| {
+            let at = e.span().start();
+            ProgramError::SourceError(
+                path.display().to_string(),
+                format!("{} at line {}", e, at.line),
+            )
+        })?;
+        Ok(SourceFile {
+            path: path.to_path_buf(),
+            text,
+            file,
+            changes: vec![],
+        })
+    }
+
+    /// Error about this file
+    pub fn error(&self, reason: impl Into<String>) -> ProgramError {
+        ProgramError::SourceError(self.path.display().to_string(), reason.into())
+    }
+
+    /// Byte offset of a line and column
+    pub fn offset(&self, at: LineColumn) -> usize {
+        let start = self.line_offset(at.line);
+        let line = &self.text[start..];
+        start
+            + line
+                .char_indices()
+                .nth(at.column)
+                .map_or(line.len(), |(i, _)| i)
+    }
+
+    /// Byte offset of the start of 1-based `line`
+    fn line_offset(&self, line: usize) -> usize {
+        match line {
+            0 | 1 => 0,
+            _ => self
+                .text
+                .match_indices('\n')
+                .nth(line - 2)
+                .map_or(self.text.len(), |(i, _)| i + 1),
+        }
+    }
+
+    /// Byte offset of the start of the line holding `at`
+    pub fn line_start(&self, at: LineColumn) -> usize {
+        self.line_offset(at.line)
+    }
+
+    /// Byte offset just past the line holding `at`
+    pub fn line_end(&self, at: LineColumn) -> usize {
+        self.line_offset(at.line + 1)
+    }
+
+    /// Leading whitespace of the line holding `at`
+    pub fn indent(&self, at: LineColumn) -> String {
+        self.text[self.line_start(at)..]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect()
+    }
+
+    /// Source text covered by `span`
+    pub fn source(&self, span: Span) -> &str {
+        &self.text[self.offset(span.start())..self.offset(span.end())]
+    }
+
+    /// Plans inserting `text` at byte `offset`. Insertions at the same
+    /// offset keep the order they were planned in
+    pub fn insert(&mut self, offset: usize, text: impl Into<String>) {
+        self.changes.push((offset, offset, text.into()));
+    }
+
+    /// Plans replacing the bytes from `start` to `end` with `text`
+    pub fn replace(&mut self, start: usize, end: usize, text: impl Into<String>) {
+        self.changes.push((start, end, text.into()));
+    }
+
+    /// Plans `text` as whole lines before the line holding `at`
+    pub fn insert_lines_before(&mut self, at: LineColumn, text: impl Into<String>) {
+        let offset = self.line_start(at);
+        self.insert(offset, text);
+    }
+
+    /// Plans `use` declarations for the `(name, path)` pairs not yet in
+    /// scope of `items`. Each goes in sorted place among the last group of
+    /// `use` items, or at `fallback` when there are none
+    pub fn import_missing(
+        &mut self,
+        items: &[Item],
+        in_scope: &[String],
+        needed: &[(&str, &str)],
+        fallback: LineColumn,
+    ) {
+        let mut missing = needed
+            .iter()
+            .filter(|(name, _)| !in_scope.iter().any(|n| n == name))
+            .map(|(_, path)| *path)
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+        let group = use_group(items);
+        if group.is_empty() {
+            let indent = self.indent(fallback);
+            let text = missing
+                .iter()
+                .map(|path| format!("{}use {};\n", indent, path))
+                .collect::<String>();
+            let offset = self.line_start(fallback);
+            self.insert(offset, text);
+            return;
+        }
+        let indent = self.indent(group[0].span().start());
+        for path in missing {
+            let offset = match group.iter().find(|u| {
+                let tree = self.source(u.tree.span()).replace(char::is_whitespace, "");
+                tree.as_str() > path
+            }) {
+                Some(u) => self.line_start(u.span().start()),
+                None => self.line_end(group[group.len() - 1].span().end()),
+            };
+            self.insert(offset, format!("{}use {};\n", indent, path));
+        }
+    }
+
+    /// The text with the planned changes made
+    pub fn edited(&self) -> String {
+        let mut changes = self.changes.iter().collect::<Vec<_>>();
+        changes.sort_by_key(|(start, ..)| *start);
+        let mut out = String::with_capacity(self.text.len());
+        let mut copied = 0;
+        for (start, end, text) in changes {
+            out.push_str(&self.text[copied.min(*start)..*start]);
+            out.push_str(text);
+            copied = copied.max(*end);
+        }
+        out.push_str(&self.text[copied..]);
+        out
+    }
+}
+
+/// The last group of `use` items, those on consecutive lines
+pub fn use_group(items: &[Item]) -> Vec<&ItemUse> {
+    let mut group: Vec<&ItemUse> = vec![];
+    for item in items.iter().rev() {
+        let Item::Use(u) = item else {
+            continue;
+        };
+        match group.first() {
+            Some(first) if u.span().end().line + 1 != first.span().start().line => break,
+            _ => group.insert(0, u),
+        }
+    }
+    group
+}
+
+/// Collects the paths a `use` tree imports below `prefix`, a glob as `*`.
+/// False for trees a path can not express, such as renames
+pub fn use_paths(tree: &UseTree, prefix: &str, paths: &mut Vec<String>) -> bool {
+    let join = |name: &str| match prefix.is_empty() {
+        true => name.to_string(),
+        false => format!("{}::{}", prefix, name),
+    };
+    match tree {
+        UseTree::Path(path) => use_paths(&path.tree, &join(&path.ident.to_string()), paths),
+        UseTree::Name(name) if name.ident == "self" => {
+            paths.push(prefix.to_string());
+            !prefix.is_empty()
+        }
+        UseTree::Name(name) => {
+            paths.push(join(&name.ident.to_string()));
+            true
+        }
+        UseTree::Glob(_) => {
+            paths.push(join("*"));
+            true
+        }
+        UseTree::Rename(_) => false,
+        UseTree::Group(group) => group
+            .items
+            .iter()
+            .all(|tree| use_paths(tree, prefix, paths)),
+    }
+}
+
+/// Collects the names a `use` tree below module `module` brings into scope
+fn use_names(tree: &UseTree, module: &str, parent: &[String], names: &mut Vec<String>) {
+    match tree {
+        UseTree::Path(path) if path.ident == "super" && matches!(*path.tree, UseTree::Glob(_)) => {
+            names.extend(parent.iter().cloned())
+        }
+        UseTree::Path(path) => use_names(&path.tree, &path.ident.to_string(), parent, names),
+        UseTree::Name(name) if name.ident == "self" => names.push(module.to_string()),
+        UseTree::Name(name) => names.push(name.ident.to_string()),
+        UseTree::Rename(rename) => names.push(rename.rename.to_string()),
+        UseTree::Glob(_) => {}
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                use_names(tree, module, parent, names);
+            }
+        }
+    }
+}
+
+/// Names in scope of `items`: those they import or declare. `parent`
+/// holds the names `use super::*` brings in
+pub fn names_in_scope(items: &[Item], parent: &[String]) -> Vec<String> {
+    let mut names = vec![];
+    for item in items {
+        let ident = match item {
+            Item::Use(u) => {
+                use_names(&u.tree, "", parent, &mut names);
+                continue;
+            }
+            Item::Const(i) => &i.ident,
+            Item::Enum(i) => &i.ident,
+            Item::Fn(i) => &i.sig.ident,
+            Item::Mod(i) => &i.ident,
+            Item::Static(i) => &i.ident,
+            Item::Struct(i) => &i.ident,
+            Item::Trait(i) => &i.ident,
+            Item::Type(i) => &i.ident,
+            _ => continue,
+        };
+        names.push(ident.to_string());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "//! Module\nuse a::{b, c as d, e::{self}};\n\nfn f() {\n    g();\n}\n";
+
+    fn source_file(dir: &Path, text: &str) -> SourceFile {
+        let path = dir.join("lib.rs");
+        std::fs::write(&path, text).unwrap();
+        SourceFile::read(&path).unwrap()
+    }
+
+    #[test]
+    fn source_file_edits_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source = source_file(dir.path(), SOURCE);
+        let Item::Fn(f) = &source.file.items[1] else {
+            panic!()
+        };
+        let close = f.block.brace_token.span.close().start();
+        let body = f.block.stmts[0].span();
+        assert_eq!(source.source(body), "g();");
+        assert_eq!(source.indent(body.start()), "    ");
+        let items = source.file.items.clone();
+        let names = names_in_scope(&items, &[]);
+        assert_eq!(names, vec!["b", "d", "e", "f"]);
+        source.insert_lines_before(close, "    h();\n");
+        source.import_missing(&items, &names, &[("b", "a::b"), ("h", "e::h")], close);
+        assert_eq!(
+            source.edited(),
+            "//! Module\nuse a::{b, c as d, e::{self}};\nuse e::h;\n\nfn f() {\n    g();\n    h();\n}\n"
+        );
+    }
+
+    #[test]
+    fn use_paths_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "use a::{b, e::{self, f}, g::*};\n\nuse h;\nuse i::j;\n";
+        let mut source = source_file(dir.path(), text);
+        let items = source.file.items.clone();
+        let group = use_group(&items);
+        assert_eq!(group.len(), 2);
+        let Item::Use(first) = &items[0] else {
+            panic!()
+        };
+        let mut paths = vec![];
+        assert!(use_paths(&first.tree, "", &mut paths));
+        assert_eq!(paths, vec!["a::b", "a::e", "a::e::f", "a::g::*"]);
+        assert!(!use_paths(&syn::parse_quote!(a::{b as c}), "", &mut paths));
+        let start = source.line_start(group[0].span().start());
+        let end = source.line_end(group[1].span().end());
+        source.replace(start, end, "use h::{self, k};\n");
+        source.insert(end, "use l;\n");
+        assert_eq!(
+            source.edited(),
+            "use a::{b, e::{self, f}, g::*};\n\nuse h::{self, k};\nuse l;\n"
+        );
+    }
+
+    #[test]
+    fn source_file_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "fn f( {").unwrap();
+        assert!(matches!(
+            SourceFile::read(&path),
+            Err(ProgramError::SourceError(..))
+        ));
+    }
+}
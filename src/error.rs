@@ -10,15 +10,75 @@ pub enum ProgramError {
     NoHomeFound,
     #[error("Solana install not found in {0}")]
     SolanaNotFound(String),
+    #[error("No string `channel` entry in {0}")]
+    SolanaVersionMissing(String),
+    #[error("Can not resolve the release of Solana channel `{0}` from {1}")]
+    SolanaChannelUnresolved(String, String),
+    #[error("Invalid Solana version {0}, expected X.Y.Z")]
+    InvalidSolanaVersion(String),
+    #[error("Solana version not found, use --solana-version or set SOLANA_VERSION ({0})")]
+    SolanaVersionUnresolved(String),
     #[error("Project file {0} exists")]
     ProjectExistsError(String),
-    #[error("Program folder exists")]
-    ProgramExistsError,
+    #[error("Program folder {0} exists, use --dir to choose another")]
+    ProgramExistsError(String),
+    #[error("Output folder {0} exists")]
+    OutputExistsError(String),
+    #[error("Output folder {0} must end with the name of a folder to create")]
+    InvalidOutput(String),
+    #[error("Manifest path {0} must point to a Cargo.toml")]
+    InvalidManifestPath(String),
+    #[error("Root Cargo.toml has no [package] to move")]
+    NoRootPackage,
+    #[error("Package folder {0} exists")]
+    PackageExistsError(String),
+    #[error("File {0} changed since the changes were planned")]
+    StaleFileError(String),
+    #[error("Incompatible Solana upgrade of {0}, use --force to apply it anyway")]
+    IncompatibleUpgrade(String),
+    #[error("Template {0} not found, it is neither a folder nor a built-in template")]
+    TemplateNotFound(String),
+    #[error("Invalid template name {0}, expected a folder name other than a built-in template")]
+    InvalidTemplateName(String),
+    #[error("User template {0} exists, remove it to save it again")]
+    TemplateExistsError(String),
+    #[error("Template {0} could not be read: {1}")]
+    TemplateFetchError(String, String),
+    #[error("Can not edit {0}: {1}")]
+    SourceError(String, String),
+    #[error("Template folder {0} has no files")]
+    TemplateEmpty(String),
+    #[error("Template {0} failed to render: {1}")]
+    TemplateRenderError(String, String),
+    #[error(
+        "Invalid program name {0}, expected letters, digits, - and _ starting with a letter or _"
+    )]
+    InvalidProgramName(String),
+    #[error("Invalid program id {0}, expected a base58 public key")]
+    InvalidProgramId(String),
+    #[error("Template descriptor {0}: {1}")]
+    TemplateFormatError(String, String),
+    #[error("Program spec {0}: {1}")]
+    SpecFormatError(String, String),
+    #[error("Spec changes the signature of handlers {0}, use --force to keep their bodies anyway")]
+    HandlerConflict(String),
+    #[error("Spec drops the instructions of handwritten handlers {0}, use --force to remove them anyway")]
+    HandlerRemoved(String),
+    #[error("Invalid --{0} value: {1}")]
+    InvalidArgument(String, String),
+    #[error("Template variable {0} is required")]
+    MissingVariable(String),
+    #[error("Invalid value {1} for template variable {0}: {2}")]
+    InvalidVariable(String, String, String),
+    #[error("Hook `{0}` failed, the changes were undone")]
+    HookFailed(String),
+    #[error("Manifest entry {0} has an unexpected format")]
+    ManifestFormatError(String),
     // From other modules
     CargoError(#[from] cargo_toml::Error),
     ClapError(#[from] clap::Error),
     IoError(#[from] std::io::Error),
-    TomlError(#[from] toml::ser::Error),
+    TomlEditError(#[from] toml_edit::TomlError),
     YamlError(#[from] ScanError),
 }
 
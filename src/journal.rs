@@ -0,0 +1,313 @@
+//! Transactional application of a plan
+//!
+//! All new content is first written to a staging folder beside the target
+//! tree. Only once staging succeeds are the changes moved into place, with
+//! each step recorded in a journal. The hooks of the plan then run on the
+//! committed tree. Any failure, a failing hook included, replays the journal
+//! in reverse so the tree, including the original bytes of updated files, is
+//! restored exactly.
+
+use crate::{
+    error::{CargoResult, ProgramError},
+    plan::{Plan, PlanAction},
+};
+use std::{
+    fs::{self, create_dir, create_dir_all, remove_dir_all, remove_file, rename},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Prefix of the staging folder created in the plan root
+const STAGING_PREFIX: &str = ".cargo-solana-staging";
+
+/// A completed step which can be undone
+#[derive(Debug)]
+enum JournalEntry {
+    CreatedDir(PathBuf),
+    CreatedFile(PathBuf),
+    Replaced { path: PathBuf, original: Vec<u8> },
+    Moved { from: PathBuf, to: PathBuf },
+}
+
+/// Ordered record of the steps committed so far
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Undo a single step
+    fn undo(entry: &JournalEntry) -> io::Result<()> {
+        match entry {
+            // Whatever a hook wrote into a created folder goes with it
+            JournalEntry::CreatedDir(path) => remove_dir_all(path),
+            JournalEntry::CreatedFile(path) => remove_file(path),
+            JournalEntry::Replaced { path, original } => fs::write(path, original),
+            JournalEntry::Moved { from, to } => rename(to, from),
+        }
+    }
+
+    /// Undo all recorded steps, most recent first. Steps that can not be
+    /// undone are reported and the remaining steps are still attempted
+    pub fn rollback(&mut self) {
+        while let Some(entry) = self.entries.pop() {
+            if let Err(e) = Journal::undo(&entry) {
+                eprintln!("Rollback of {:?} failed: {}", entry, e);
+            }
+        }
+    }
+
+    /// Create directories for `path` that do not exist yet, recording each
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let mut missing = path
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+            .collect::<Vec<_>>();
+        missing.reverse();
+        for dir in missing {
+            create_dir(dir)?;
+            self.entries
+                .push(JournalEntry::CreatedDir(dir.to_path_buf()));
+        }
+        Ok(())
+    }
+}
+
+/// Staging folder which is removed when dropped
+struct Staging {
+    dir: PathBuf,
+}
+
+impl Staging {
+    /// Creates the staging folder under `root`
+    fn new(root: &Path) -> io::Result<Self> {
+        let dir = root.join(format!("{}-{}", STAGING_PREFIX, std::process::id()));
+        create_dir(&dir)?;
+        Ok(Staging { dir })
+    }
+
+    /// Where content for `path` is staged, which must be below `root`
+    fn path_for(&self, root: &Path, path: &Path) -> io::Result<PathBuf> {
+        match path.strip_prefix(root) {
+            Ok(relative)
+                if relative
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))) =>
+            {
+                Ok(self.dir.join(relative))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not within {}", path.display(), root.display()),
+            )),
+        }
+    }
+
+    /// Writes content for `path` into the staging folder
+    fn write(&self, root: &Path, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let staged = self.path_for(root, path)?;
+        if let Some(parent) = staged.parent() {
+            create_dir_all(parent)?;
+        }
+        fs::write(staged, contents)
+    }
+
+    /// Writes all new content of the plan into the staging folder
+    fn stage(&self, plan: &Plan) -> io::Result<()> {
+        for action in &plan.actions {
+            match action {
+                PlanAction::CreateDir(path) => {
+                    create_dir_all(self.path_for(&plan.root, path)?)?;
+                }
+                PlanAction::CreateFile { path, contents }
+                | PlanAction::UpdateFile { path, contents, .. } => {
+                    self.write(&plan.root, path, contents.as_bytes())?;
+                }
+                PlanAction::CreateBinary { path, contents } => {
+                    self.write(&plan.root, path, contents)?;
+                }
+                PlanAction::Move { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.dir);
+    }
+}
+
+/// Moves staged content into place one step at a time, journaling each
+fn commit(plan: &Plan, staging: &Staging, journal: &mut Journal) -> CargoResult<()> {
+    for action in &plan.actions {
+        match action {
+            PlanAction::CreateDir(path) => {
+                println!("Building {}", path.display());
+                create_dir(path)?;
+                journal
+                    .entries
+                    .push(JournalEntry::CreatedDir(path.to_path_buf()));
+            }
+            PlanAction::CreateFile { path, .. } | PlanAction::CreateBinary { path, .. } => {
+                println!("  Creating {}", path.display());
+                if path.exists() {
+                    return Err(ProgramError::StaleFileError(path.display().to_string()));
+                }
+                rename(staging.path_for(&plan.root, path)?, path)?;
+                journal
+                    .entries
+                    .push(JournalEntry::CreatedFile(path.to_path_buf()));
+            }
+            PlanAction::UpdateFile { path, original, .. } => {
+                println!("  Updating {}", path.display());
+                let current = fs::read(path)?;
+                if current != original.as_bytes() {
+                    return Err(ProgramError::StaleFileError(path.display().to_string()));
+                }
+                let staged = staging.path_for(&plan.root, path)?;
+                fs::set_permissions(&staged, fs::metadata(path)?.permissions())?;
+                rename(staged, path)?;
+                journal.entries.push(JournalEntry::Replaced {
+                    path: path.to_path_buf(),
+                    original: current,
+                });
+            }
+            PlanAction::Move { from, to } => {
+                println!("  Moving {} to {}", from.display(), to.display());
+                rename(from, to)?;
+                journal.entries.push(JournalEntry::Moved {
+                    from: from.to_path_buf(),
+                    to: to.to_path_buf(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the plan and runs its hooks as a single transaction. Either every
+/// change is made and every hook succeeds or, on any failure, the tree is
+/// restored to its state before the call
+pub fn apply(plan: &Plan) -> CargoResult<()> {
+    let mut journal = Journal::default();
+    let result = journal
+        .create_dir_all(&plan.root)
+        .map_err(ProgramError::from)
+        .and_then(|_| {
+            let staging = Staging::new(&plan.root)?;
+            staging.stage(plan)?;
+            commit(plan, &staging, &mut journal)
+        })
+        .and_then(|_| plan.run_hooks());
+    if result.is_err() {
+        println!("Failed, rolling back changes");
+        journal.rollback();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Lists everything below `root` for comparing trees
+    fn snapshot(root: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+        let mut out = vec![];
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    pending.push(path.clone());
+                    out.push((path, None));
+                } else {
+                    let bytes = fs::read(&path).unwrap();
+                    out.push((path, Some(bytes)));
+                }
+            }
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn apply_pass() {
+        let root = tempdir().unwrap();
+        let ws = root.path().join("new/ws");
+        let mut plan = Plan::new(&ws);
+        plan.create_dir(ws.join("program"));
+        plan.create_file(ws.join("program/lib.rs"), "lib".to_string());
+        plan.create_file(ws.join("Cargo.toml"), "[workspace]".to_string());
+        apply(&plan).unwrap();
+        assert_eq!(
+            fs::read_to_string(ws.join("program/lib.rs")).unwrap(),
+            "lib"
+        );
+        assert_eq!(
+            snapshot(&ws).len(),
+            3,
+            "staging folder should be cleaned up"
+        );
+    }
+
+    #[test]
+    fn apply_rollback_pass() {
+        let root = tempdir().unwrap();
+        let original = "# keep\r\n[workspace]\r\nmembers = []\r\n";
+        fs::write(root.path().join("Cargo.toml"), original).unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+        fs::write(root.path().join("src/main.rs"), "fn main() {}").unwrap();
+        let before = snapshot(root.path());
+
+        let mut plan = Plan::new(root.path());
+        plan.create_dir(root.path().join("programs"));
+        plan.create_dir(root.path().join("programs/foo"));
+        plan.create_file(root.path().join("programs/foo/lib.rs"), "lib".to_string());
+        plan.move_path(root.path().join("src"), root.path().join("programs/src"));
+        plan.update_file(
+            root.path().join("Cargo.toml"),
+            original.to_string(),
+            "[workspace]\nmembers = [\"programs/foo\"]\n".to_string(),
+        );
+        // Fails after everything else has been committed
+        plan.move_path(root.path().join("missing"), root.path().join("gone"));
+        assert!(apply(&plan).is_err());
+        assert_eq!(snapshot(root.path()), before);
+    }
+
+    #[test]
+    fn apply_outside_root_fail() {
+        let root = tempdir().unwrap();
+        let ws = root.path().join("ws");
+        fs::create_dir(&ws).unwrap();
+        for outside in [root.path().join("lib.rs"), ws.join("program/../../lib.rs")] {
+            let mut plan = Plan::new(&ws);
+            plan.create_dir(ws.join("program"));
+            plan.create_file(outside, "lib".to_string());
+            assert!(apply(&plan).is_err());
+            assert_eq!(snapshot(root.path()), vec![(ws.clone(), None)]);
+        }
+    }
+
+    #[test]
+    fn apply_stale_fail() {
+        let root = tempdir().unwrap();
+        fs::write(root.path().join("Cargo.toml"), "changed").unwrap();
+        let mut plan = Plan::new(root.path());
+        plan.create_dir(root.path().join("program"));
+        plan.update_file(
+            root.path().join("Cargo.toml"),
+            "planned".to_string(),
+            "new".to_string(),
+        );
+        assert!(matches!(apply(&plan), Err(ProgramError::StaleFileError(_))));
+        assert!(!root.path().join("program").exists());
+        assert_eq!(
+            fs::read_to_string(root.path().join("Cargo.toml")).unwrap(),
+            "changed"
+        );
+    }
+}
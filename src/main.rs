@@ -10,16 +10,42 @@
 //! `cargo solana --help`</p>
 //! `cargo solana create -n <name>`</p>
 //! `cargo solana init -n <name>`</p>
+//! `cargo solana upgrade --to <version>`</p>
+//! `cargo solana template list`</p>
+//! `cargo solana template show <template>`</p>
+//! `cargo solana template render <template> --out <dir>`</p>
+//! `cargo solana template save --from <dir> --name <template>`</p>
+//! `cargo solana add instruction <Name> --arg <name:type> --account <name:mut:signer>`</p>
+//! `cargo solana add error <Name> --message <message>`</p>
+//! `cargo solana add state <Name> --field <name:type[:max]>`</p>
+//! `cargo solana generate --spec <program.toml>`</p>
 //!
 
 use cli::Configuration;
-use ops::{create_program_update_workspace, create_project_program};
+use ops::{
+    add_error, add_instruction, add_state, create_program_update_workspace, create_project_program,
+    generate, list_templates, render_template, save_template, show_template, upgrade_programs,
+};
 
 // Modules
+mod archive;
+mod capture;
 mod cli;
+mod compat;
+mod descriptor;
+mod edit;
 mod error;
+mod journal;
 mod ops;
+mod plan;
+mod scaffold;
+mod spec;
+mod template;
+#[cfg(test)]
+mod test_support;
 mod utils;
+mod version;
+mod workspace;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut config = Configuration::new()?;
@@ -27,6 +53,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     match config.command {
         cli::ExecutionCommand::Create => create_project_program(&config)?,
         cli::ExecutionCommand::Init => create_program_update_workspace(&mut config)?,
+        cli::ExecutionCommand::Upgrade => upgrade_programs(&config)?,
+        cli::ExecutionCommand::TemplateList => list_templates(&config)?,
+        cli::ExecutionCommand::TemplateShow => show_template(&config)?,
+        cli::ExecutionCommand::TemplateRender => render_template(&config)?,
+        cli::ExecutionCommand::TemplateSave => save_template(&config)?,
+        cli::ExecutionCommand::AddInstruction => add_instruction(&config)?,
+        cli::ExecutionCommand::AddError => add_error(&config)?,
+        cli::ExecutionCommand::AddState => add_state(&config)?,
+        cli::ExecutionCommand::Generate => generate(&config)?,
     }
     Ok(())
 }
@@ -1,143 +1,688 @@
 //! Execution operations
 
-use cargo_toml::Workspace;
-
 use crate::{
+    archive::record_template,
+    capture::capture_program,
     cli::Configuration,
-    error::{self, CargoResult, ProgramError},
-    utils::get_program_resources,
+    descriptor::{Contents, Descriptor, TemplateSource, MANIFEST_DEST},
+    error::{CargoResult, ProgramError},
+    plan::{command_line, Plan},
+    scaffold::{self, ProgramSources},
+    spec::{self, ProgramSpec, Regenerated},
+    template::TemplateVars,
+    utils::{
+        build_program_manifest, build_project_manifest, builtin_template, finish_program_manifest,
+        program_template, user_templates, BUILTIN_TEMPLATES, SHARED_DEPENDENCIES,
+    },
+    version::version_of_requirement,
+    workspace::{
+        add_workspace_dependencies, add_workspace_member, hoist_dependencies, set_solana_versions,
+        split_package_manifest, workspace_members, VersionChange, PACKAGE_ENTRIES,
+    },
 };
+use heck::ToSnakeCase;
+use semver::Version;
 use std::{
-    env::set_current_dir,
-    fs::{create_dir, remove_file, rename, File},
-    io::Write,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
 };
+use toml_edit::DocumentMut;
+
+/// Plans creation of `dir` and any of its parents missing below `root`
+/// and not already planned
+fn plan_missing_dirs(root: &Path, dir: &Path, plan: &mut Plan) {
+    let mut missing = dir
+        .ancestors()
+        .take_while(|p| *p != root && !p.exists() && !plan.creates_dir(p))
+        .map(Path::to_path_buf)
+        .collect::<Vec<PathBuf>>();
+    missing.reverse();
+    for dir in missing {
+        plan.create_dir(dir);
+    }
+}
 
-/// Generates program artifacts
-/// First program folder and set Cargo.toml
-/// Then src folder and dump in files
-fn create_program(config: &Configuration) -> CargoResult<()> {
-    let mut current_dir = std::env::current_dir()?;
-    println!("Starting in {}", current_dir.display());
-    current_dir.push("program");
-    println!("Testing existance {}", current_dir.display());
-    if !current_dir.exists() {
-        {
-            // Generate the base directory and change into it
-            println!("Building {}", current_dir.display());
-            create_dir(&current_dir)?;
-            set_current_dir(&current_dir)?;
-            // Plop in the manifest
-            println!("  Putting Cargo.toml");
-            let cargo_text = toml::to_string(&config.program_manifest_template)?;
-            current_dir.push("Cargo.toml");
-            let mut cargo = File::create(&current_dir)?;
-            cargo.write_all(cargo_text.as_bytes())?;
-            current_dir.pop();
-        }
-        // Generate the src directory
-        {
-            current_dir.push("src");
-            println!("Building {}", current_dir.display());
-            create_dir(&current_dir)?;
-            set_current_dir(&current_dir)?;
-            // Smooth the progname and load the resources
-            let resource_map = get_program_resources(str::replace(&config.progname, "-", "_"));
-            for (res_filename, res_file) in resource_map {
-                println!("  Creating {}", res_filename);
-                let mut src_file = File::create(res_filename)?;
-                src_file.write_all(res_file.as_bytes())?;
+/// Plans program artifacts in the `root` folder
+/// First program folder, including any missing parents, and set Cargo.toml
+/// Then the files of the template descriptor and its hooks
+/// Returns the dependencies hoisted for `[workspace.dependencies]`
+fn plan_program(
+    config: &Configuration,
+    root: &Path,
+    plan: &mut Plan,
+) -> CargoResult<Vec<(String, String)>> {
+    let program_dir = root.join(&config.program_dir);
+    if program_dir.exists() {
+        return Err(ProgramError::ProgramExistsError(
+            program_dir.display().to_string(),
+        ));
+    }
+    // Resolve the template variables, asking for them when interactive
+    let source = program_template(config);
+    let descriptor = Descriptor::load(&source)?;
+    let mut vars = TemplateVars::from(config);
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    vars.extra = match config.interactive {
+        true => descriptor.resolve_variables(&config.vars, Some(&mut input))?,
+        false => descriptor.resolve_variables(&config.vars, None)?,
+    };
+    let mut files = descriptor.render_files(&source, &vars)?;
+    // Generate the base directory and plop in the manifest, the built-in
+    // one unless the template renders its own
+    plan_missing_dirs(root, &program_dir, plan);
+    let mut manifest = match files.iter().position(|(dest, _)| dest == MANIFEST_DEST) {
+        Some(index) => match files.remove(index).1 {
+            Contents::Text(text) => finish_program_manifest(text.parse()?, &vars)?,
+            Contents::Binary(_) => {
+                return Err(ProgramError::ManifestFormatError(MANIFEST_DEST.into()))
             }
-            current_dir.pop();
+        },
+        None => build_program_manifest(&vars)?,
+    };
+    // Record the pinned revision of templates versioned like source
+    if let TemplateSource::Archive(archive) = &source {
+        record_template(&mut manifest, archive);
+    }
+    let hoisted = match config.workspace_deps {
+        true => hoist_dependencies(&mut manifest, &SHARED_DEPENDENCIES),
+        false => vec![],
+    };
+    plan.create_file(program_dir.join(MANIFEST_DEST), manifest.to_string());
+    // Dump in the template files, creating their folders
+    for (dest, contents) in files {
+        let path = program_dir.join(dest);
+        plan_missing_dirs(&program_dir, path.parent().unwrap_or(&program_dir), plan);
+        match contents {
+            Contents::Text(text) => plan.create_file(path, text),
+            Contents::Binary(bytes) => plan.create_binary(path, bytes),
+        }
+    }
+    for hook in &descriptor.hooks {
+        plan.hook(program_dir.clone(), hook.clone());
+    }
+    Ok(hoisted)
+}
+
+/// Keeps the hooks of the plan only when `--allow-hooks` is given or, from
+/// `input`, the user agrees to run the commands listed. Those of a template
+/// fetched from a repository or tarball need `--allow-hooks`
+fn confirm_hooks(
+    config: &Configuration,
+    plan: &mut Plan,
+    input: Option<&mut dyn BufRead>,
+) -> CargoResult<()> {
+    if plan.hooks.is_empty() || config.allow_hooks {
+        return Ok(());
+    }
+    println!("The template runs these commands once the files are written:");
+    for (dir, arguments) in &plan.hooks {
+        println!("  {} (in {})", command_line(arguments), dir.display());
+    }
+    let fetched = matches!(config.template, Some(TemplateSource::Archive(_)));
+    if let (Some(input), false) = (input, fetched) {
+        print!("Run them? [y/N]: ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        if matches!(line.trim(), "y" | "Y" | "yes") {
+            return Ok(());
         }
-    } else {
-        return Err(ProgramError::ProgramExistsError);
     }
-    current_dir.pop();
-    println!("Current dir {}", current_dir.display());
-    println!("Landing back in {}", current_dir.display());
-    set_current_dir(current_dir)?;
+    println!("Warning: skipping the hooks, pass --allow-hooks to run them");
+    plan.hooks.clear();
     Ok(())
 }
 
+/// Applies a plan of a template's files, running its hooks once confirmed
+fn apply_with_hooks(config: &Configuration, mut plan: Plan) -> CargoResult<()> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    match config.interactive {
+        true => confirm_hooks(config, &mut plan, Some(&mut input))?,
+        false => confirm_hooks(config, &mut plan, None)?,
+    }
+    plan.apply()
+}
+
+/// Adds the program to the workspace root manifest text, along with
+/// any hoisted dependencies
+fn update_root_manifest(
+    config: &Configuration,
+    manifest: &str,
+    hoisted: &[(String, String)],
+) -> CargoResult<String> {
+    let updated = add_workspace_member(manifest, &config.program_dir)?;
+    match hoisted.is_empty() {
+        true => Ok(updated),
+        false => add_workspace_dependencies(&updated, hoisted),
+    }
+}
+
+/// Plans the program file set and the update of the existing Cargo.toml workspace
+fn plan_program_update_workspace(config: &Configuration) -> CargoResult<Plan> {
+    let root = &config.target_dir;
+    let manifest_path = root.join("Cargo.toml");
+    let mut plan = Plan::new(root);
+    let hoisted = plan_program(config, root, &mut plan)?;
+    // Without a root manifest start a virtual workspace from the project template
+    if !manifest_path.exists() {
+        if config.move_package.is_some() {
+            return Err(ProgramError::NoRootPackage);
+        }
+        plan.create_file(manifest_path, project_manifest(config, &hoisted)?);
+        return Ok(plan);
+    }
+    let original = std::fs::read_to_string(&manifest_path)?;
+    let root_manifest = match &config.move_package {
+        Some(package_dir) => plan_move_package(root, package_dir, config, &original, &mut plan)?,
+        None => original.clone(),
+    };
+    // Edit the manifest text so the only change is the new member
+    let updated = update_root_manifest(config, &root_manifest, &hoisted)?;
+    plan.update_file(manifest_path, original, updated);
+    Ok(plan)
+}
+
+/// Plans relocating the root package into `package_dir` as a workspace member,
+/// returning the root manifest text reduced to a virtual workspace
+fn plan_move_package(
+    root: &Path,
+    package_dir: &str,
+    config: &Configuration,
+    manifest: &str,
+    plan: &mut Plan,
+) -> CargoResult<String> {
+    let member_dir = root.join(package_dir);
+    if member_dir.exists() || Path::new(package_dir) == Path::new(&config.program_dir) {
+        return Err(ProgramError::PackageExistsError(
+            member_dir.display().to_string(),
+        ));
+    }
+    let moved = PACKAGE_ENTRIES
+        .iter()
+        .filter(|e| root.join(e).exists())
+        .map(|e| e.to_string())
+        .collect::<Vec<String>>();
+    let (root_manifest, member_manifest) = split_package_manifest(manifest, package_dir, &moved)?;
+    plan_missing_dirs(root, &member_dir, plan);
+    plan.create_file(member_dir.join("Cargo.toml"), member_manifest);
+    for entry in moved {
+        plan.move_path(root.join(&entry), member_dir.join(&entry));
+    }
+    Ok(root_manifest)
+}
+
 /// Creates the program file set and updates the existing Cargo.toml workspace
 pub fn create_program_update_workspace(config: &mut Configuration) -> CargoResult<()> {
-    // Get and hold onto
-    let mut current_dir = std::env::current_dir()?;
-    // Generate program artifacts
-    match create_program(config) {
-        Ok(_) => {
-            let cargo = &mut config.init_manifest.clone().unwrap();
-            let program = "program".to_string();
-            match &mut cargo.workspace {
-                // Update existing
-                Some(workspace) => workspace.members.push(program),
-                // Create a whole new one
-                None => {
-                    cargo.workspace = Some(Workspace {
-                        members: vec![program],
-                        default_members: vec![],
-                        exclude: vec![],
-                        metadata: None,
-                        resolver: None,
-                    })
-                }
+    let manifest_path = config.target_dir.join("Cargo.toml");
+    let created = !manifest_path.exists();
+    let plan = plan_program_update_workspace(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    apply_with_hooks(config, plan)?;
+    if created {
+        println!("No {} found, created a workspace", manifest_path.display());
+    }
+    Ok(())
+}
+
+/// Renders the project workspace manifest listing the program folder
+fn project_manifest(config: &Configuration, hoisted: &[(String, String)]) -> CargoResult<String> {
+    update_root_manifest(
+        config,
+        &build_project_manifest(&TemplateVars::from(config))?,
+        hoisted,
+    )
+}
+
+/// Plans new project then program with source copied from resources
+fn plan_project_program(config: &Configuration) -> CargoResult<Plan> {
+    let project_dir = config.target_dir.join(&config.progname);
+    if project_dir.exists() {
+        return Err(ProgramError::ProjectExistsError(config.progname.clone()));
+    }
+    let mut plan = Plan::new(&config.target_dir);
+    plan.create_dir(project_dir.clone());
+    let hoisted = plan_program(config, &project_dir, &mut plan)?;
+    plan.create_file(
+        project_dir.join("Cargo.toml"),
+        project_manifest(config, &hoisted)?,
+    );
+    Ok(plan)
+}
+
+/// Generate new project then creates program and copies program source
+/// from resources
+pub fn create_project_program(config: &Configuration) -> CargoResult<()> {
+    let plan = plan_project_program(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    apply_with_hooks(config, plan)
+}
+
+/// Version changes made to each manifest
+type ManifestChanges = Vec<(PathBuf, Vec<VersionChange>)>;
+
+/// Plans rewriting the solana crate versions in the root and member
+/// manifests, returning the plan and each manifest's changes
+fn plan_upgrade(config: &Configuration) -> CargoResult<(Plan, ManifestChanges)> {
+    let root = &config.target_dir;
+    let mut plan = Plan::new(root);
+    let mut manifests = vec![root.join("Cargo.toml")];
+    for member in workspace_members(root)? {
+        let manifest = member.join("Cargo.toml");
+        if !manifests.contains(&manifest) {
+            manifests.push(manifest);
+        }
+    }
+    let mut changed = vec![];
+    for manifest in manifests {
+        let original = std::fs::read_to_string(&manifest)?;
+        let mut doc = original.parse::<DocumentMut>()?;
+        let changes = set_solana_versions(&mut doc, &config.solana_version);
+        if !changes.is_empty() {
+            plan.update_file(manifest.clone(), original, doc.to_string());
+            changed.push((manifest, changes));
+        }
+    }
+    Ok((plan, changed))
+}
+
+/// True when moving from requirement `from` to `to` may break the build,
+/// that is a change of major version or a downgrade
+fn incompatible_upgrade(from: &str, to: &str) -> bool {
+    let parse = |v: &str| version_of_requirement(v).and_then(|v| Version::parse(&v).ok());
+    match (parse(from), parse(to)) {
+        (Some(from), Some(to)) => from.major != to.major || to < from,
+        _ => false,
+    }
+}
+
+/// Upgrades the solana crates of the workspace programs to the configured
+/// version, reporting each change. Incompatible upgrades are refused
+/// unless forced
+pub fn upgrade_programs(config: &Configuration) -> CargoResult<()> {
+    let (plan, changed) = plan_upgrade(config)?;
+    if changed.is_empty() {
+        println!("Solana crates already at {}", config.solana_version);
+        return Ok(());
+    }
+    println!("Upgrading Solana crates to {}", config.solana_version);
+    let mut incompatible = vec![];
+    for (manifest, changes) in &changed {
+        println!("  {}", manifest.display());
+        for change in changes {
+            println!("    {} {} -> {}", change.krate, change.from, change.to);
+            if incompatible_upgrade(&change.from, &change.to) {
+                incompatible.push(format!(
+                    "{} {} in {}",
+                    change.krate,
+                    change.from,
+                    manifest.display()
+                ));
             }
-            // Rename existing to recover if error
-            match rename("./Cargo.toml", "./CargoSolana.bak") {
-                Ok(_) => {
-                    let cargo_text = toml::to_string(&cargo).unwrap();
-                    current_dir.push("Cargo.toml");
-                    let mut cargo = File::create(&current_dir)?;
-                    cargo.write_all(cargo_text.as_bytes())?;
-                    remove_file("./CargoSolana.bak").unwrap();
-                    Ok(())
-                }
-                Err(e) => {
-                    current_dir.push("program");
-                    let _ = std::fs::remove_dir_all(current_dir);
-                    Err(error::ProgramError::IoError(e))
+        }
+    }
+    if config.dry_run {
+        if !incompatible.is_empty() {
+            println!(
+                "Warning: incompatible Solana upgrade of {}{}",
+                incompatible.join(", "),
+                if config.force {
+                    ""
+                } else {
+                    ", use --force to apply it"
                 }
-            }
+            );
         }
-        // Clean up program
-        Err(e) => {
-            current_dir.push("program");
-            let _ = std::fs::remove_dir_all(current_dir);
-            Err(e)
+        plan.print();
+        return Ok(());
+    }
+    if !incompatible.is_empty() && !config.force {
+        return Err(ProgramError::IncompatibleUpgrade(incompatible.join(", ")));
+    }
+    plan.apply()
+}
+
+/// Lists the built-in templates then those of `user_dir`, one per line
+/// with the description of its descriptor
+fn template_list(user_dir: Option<&Path>) -> CargoResult<String> {
+    let describe = |source: &TemplateSource| match Descriptor::load(source) {
+        Ok(descriptor) => descriptor.description,
+        Err(e) => format!("({})", e),
+    };
+    let mut out = "Built-in templates:\n".to_string();
+    for (name, files) in BUILTIN_TEMPLATES {
+        let description = describe(&TemplateSource::Embedded(files));
+        out.push_str(&format!("  {:<16}{}\n", name, description));
+    }
+    let Some(user_dir) = user_dir else {
+        return Ok(out);
+    };
+    out.push_str(&format!("\nUser templates in {}:\n", user_dir.display()));
+    let templates = user_templates(user_dir)?;
+    if templates.is_empty() {
+        out.push_str("  none\n");
+    }
+    for (name, source) in templates {
+        let mut description = describe(&source);
+        if builtin_template(&name).is_some() {
+            description.push_str(" (hidden by the built-in template)");
         }
+        out.push_str(&format!("  {:<16}{}\n", name, description.trim_start()));
     }
+    Ok(out)
 }
 
-/// Generate new project then creates program and copies program source
-/// from resources
-pub fn create_project_program(config: &Configuration) -> CargoResult<()> {
-    let mut current_dir = std::env::current_dir()?;
-    current_dir.push(config.progname.clone());
-    if current_dir.exists() {
-        Err(ProgramError::ProjectExistsError(config.progname.clone()))
-    } else {
-        // Create and change into project dir
-        println!("Creating {}", current_dir.display());
-        create_dir(&current_dir)?;
-        set_current_dir(&current_dir)?;
-        // Create the toml file
-        current_dir.push("Cargo.toml");
-        println!("  Creating {}", current_dir.display());
-        let mut cargo = File::create(&current_dir)?;
-        let cargo_text = toml::to_string(&config.project_manifest_template)?;
-        cargo.write_all(cargo_text.as_bytes())?;
-        // Pop filename
-        current_dir.pop();
-        // Create program in current dir
-        println!("Creating program in {}", current_dir.display());
-        create_program(config)?;
-        // Pop to original and change there
-        current_dir.pop();
-        println!("Changing back to {} dir", current_dir.display());
-        set_current_dir(&current_dir)?;
-        Ok(())
+/// Describes the files, variables and hooks of a template
+fn template_summary(source: &TemplateSource) -> CargoResult<String> {
+    let descriptor = Descriptor::load(source)?;
+    let mut out = match descriptor.name.is_empty() {
+        true => format!("{}\n", source.origin()),
+        false => format!("{} ({})\n", descriptor.name, source.origin()),
+    };
+    if !descriptor.description.is_empty() {
+        out.push_str(&format!("{}\n", descriptor.description));
+    }
+    out.push_str("\nFiles:\n");
+    for file in &descriptor.files {
+        let mut flags = vec![];
+        if file.binary {
+            flags.push("binary".to_string());
+        }
+        if file.verbatim {
+            flags.push("verbatim".to_string());
+        }
+        if let Some(when) = &file.when {
+            flags.push(format!("when {}", when));
+        }
+        out.push_str(&format!("  {} -> {}", file.source, file.dest));
+        match flags.is_empty() {
+            true => out.push('\n'),
+            false => out.push_str(&format!(" ({})\n", flags.join(", "))),
+        }
+    }
+    if !descriptor.variables.is_empty() {
+        out.push_str("\nVariables:\n");
+    }
+    for variable in &descriptor.variables {
+        match &variable.description {
+            Some(description) => out.push_str(&format!("  {}: {}\n", variable.name, description)),
+            None => out.push_str(&format!("  {}\n", variable.name)),
+        }
+        match &variable.default {
+            Some(default) => out.push_str(&format!("    default: {}\n", default)),
+            None => out.push_str("    required\n"),
+        }
+        if !variable.choices.is_empty() {
+            out.push_str(&format!("    choices: {}\n", variable.choices.join(", ")));
+        }
+        if let Some(pattern) = &variable.pattern {
+            out.push_str(&format!("    pattern: {}\n", pattern));
+        }
+    }
+    if !descriptor.hooks.is_empty() {
+        out.push_str("\nHooks:\n");
+    }
+    for hook in &descriptor.hooks {
+        out.push_str(&format!("  {}\n", command_line(hook)));
+    }
+    Ok(out)
+}
+
+/// Prints the built-in and user templates
+pub fn list_templates(config: &Configuration) -> CargoResult<()> {
+    print!("{}", template_list(config.user_templates.as_deref())?);
+    Ok(())
+}
+
+/// Prints the files, variables and hooks of the configured template
+pub fn show_template(config: &Configuration) -> CargoResult<()> {
+    print!("{}", template_summary(&program_template(config))?);
+    Ok(())
+}
+
+/// Plans the program files of the template alone in the program folder,
+/// leaving out any workspace
+fn plan_render(config: &Configuration) -> CargoResult<Plan> {
+    let out = config.target_dir.join(&config.program_dir);
+    if out.exists() {
+        return Err(ProgramError::OutputExistsError(out.display().to_string()));
+    }
+    let mut plan = Plan::new(&config.target_dir);
+    plan_program(config, &config.target_dir, &mut plan)?;
+    Ok(plan)
+}
+
+/// Renders the configured template into a new folder
+pub fn render_template(config: &Configuration) -> CargoResult<()> {
+    let plan = plan_render(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    apply_with_hooks(config, plan)
+}
+
+/// Plans writing the program in the target folder as user template
+/// `progname`, a new folder of the user templates folder
+fn plan_save_template(config: &Configuration) -> CargoResult<Plan> {
+    let user_dir = config
+        .user_templates
+        .as_deref()
+        .ok_or(ProgramError::NoHomeFound)?;
+    let name = &config.progname;
+    let single_folder = matches!(
+        Path::new(name).components().collect::<Vec<_>>()[..],
+        [std::path::Component::Normal(_)]
+    );
+    if !single_folder || name.starts_with('.') || builtin_template(name).is_some() {
+        return Err(ProgramError::InvalidTemplateName(name.clone()));
+    }
+    let template_dir = user_dir.join(name);
+    if template_dir.exists() {
+        return Err(ProgramError::TemplateExistsError(
+            template_dir.display().to_string(),
+        ));
+    }
+    let mut plan = Plan::new(user_dir);
+    plan_missing_dirs(user_dir, &template_dir, &mut plan);
+    for (file, contents) in capture_program(&config.target_dir, name)? {
+        let path = template_dir.join(file);
+        plan_missing_dirs(
+            &template_dir,
+            path.parent().unwrap_or(&template_dir),
+            &mut plan,
+        );
+        match contents {
+            Contents::Text(text) => plan.create_file(path, text),
+            Contents::Binary(bytes) => plan.create_binary(path, bytes),
+        }
     }
+    Ok(plan)
+}
+
+/// Saves the program in the target folder as a user template
+pub fn save_template(config: &Configuration) -> CargoResult<()> {
+    let plan = plan_save_template(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.apply()?;
+    println!(
+        "Saved template {0}, use it with --template {0}",
+        config.progname
+    );
+    Ok(())
+}
+
+/// Plans writing edited sources, creating those which are new
+fn plan_edits(plan: &mut Plan, sources: ProgramSources) {
+    for (path, original, edited) in sources.edits() {
+        match original {
+            Some(original) => plan.update_file(path, original, edited),
+            None => plan.create_file(path, edited),
+        }
+    }
+}
+
+/// Plans the source edits adding the instruction `progname` to the program
+/// in the target folder
+fn plan_add_instruction(config: &Configuration) -> CargoResult<Plan> {
+    let mut plan = Plan::new(&config.target_dir);
+    let mut sources = ProgramSources::new(&config.target_dir);
+    scaffold::add_instruction(
+        &mut sources,
+        &config.progname,
+        &config.fields,
+        &config.accounts,
+    )?;
+    plan_edits(&mut plan, sources);
+    Ok(plan)
+}
+
+/// Adds an instruction, its handler and a test to the program in the
+/// target folder
+pub fn add_instruction(config: &Configuration) -> CargoResult<()> {
+    let plan = plan_add_instruction(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.apply()?;
+    println!(
+        "Added instruction {}, fill in its handler and test",
+        config.progname
+    );
+    Ok(())
+}
+
+/// Plans the source edits adding the error `progname` to the program in
+/// the target folder, returning the error codes this changes
+fn plan_add_error(config: &Configuration) -> CargoResult<(Plan, scaffold::Renumbered)> {
+    let message = match &config.message {
+        Some(message) => message.clone(),
+        None => {
+            // The name in words, as a sentence
+            let words = config.progname.to_snake_case().replace('_', " ");
+            let mut chars = words.chars();
+            chars
+                .next()
+                .map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+        }
+    };
+    let mut sources = ProgramSources::new(&config.target_dir);
+    let renumbered = scaffold::add_error(
+        &mut sources,
+        &config.progname,
+        &message,
+        config.before.as_deref(),
+    )?;
+    let mut plan = Plan::new(&config.target_dir);
+    plan_edits(&mut plan, sources);
+    Ok((plan, renumbered))
+}
+
+/// Adds a custom error to the program in the target folder, warning about
+/// the error codes it changes
+pub fn add_error(config: &Configuration) -> CargoResult<()> {
+    let (plan, renumbered) = plan_add_error(config)?;
+    if !renumbered.is_empty() {
+        println!("Warning: error codes change, update the clients which decode them");
+        for (variant, from, to) in &renumbered {
+            println!("  {} {} -> {}", variant, from, to);
+        }
+    }
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.apply()?;
+    println!("Added error {}", config.progname);
+    Ok(())
+}
+
+/// Plans the source edits adding the state `progname` to the program in
+/// the target folder
+fn plan_add_state(config: &Configuration) -> CargoResult<Plan> {
+    let mut plan = Plan::new(&config.target_dir);
+    let mut sources = ProgramSources::new(&config.target_dir);
+    scaffold::add_state(&mut sources, &config.progname, &config.fields)?;
+    plan_edits(&mut plan, sources);
+    Ok(plan)
+}
+
+/// Adds an account state struct and its Pack impl to the program in the
+/// target folder
+pub fn add_state(config: &Configuration) -> CargoResult<()> {
+    let plan = plan_add_state(config)?;
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.apply()?;
+    println!("Added state {}", config.progname);
+    Ok(())
+}
+
+/// Plans the program sources generated from the spec, and what they keep
+/// of the handwritten code
+fn plan_generate(config: &Configuration) -> CargoResult<(Plan, ProgramSpec, Regenerated)> {
+    let spec = ProgramSpec::load(config.spec.as_deref().unwrap())?;
+    let mut plan = Plan::new(&config.target_dir);
+    let (sources, regenerated) = spec::generate(&config.target_dir, &spec, config.force)?;
+    plan_edits(&mut plan, sources);
+    Ok((plan, spec, regenerated))
+}
+
+/// Generates the sources of the program in the target folder from a spec,
+/// listing the handwritten code this keeps and warning about what it drops
+/// or breaks
+pub fn generate(config: &Configuration) -> CargoResult<()> {
+    let (plan, spec, regenerated) = plan_generate(config)?;
+    if !regenerated.removed.is_empty() {
+        println!("Warning: removing the handlers and tests of instructions the spec dropped");
+        for name in &regenerated.removed {
+            println!("  {}", name);
+        }
+    }
+    if !regenerated.carried.is_empty() {
+        println!("Keeping the errors and state items the spec does not declare");
+        for name in &regenerated.carried {
+            println!("  {}", name);
+        }
+    }
+    if !regenerated.conflicts.is_empty() {
+        println!("Warning: handler signatures change, update the bodies kept");
+        for (_, was, now) in &regenerated.conflicts {
+            println!("  {}\n  -> {}", was, now);
+        }
+    }
+    if config.dry_run {
+        plan.print();
+        return Ok(());
+    }
+    plan.apply()?;
+    let counts = format!(
+        "{} instructions, {} accounts and {} errors",
+        spec.instructions.len(),
+        spec.accounts.len(),
+        spec.errors.len()
+    );
+    match regenerated.kept.len() {
+        0 => println!("Generated {}, fill in the handlers and tests", counts),
+        kept => println!(
+            "Generated {}, keeping {} handler and test bodies",
+            counts, kept
+        ),
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -146,72 +691,655 @@ mod tests {
     use super::*;
     use cargo_toml::Manifest;
 
-    use crate::{
-        cli::ExecutionCommand,
-        utils::{build_program_manifest, project_template_as_manifest},
-    };
-    use std::fs::{remove_file, rename};
+    use crate::{cli::ExecutionCommand, descriptor::Archive};
+    use std::{fs::create_dir, path::PathBuf};
+    use tempfile::tempdir;
 
-    #[test]
-    fn test_create_program_pass() {
-        // Build the configuration file
-        let name = "foo";
-        let progm = build_program_manifest(name.to_string()).unwrap();
-        let projm = project_template_as_manifest().unwrap();
-        let mut base_dir = std::env::current_dir().unwrap();
-        base_dir.push("program");
-        let configuration = Configuration {
+    /// Builds a configuration rooted at `target_dir` without
+    /// depending on a local Solana install
+    fn test_configuration(name: &str, target_dir: PathBuf) -> Configuration {
+        Configuration {
             progname: name.to_string(),
             command: ExecutionCommand::Init,
-            init_manifest: None,
-            program_manifest_template: progm,
-            project_manifest_template: projm,
+            target_dir,
+            program_dir: "program".to_string(),
+            move_package: None,
+            workspace_deps: false,
+            force: false,
+            template: None,
+            user_templates: None,
+            vars: vec![],
+            interactive: false,
+            allow_hooks: false,
+            dry_run: false,
+            solana_version: "1.10.0".to_string(),
+            author: None,
+            license: None,
+            program_id: None,
+            fields: vec![],
+            accounts: vec![],
+            message: None,
+            before: None,
+            spec: None,
+        }
+    }
+
+    #[test]
+    fn test_create_program_pass() {
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let cwd = std::env::current_dir().unwrap();
+        let mut plan = Plan::new(base_dir.path());
+        assert!(plan_program(&configuration, base_dir.path(), &mut plan).is_ok());
+        assert!(plan.apply().is_ok());
+        assert!(base_dir.path().join("program/Cargo.toml").exists());
+        assert!(base_dir.path().join("program/src/lib.rs").exists());
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+    }
+
+    #[test]
+    fn test_create_program_template_pass() {
+        let base_dir = tempdir().unwrap();
+        let template = base_dir.path().join("house");
+        std::fs::create_dir_all(template.join("state")).unwrap();
+        std::fs::write(template.join("lib.rs"), "//! PROGNAME\npub mod state;\n").unwrap();
+        std::fs::write(template.join("state/mod.rs"), "").unwrap();
+        std::fs::write(template.join("state/account.rs"), "").unwrap();
+        let mut configuration = test_configuration("foo-bar", base_dir.path().to_path_buf());
+        configuration.template = Some(TemplateSource::Folder(template));
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        assert!(plan.apply().is_ok());
+        let src_dir = base_dir.path().join("program/src");
+        assert_eq!(
+            std::fs::read_to_string(src_dir.join("lib.rs")).unwrap(),
+            "//! foo_bar\npub mod state;\n"
+        );
+        assert!(src_dir.join("state/mod.rs").exists());
+        assert!(!src_dir.join("entry_point.rs").exists());
+    }
+
+    #[test]
+    fn confirm_hooks_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let planned = || {
+            let mut plan = Plan::new(base_dir.path());
+            plan.hook(
+                base_dir.path().to_path_buf(),
+                vec!["cargo".into(), "fmt".into()],
+            );
+            plan
         };
-        assert!(create_program(&configuration).is_ok());
-        println!("Removing {}", base_dir.display());
-        std::fs::remove_dir_all(base_dir).unwrap();
+        // Skipped unless confirmed or allowed
+        for answer in [None, Some("\n"), Some("n\n")] {
+            let mut plan = planned();
+            let mut input = answer.map(str::as_bytes);
+            let input = input.as_mut().map(|i| i as &mut dyn BufRead);
+            confirm_hooks(&configuration, &mut plan, input).unwrap();
+            assert!(plan.hooks.is_empty());
+        }
+        let mut plan = planned();
+        confirm_hooks(&configuration, &mut plan, Some(&mut "y\n".as_bytes())).unwrap();
+        assert_eq!(plan.hooks.len(), 1);
+        configuration.allow_hooks = true;
+        let mut plan = planned();
+        confirm_hooks(&configuration, &mut plan, None).unwrap();
+        assert_eq!(plan.hooks.len(), 1);
+        // Fetched templates only run them when allowed
+        configuration.template = Some(TemplateSource::Archive(Archive {
+            source: "starters#v3".to_string(),
+            revision: "0a1b".to_string(),
+            files: vec![],
+        }));
+        let mut plan = planned();
+        confirm_hooks(&configuration, &mut plan, None).unwrap();
+        assert_eq!(plan.hooks.len(), 1);
+        configuration.allow_hooks = false;
+        let mut plan = planned();
+        confirm_hooks(&configuration, &mut plan, Some(&mut "y\n".as_bytes())).unwrap();
+        assert!(plan.hooks.is_empty());
+    }
+
+    #[test]
+    fn test_create_program_descriptor_pass() {
+        let base_dir = tempdir().unwrap();
+        let template = base_dir.path().join("house");
+        create_dir(&template).unwrap();
+        std::fs::write(
+            template.join("template.toml"),
+            "[variables.account]\ndefault = \"Vault\"\n\n\
+            [[files]]\nsource = \"lib.rs\"\ndest = \"src/lib.rs\"\n\n\
+            [[files]]\nsource = \"keypair.bin\"\ndest = \"keys/{{ name }}.bin\"\nbinary = true\n\n\
+            [hooks]\npost = [\"cp src/lib.rs src/copy.rs\"]\n",
+        )
+        .unwrap();
+        std::fs::write(template.join("lib.rs"), "pub struct {{ account }};\n").unwrap();
+        std::fs::write(template.join("keypair.bin"), [0u8, 0xff]).unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.template = Some(TemplateSource::Folder(template));
+        configuration.vars = vec![("account".to_string(), "Escrow".to_string())];
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        assert_eq!(plan.hooks.len(), 1);
+        assert!(plan.apply().is_ok());
+        let program_dir = base_dir.path().join("program");
+        // Without a manifest in the template the built-in one is used
+        assert!(std::fs::read_to_string(program_dir.join("Cargo.toml"))
+            .unwrap()
+            .contains("solana-program = \"1.10.0\""));
+        assert_eq!(
+            std::fs::read_to_string(program_dir.join("src/copy.rs")).unwrap(),
+            "pub struct Escrow;\n"
+        );
+        assert_eq!(
+            std::fs::read(program_dir.join("keys/foo.bin")).unwrap(),
+            vec![0u8, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_create_project_builtin_template_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.template = builtin_template("escrow");
+        assert!(create_project_program(&configuration).is_ok());
+        let src_dir = base_dir.path().join("foo/program/src");
+        assert!(std::fs::read_to_string(src_dir.join("state.rs"))
+            .unwrap()
+            .contains("pub struct EscrowState"));
+        assert!(std::fs::read_to_string(src_dir.join("instruction.rs"))
+            .unwrap()
+            .contains("    InitializeEscrow(u64, u64),\n"));
+        configuration.progname = "bar".to_string();
+        configuration.template = builtin_template("minimal");
+        assert!(create_project_program(&configuration).is_ok());
+        let src_dir = base_dir.path().join("bar/program/src");
+        assert!(src_dir.join("lib.rs").exists());
+        assert!(!src_dir.join("process.rs").exists());
+    }
+
+    #[test]
+    fn test_create_program_exists_fail() {
+        let base_dir = tempdir().unwrap();
+        create_dir(base_dir.path().join("program")).unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let mut plan = Plan::new(base_dir.path());
+        assert!(matches!(
+            plan_program(&configuration, base_dir.path(), &mut plan),
+            Err(ProgramError::ProgramExistsError(_))
+        ));
+        assert!(base_dir.path().join("program").exists());
     }
 
     #[test]
     fn test_create_program_update_workspace_pass() {
-        let name = "foo";
-        let progm = build_program_manifest(name.to_string()).unwrap();
-        let projm = project_template_as_manifest().unwrap();
-        let mut base_dir = std::env::current_dir().unwrap();
-        base_dir.push("program");
-        let exist_cargo = Some(Manifest::from_path("./Cargo.toml").unwrap());
-        rename("./Cargo.toml", "./Cargo.bak").unwrap();
-
-        let mut configuration = Configuration {
-            progname: name.to_string(),
-            command: ExecutionCommand::Init,
-            init_manifest: exist_cargo,
-            program_manifest_template: progm,
-            project_manifest_template: projm,
-        };
+        let base_dir = tempdir().unwrap();
+        let manifest_path = base_dir.path().join("Cargo.toml");
+        std::fs::copy("./Cargo.toml", &manifest_path).unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
         assert!(create_program_update_workspace(&mut configuration).is_ok());
-        println!("Removing {}", base_dir.display());
-        std::fs::remove_dir_all(base_dir).unwrap();
-        println!("Removing temporary Cargo update");
-        remove_file("./Cargo.toml").unwrap();
-        rename("./Cargo.bak", "./Cargo.toml").unwrap();
+        assert!(base_dir.path().join("program/src/lib.rs").exists());
+        assert!(!base_dir.path().join("CargoSolana.bak").exists());
+        let updated = Manifest::from_path(&manifest_path).unwrap();
+        assert_eq!(updated.workspace.unwrap().members, vec!["program"]);
+        // Original package content is untouched
+        let original = std::fs::read_to_string("./Cargo.toml").unwrap();
+        let updated = std::fs::read_to_string(&manifest_path).unwrap();
+        assert!(updated.starts_with(&original));
     }
+
     #[test]
     fn test_create_project_pass() {
-        let name = "foo-bar";
-        let progm = build_program_manifest(name.to_string()).unwrap();
-        let projm = project_template_as_manifest().unwrap();
-        let mut base_dir = std::env::current_dir().unwrap();
-        base_dir.push("foo-bar");
-        let configuration = Configuration {
-            progname: name.to_string(),
-            command: ExecutionCommand::Init,
-            init_manifest: None,
-            program_manifest_template: progm,
-            project_manifest_template: projm,
-        };
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo-bar", base_dir.path().join("onchain"));
+        assert!(create_project_program(&configuration).is_ok());
+        let project_dir = base_dir.path().join("onchain/foo-bar");
+        assert!(project_dir.join("Cargo.toml").exists());
+        assert!(project_dir.join("program/src/entry_point.rs").exists());
+        let project = Manifest::from_path(project_dir.join("Cargo.toml")).unwrap();
+        assert_eq!(project.workspace.unwrap().members, vec!["program"]);
+        let project = std::fs::read_to_string(project_dir.join("Cargo.toml")).unwrap();
+        assert!(project.contains("resolver = \"2\"\n"));
+    }
+
+    #[test]
+    fn test_dry_run_pass() {
+        let base_dir = tempdir().unwrap();
+        let manifest_path = base_dir.path().join("Cargo.toml");
+        std::fs::copy("./Cargo.toml", &manifest_path).unwrap();
+        let original = std::fs::read_to_string(&manifest_path).unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.dry_run = true;
+        let plan = plan_program_update_workspace(&configuration).unwrap();
+        assert_eq!(plan.actions.len(), 10);
+        assert!(create_program_update_workspace(&mut configuration).is_ok());
+        assert!(!base_dir.path().join("program").exists());
+        assert_eq!(std::fs::read_to_string(&manifest_path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_multiple_programs_pass() {
+        let base_dir = tempdir().unwrap();
+        let manifest_path = base_dir.path().join("Cargo.toml");
+        std::fs::write(&manifest_path, "[workspace]\nmembers = []\n").unwrap();
+        for name in ["foo", "bar"] {
+            let mut configuration = test_configuration(name, base_dir.path().to_path_buf());
+            configuration.program_dir = format!("programs/{}", name);
+            assert!(create_program_update_workspace(&mut configuration).is_ok());
+        }
+        assert!(base_dir.path().join("programs/foo/src/lib.rs").exists());
+        assert!(base_dir.path().join("programs/bar/src/lib.rs").exists());
+        let updated = Manifest::from_path(&manifest_path).unwrap();
+        assert_eq!(
+            updated.workspace.unwrap().members,
+            vec!["programs/foo", "programs/bar"]
+        );
+    }
+
+    #[test]
+    fn test_program_glob_member_pass() {
+        let base_dir = tempdir().unwrap();
+        let manifest_path = base_dir.path().join("Cargo.toml");
+        std::fs::write(&manifest_path, "[workspace]\nmembers = [\"programs/*\"]\n").unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.program_dir = "programs/foo".to_string();
+        assert!(create_program_update_workspace(&mut configuration).is_ok());
+        let updated = Manifest::from_path(&manifest_path).unwrap();
+        assert_eq!(updated.workspace.unwrap().members, vec!["programs/*"]);
+    }
+
+    #[test]
+    fn test_move_package_pass() {
+        let base_dir = tempdir().unwrap();
+        let manifest_path = base_dir.path().join("Cargo.toml");
+        std::fs::write(
+            &manifest_path,
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        create_dir(base_dir.path().join("src")).unwrap();
+        std::fs::write(base_dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.move_package = Some("app".to_string());
+        assert!(create_program_update_workspace(&mut configuration).is_ok());
+        assert!(base_dir.path().join("app/src/main.rs").exists());
+        assert!(!base_dir.path().join("src").exists());
+        let member = Manifest::from_path(base_dir.path().join("app/Cargo.toml")).unwrap();
+        assert_eq!(member.package.unwrap().name, "app");
+        let updated = Manifest::from_path(&manifest_path).unwrap();
+        assert!(updated.package.is_none());
+        assert_eq!(updated.workspace.unwrap().members, vec!["app", "program"]);
+    }
+
+    #[test]
+    fn test_init_without_manifest_pass() {
+        let base_dir = tempdir().unwrap();
+        let root = base_dir.path().join("onchain");
+        let mut configuration = test_configuration("foo", root.clone());
+        assert!(create_program_update_workspace(&mut configuration).is_ok());
+        assert!(root.join("program/src/lib.rs").exists());
+        let created = Manifest::from_path(root.join("Cargo.toml")).unwrap();
+        assert!(created.package.is_none());
+        assert_eq!(created.workspace.unwrap().members, vec!["program"]);
+        // As when moving a root package, the edition 2021 resolver
+        let created = std::fs::read_to_string(root.join("Cargo.toml")).unwrap();
+        assert!(created.contains("resolver = \"2\"\n"));
+    }
+
+    #[test]
+    fn test_create_project_workspace_deps_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.workspace_deps = true;
         assert!(create_project_program(&configuration).is_ok());
-        println!("Removing {}", base_dir.display());
-        std::fs::remove_dir_all(base_dir).unwrap();
+        let project = std::fs::read_to_string(base_dir.path().join("foo/Cargo.toml")).unwrap();
+        let project = project.parse::<toml_edit::DocumentMut>().unwrap();
+        let shared = project["workspace"]["dependencies"].as_table().unwrap();
+        assert_eq!(shared.len(), SHARED_DEPENDENCIES.len());
+        assert_eq!(shared["solana-sdk"].as_str(), Some("1.10.0"));
+        let program =
+            std::fs::read_to_string(base_dir.path().join("foo/program/Cargo.toml")).unwrap();
+        let program = program.parse::<toml_edit::DocumentMut>().unwrap();
+        assert_eq!(
+            program["dependencies"]["borsh"]["workspace"].as_bool(),
+            Some(true)
+        );
+        assert_eq!(
+            program["dev-dependencies"]["assert_matches"].as_str(),
+            Some("1.5.0")
+        );
+    }
+
+    /// Writes a workspace with a program inheriting solana-program from the
+    /// root and a client pinning its own solana-sdk
+    fn upgrade_workspace(root: &Path) {
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"programs/*\", \"client\"]\n\n\
+            [workspace.dependencies]\nsolana-program = \"1.14.17\" # pinned\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("programs/foo")).unwrap();
+        std::fs::write(
+            root.join("programs/foo/Cargo.toml"),
+            "[package]\nname = \"foo\"\n\n[dependencies]\n\
+            solana-program = { workspace = true }\n\n[dev-dependencies]\n\
+            solana-program-test = \"=1.14.17\"\n",
+        )
+        .unwrap();
+        create_dir(root.join("client")).unwrap();
+        std::fs::write(
+            root.join("client/Cargo.toml"),
+            "[package]\nname = \"client\"\n\n[dependencies]\n\
+            serde = \"1\"\nsolana-sdk = { version = \"~1.14\", default-features = false }\n",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_pass() {
+        let base_dir = tempdir().unwrap();
+        upgrade_workspace(base_dir.path());
+        let mut configuration = test_configuration("", base_dir.path().to_path_buf());
+        configuration.solana_version = "1.16.0".to_string();
+        let (plan, changed) = plan_upgrade(&configuration).unwrap();
+        assert_eq!(plan.actions.len(), 3);
+        assert_eq!(changed.len(), 3);
+        assert!(upgrade_programs(&configuration).is_ok());
+        let read = |p: &str| std::fs::read_to_string(base_dir.path().join(p)).unwrap();
+        assert!(read("Cargo.toml").ends_with("solana-program = \"1.16.0\" # pinned\n"));
+        assert!(read("programs/foo/Cargo.toml").contains("solana-program = { workspace = true }"));
+        assert!(read("programs/foo/Cargo.toml").contains("solana-program-test = \"=1.16.0\""));
+        assert!(read("client/Cargo.toml").contains(
+            "serde = \"1\"\nsolana-sdk = { version = \"~1.16.0\", default-features = false }"
+        ));
+        // Nothing left to change
+        let (plan, _) = plan_upgrade(&configuration).unwrap();
+        assert!(plan.actions.is_empty());
+    }
+
+    #[test]
+    fn test_upgrade_incompatible_fail() {
+        let base_dir = tempdir().unwrap();
+        upgrade_workspace(base_dir.path());
+        let original = std::fs::read_to_string(base_dir.path().join("Cargo.toml")).unwrap();
+        let mut configuration = test_configuration("", base_dir.path().to_path_buf());
+        configuration.solana_version = "2.0.3".to_string();
+        assert!(matches!(
+            upgrade_programs(&configuration),
+            Err(ProgramError::IncompatibleUpgrade(_))
+        ));
+        assert_eq!(
+            std::fs::read_to_string(base_dir.path().join("Cargo.toml")).unwrap(),
+            original
+        );
+        // A dry run previews it with a warning
+        configuration.dry_run = true;
+        assert!(upgrade_programs(&configuration).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(base_dir.path().join("Cargo.toml")).unwrap(),
+            original
+        );
+        configuration.dry_run = false;
+        configuration.force = true;
+        assert!(upgrade_programs(&configuration).is_ok());
+        assert!(std::fs::read_to_string(base_dir.path().join("Cargo.toml"))
+            .unwrap()
+            .contains("\"2.0.3\""));
+    }
+
+    #[test]
+    fn template_list_pass() {
+        let user_dir = tempdir().unwrap();
+        let house = user_dir.path().join("house");
+        create_dir(&house).unwrap();
+        std::fs::write(
+            house.join("template.toml"),
+            "[template]\nname = \"house\"\ndescription = \"Our programs\"\n",
+        )
+        .unwrap();
+        create_dir(user_dir.path().join("escrow")).unwrap();
+        let list = template_list(Some(user_dir.path())).unwrap();
+        assert!(list.starts_with("Built-in templates:\n  minimal "));
+        assert!(list.contains("\n  counter "));
+        assert!(list.contains("\n  house           Our programs\n"));
+        assert!(list.contains("\n  escrow          (Template folder "));
+        assert!(list.contains("(hidden by the built-in template)\n"));
+        assert!(!template_list(None).unwrap().contains("User templates"));
+    }
+
+    #[test]
+    fn template_summary_pass() {
+        let summary = template_summary(&builtin_template("escrow").unwrap()).unwrap();
+        assert!(summary.starts_with("escrow (built-in template)\n"));
+        assert!(summary.contains("\nFiles:\n  prog.cargo.toml -> Cargo.toml\n"));
+        assert!(summary.contains("\nVariables:\n  instructions"));
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "").unwrap();
+        let summary = template_summary(&TemplateSource::Folder(dir.path().to_path_buf())).unwrap();
+        assert!(summary.ends_with("\nFiles:\n  lib.rs -> src/lib.rs\n"));
+    }
+
+    #[test]
+    fn test_render_template_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("vault", base_dir.path().to_path_buf());
+        configuration.command = ExecutionCommand::TemplateRender;
+        configuration.program_dir = "out".to_string();
+        configuration.template = builtin_template("vault");
+        render_template(&configuration).unwrap();
+        let out = base_dir.path().join("out");
+        assert!(out.join("src/state.rs").exists());
+        let manifest = Manifest::from_path(out.join("Cargo.toml")).unwrap();
+        assert_eq!(manifest.package.unwrap().name, "vault");
+        // No workspace is created or updated
+        assert!(!base_dir.path().join("Cargo.toml").exists());
+        assert!(matches!(
+            render_template(&configuration),
+            Err(ProgramError::OutputExistsError(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_program_archive_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        configuration.template = Some(TemplateSource::Archive(Archive {
+            source: "../starters.git#v3".to_string(),
+            revision: "9fceb02d0ae598e95dc970b74767f19372d61af8".to_string(),
+            files: vec![("lib.rs".to_string(), b"//! PROGNAME\n".to_vec())],
+        }));
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let read = |path: &str| std::fs::read_to_string(base_dir.path().join(path)).unwrap();
+        assert_eq!(read("program/src/lib.rs"), "//! foo\n");
+        let manifest = read("program/Cargo.toml").parse::<DocumentMut>().unwrap();
+        let record = &manifest["package"]["metadata"]["cargo-solana"];
+        assert_eq!(record["template"].as_str(), Some("../starters.git#v3"));
+        assert_eq!(
+            record["revision"].as_str(),
+            Some("9fceb02d0ae598e95dc970b74767f19372d61af8")
+        );
+    }
+
+    #[test]
+    fn test_save_template_pass() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("my-vault", base_dir.path().to_path_buf());
+        configuration.template = builtin_template("vault");
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        // Save the generated program then generate another one from it
+        let user_dir = base_dir.path().join("templates");
+        configuration.command = ExecutionCommand::TemplateSave;
+        configuration.target_dir = base_dir.path().join("program");
+        configuration.progname = "house".to_string();
+        configuration.user_templates = Some(user_dir.clone());
+        save_template(&configuration).unwrap();
+        let saved = std::fs::read_to_string(user_dir.join("house/src/entry_point.rs")).unwrap();
+        assert!(saved.contains("const NAME: &str = \"{{ crate_name }}\";"));
+        assert!(!saved.contains("my_vault"));
+        assert!(matches!(
+            save_template(&configuration),
+            Err(ProgramError::TemplateExistsError(_))
+        ));
+        let mut configuration = test_configuration("other", base_dir.path().to_path_buf());
+        configuration.program_dir = "other".to_string();
+        configuration.template = Some(TemplateSource::Folder(user_dir.join("house")));
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let read = |path: &str| std::fs::read_to_string(base_dir.path().join(path)).unwrap();
+        assert_eq!(
+            read("other/src/entry_point.rs"),
+            read("program/src/entry_point.rs").replace("my_vault", "other")
+        );
+        assert_eq!(read("other/src/state.rs"), read("program/src/state.rs"));
+        let manifest = Manifest::from_path(base_dir.path().join("other/Cargo.toml")).unwrap();
+        assert_eq!(manifest.package.unwrap().name, "other");
+    }
+
+    #[test]
+    fn test_save_template_fail() {
+        let base_dir = tempdir().unwrap();
+        let mut configuration = test_configuration("house", base_dir.path().to_path_buf());
+        configuration.command = ExecutionCommand::TemplateSave;
+        assert!(matches!(
+            save_template(&configuration),
+            Err(ProgramError::NoHomeFound)
+        ));
+        configuration.user_templates = Some(base_dir.path().join("templates"));
+        for name in ["escrow", "a/b", "..", ".hidden"] {
+            configuration.progname = name.to_string();
+            assert!(matches!(
+                save_template(&configuration),
+                Err(ProgramError::InvalidTemplateName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn incompatible_upgrade_pass() {
+        assert!(!incompatible_upgrade("1.14.17", "1.16.0"));
+        assert!(!incompatible_upgrade("~1.14", "~1.14.5"));
+        assert!(incompatible_upgrade("1.18.26", "2.0.3"));
+        assert!(incompatible_upgrade("=1.16.0", "=1.14.17"));
+    }
+
+    #[test]
+    fn test_add_instruction_pass() {
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let program_dir = base_dir.path().join("program");
+        let mut configuration = test_configuration("Transfer", program_dir.clone());
+        configuration.command = ExecutionCommand::AddInstruction;
+        configuration.fields = vec![scaffold::parse_arg("amount:u64").unwrap()];
+        configuration.accounts = vec![scaffold::parse_account("source:mut:signer").unwrap()];
+        let tree = plan_add_instruction(&configuration).unwrap().tree();
+        for file in ["entry_point.rs", "instruction.rs", "process.rs"] {
+            assert!(tree.contains(&format!("{} (modified)", file)));
+        }
+        add_instruction(&configuration).unwrap();
+        let process = std::fs::read_to_string(program_dir.join("src/process.rs")).unwrap();
+        assert!(process.contains("ProgramInstruction::Transfer(amount) => transfer("));
+        assert!(matches!(
+            add_instruction(&configuration),
+            Err(ProgramError::SourceError(..))
+        ));
+    }
+
+    #[test]
+    fn test_add_error_pass() {
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let program_dir = base_dir.path().join("program");
+        let mut configuration = test_configuration("NotVaultOwner", program_dir.clone());
+        configuration.command = ExecutionCommand::AddError;
+        let (_, renumbered) = plan_add_error(&configuration).unwrap();
+        assert!(renumbered.is_empty());
+        add_error(&configuration).unwrap();
+        let errors = std::fs::read_to_string(program_dir.join("src/error.rs")).unwrap();
+        assert!(errors.contains("    #[error(\"Not vault owner\")]\n    NotVaultOwner,\n"));
+        configuration.progname = "BadSeed".to_string();
+        configuration.before = Some("DataVersionMismatchError".to_string());
+        let (_, renumbered) = plan_add_error(&configuration).unwrap();
+        assert_eq!(
+            renumbered,
+            vec![
+                ("DataVersionMismatchError".to_string(), 1, 2),
+                ("NotVaultOwner".to_string(), 2, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_state_pass() {
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let program_dir = base_dir.path().join("program");
+        let mut configuration = test_configuration("Vault", program_dir.clone());
+        configuration.command = ExecutionCommand::AddState;
+        configuration.fields = vec![
+            scaffold::parse_field("owner:Pubkey").unwrap(),
+            scaffold::parse_field("balance:u64").unwrap(),
+        ];
+        let tree = plan_add_state(&configuration).unwrap().tree();
+        assert!(tree.contains("state.rs (modified)"));
+        add_state(&configuration).unwrap();
+        let state = std::fs::read_to_string(program_dir.join("src/state.rs")).unwrap();
+        assert!(state.contains("pub struct VaultState {"));
+        assert!(matches!(
+            add_state(&configuration),
+            Err(ProgramError::SourceError(..))
+        ));
+    }
+
+    #[test]
+    fn test_generate_pass() {
+        let base_dir = tempdir().unwrap();
+        let configuration = test_configuration("foo", base_dir.path().to_path_buf());
+        let mut plan = Plan::new(base_dir.path());
+        plan_program(&configuration, base_dir.path(), &mut plan).unwrap();
+        plan.apply().unwrap();
+        let program_dir = base_dir.path().join("program");
+        let spec_path = base_dir.path().join("foo.toml");
+        std::fs::write(
+            &spec_path,
+            "[accounts.Vault]\nfields = [\"owner:Pubkey\"]\n\n[instructions.Open]\naccounts = [\"owner:signer\", \"vault:mut\"]\n",
+        )
+        .unwrap();
+        let mut configuration = test_configuration("", program_dir.clone());
+        configuration.command = ExecutionCommand::Generate;
+        configuration.spec = Some(spec_path);
+        // The handlers of the template are written by hand
+        assert!(matches!(
+            plan_generate(&configuration),
+            Err(ProgramError::HandlerRemoved(_))
+        ));
+        configuration.force = true;
+        let (plan, ..) = plan_generate(&configuration).unwrap();
+        assert!(plan.tree().contains("process.rs (modified)"));
+        generate(&configuration).unwrap();
+        let process = std::fs::read_to_string(program_dir.join("src/process.rs")).unwrap();
+        assert!(process.contains("ProgramInstruction::Open"));
+        let state = std::fs::read_to_string(program_dir.join("src/state.rs")).unwrap();
+        assert!(state.contains("pub struct VaultState {"));
+        configuration.spec = Some(base_dir.path().join("foo.json"));
+        assert!(matches!(
+            generate(&configuration),
+            Err(ProgramError::SpecFormatError(..))
+        ));
     }
 }
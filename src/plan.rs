@@ -0,0 +1,306 @@
+//! Planned file system changes
+//!
+//! Operations first describe everything they intend to write as a `Plan`
+//! which can then either be printed (dry run) or applied to disk.
+
+use crate::{
+    error::{CargoResult, ProgramError},
+    journal,
+};
+use similar::TextDiff;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A single planned change
+#[derive(Debug)]
+pub enum PlanAction {
+    /// Create a new directory
+    CreateDir(PathBuf),
+    /// Create a new file with contents
+    CreateFile { path: PathBuf, contents: String },
+    /// Create a new file with binary contents
+    CreateBinary { path: PathBuf, contents: Vec<u8> },
+    /// Replace the contents of an existing file
+    UpdateFile {
+        path: PathBuf,
+        original: String,
+        contents: String,
+    },
+    /// Relocate an existing file or folder
+    Move { from: PathBuf, to: PathBuf },
+}
+
+impl PlanAction {
+    /// The path this action targets
+    pub fn path(&self) -> &Path {
+        match self {
+            PlanAction::CreateDir(path)
+            | PlanAction::CreateFile { path, .. }
+            | PlanAction::CreateBinary { path, .. }
+            | PlanAction::UpdateFile { path, .. }
+            | PlanAction::Move { to: path, .. } => path,
+        }
+    }
+}
+
+/// A hook's arguments as a command line, quoting those with spaces or quotes
+pub fn command_line(arguments: &[String]) -> String {
+    arguments
+        .iter()
+        .map(|a| {
+            match a.is_empty() || a.contains(|c: char| c.is_whitespace() || "'\"\\".contains(c)) {
+                true => format!("{:?}", a),
+                false => a.clone(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Ordered collection of changes rooted at a directory
+#[derive(Debug)]
+pub struct Plan {
+    pub root: PathBuf,
+    pub actions: Vec<PlanAction>,
+    /// Commands run in a folder once all changes are applied, as arguments
+    pub hooks: Vec<(PathBuf, Vec<String>)>,
+}
+
+impl Plan {
+    /// Start an empty plan for changes under `root`
+    pub fn new(root: &Path) -> Self {
+        Plan {
+            root: root.to_path_buf(),
+            actions: vec![],
+            hooks: vec![],
+        }
+    }
+
+    /// Plan a new directory
+    pub fn create_dir(&mut self, path: PathBuf) {
+        self.actions.push(PlanAction::CreateDir(path));
+    }
+
+    /// True if the plan already creates the directory `path`
+    pub fn creates_dir(&self, path: &Path) -> bool {
+        self.actions
+            .iter()
+            .any(|a| matches!(a, PlanAction::CreateDir(d) if d == path))
+    }
+
+    /// Plan a new file
+    pub fn create_file(&mut self, path: PathBuf, contents: String) {
+        self.actions.push(PlanAction::CreateFile { path, contents });
+    }
+
+    /// Plan a new binary file
+    pub fn create_binary(&mut self, path: PathBuf, contents: Vec<u8>) {
+        self.actions
+            .push(PlanAction::CreateBinary { path, contents });
+    }
+
+    /// Plan a command to run in `dir` after the changes are applied
+    pub fn hook(&mut self, dir: PathBuf, arguments: Vec<String>) {
+        self.hooks.push((dir, arguments));
+    }
+
+    /// Plan a rewrite of an existing file
+    pub fn update_file(&mut self, path: PathBuf, original: String, contents: String) {
+        self.actions.push(PlanAction::UpdateFile {
+            path,
+            original,
+            contents,
+        });
+    }
+
+    /// Plan moving an existing file or folder
+    pub fn move_path(&mut self, from: PathBuf, to: PathBuf) {
+        self.actions.push(PlanAction::Move { from, to });
+    }
+
+    /// Path relative to the plan root for display
+    fn display_path<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Renders the planned file tree, indenting by depth below the root
+    pub fn tree(&self) -> String {
+        let mut entries = self
+            .actions
+            .iter()
+            .map(|a| (self.display_path(a.path()), a))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut out = format!("{}\n", self.root.display());
+        for (rel, action) in entries {
+            let depth = rel.components().count();
+            let name = rel.file_name().unwrap_or_default().to_string_lossy();
+            let suffix = match action {
+                PlanAction::CreateDir(_) => "/".to_string(),
+                PlanAction::CreateFile { .. } => String::new(),
+                PlanAction::CreateBinary { .. } => " (binary)".to_string(),
+                PlanAction::UpdateFile { .. } => " (modified)".to_string(),
+                PlanAction::Move { from, .. } => {
+                    format!(" (moved from {})", self.display_path(from).display())
+                }
+            };
+            out.push_str(&format!("{}{}{}\n", "    ".repeat(depth), name, suffix));
+        }
+        out
+    }
+
+    /// Print what would be done without touching disk. Shows the file tree,
+    /// any generated Cargo.toml in full and a unified diff of updated files
+    pub fn print(&self) {
+        println!("Dry run, no changes written");
+        println!();
+        print!("{}", self.tree());
+        for action in &self.actions {
+            match action {
+                PlanAction::CreateFile { path, contents }
+                    if path.file_name().is_some_and(|f| f == "Cargo.toml") =>
+                {
+                    println!();
+                    println!("=== {} ===", self.display_path(path).display());
+                    print!("{}", contents);
+                }
+                PlanAction::UpdateFile {
+                    path,
+                    original,
+                    contents,
+                } => {
+                    let name = self.display_path(path).display().to_string();
+                    println!();
+                    print!(
+                        "{}",
+                        TextDiff::from_lines(original, contents)
+                            .unified_diff()
+                            .header(&format!("a/{}", name), &format!("b/{}", name))
+                    );
+                }
+                _ => {}
+            }
+        }
+        for (dir, arguments) in &self.hooks {
+            println!();
+            println!(
+                "Would run `{}` in {}",
+                command_line(arguments),
+                dir.display()
+            );
+        }
+        if !self.hooks.is_empty() {
+            println!("Hooks run with --allow-hooks or once confirmed");
+        }
+    }
+
+    /// Runs the hooks in order, stopping at the first that fails
+    pub fn run_hooks(&self) -> CargoResult<()> {
+        for (dir, arguments) in &self.hooks {
+            let command = command_line(arguments);
+            println!("  Running `{}`", command);
+            let status = Command::new(&arguments[0])
+                .args(&arguments[1..])
+                .current_dir(dir)
+                .status()
+                .map_err(|_| ProgramError::HookFailed(command.clone()))?;
+            if !status.success() {
+                return Err(ProgramError::HookFailed(command));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the planned changes as a single transaction, restoring
+    /// the tree if any step fails. Hooks run once the changes are in
+    /// place and a failing one undoes them too
+    pub fn apply(&self) -> CargoResult<()> {
+        journal::apply(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn plan_tree_pass() {
+        let root = PathBuf::from("ws");
+        let mut plan = Plan::new(&root);
+        plan.create_dir(root.join("program"));
+        plan.create_file(root.join("program/Cargo.toml"), String::new());
+        plan.update_file(root.join("Cargo.toml"), String::new(), String::new());
+        plan.create_binary(root.join("program/logo.png"), vec![0x89]);
+        assert_eq!(
+            plan.tree(),
+            "ws\n    Cargo.toml (modified)\n    program/\n        Cargo.toml\n        logo.png (binary)\n"
+        );
+    }
+
+    #[test]
+    fn plan_apply_pass() {
+        let root = tempdir().unwrap();
+        std::fs::write(root.path().join("Cargo.toml"), "old").unwrap();
+        let mut plan = Plan::new(root.path());
+        plan.create_dir(root.path().join("program"));
+        plan.create_file(root.path().join("program/lib.rs"), "lib".to_string());
+        plan.update_file(
+            root.path().join("Cargo.toml"),
+            "old".to_string(),
+            "new".to_string(),
+        );
+        plan.apply().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("program/lib.rs")).unwrap(),
+            "lib"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("Cargo.toml")).unwrap(),
+            "new"
+        );
+        assert!(!root.path().join("CargoSolana.bak").exists());
+    }
+
+    #[test]
+    fn plan_hooks_pass() {
+        let root = tempdir().unwrap();
+        let mut plan = Plan::new(root.path());
+        plan.create_binary(root.path().join("data.bin"), vec![0, 159, 146, 150]);
+        plan.hook(
+            root.path().to_path_buf(),
+            vec!["sh".into(), "-c".into(), "cp data.bin 'copy of.bin'".into()],
+        );
+        plan.apply().unwrap();
+        assert_eq!(
+            std::fs::read(root.path().join("copy of.bin")).unwrap(),
+            vec![0, 159, 146, 150]
+        );
+        // A failing hook undoes the changes, with what earlier hooks wrote
+        let mut plan = Plan::new(root.path());
+        let program = root.path().join("program");
+        plan.create_dir(program.clone());
+        plan.create_file(program.join("lib.rs"), "lib".to_string());
+        std::fs::write(root.path().join("Cargo.toml"), "old").unwrap();
+        plan.update_file(
+            root.path().join("Cargo.toml"),
+            "old".to_string(),
+            "new".to_string(),
+        );
+        plan.hook(program.clone(), vec!["touch".into(), "notes.txt".into()]);
+        plan.hook(program.clone(), vec!["false".into()]);
+        assert!(matches!(plan.apply(), Err(ProgramError::HookFailed(_))));
+        assert!(!program.exists());
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("Cargo.toml")).unwrap(),
+            "old"
+        );
+        assert_eq!(command_line(&plan.hooks[1].1), "false");
+        assert_eq!(
+            command_line(&["sh".into(), "-c".into(), "echo 'a b'".into()]),
+            "sh -c \"echo 'a b'\""
+        );
+    }
+}
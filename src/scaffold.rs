@@ -0,0 +1,2138 @@
+//! Program code scaffolding
+//!
+//! `cargo solana add` extends the sources of a generated program in place.
+//! An instruction is added to the `ProgramInstruction` enum and its
+//! `unpack` match, dispatched from `process` to a new handler and covered
+//! by a new test.
+
+use crate::{
+    edit::{names_in_scope, use_group, use_paths, SourceFile},
+    error::{CargoResult, ProgramError},
+};
+use heck::{ToShoutySnakeCase, ToSnakeCase, ToUpperCamelCase};
+use proc_macro2::LineColumn;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use syn::{
+    spanned::Spanned, visit::Visit, Arm, ExprMatch, FnArg, Item, ItemFn, ItemMod, Pat, Type,
+};
+
+/// Instruction enum of generated programs
+pub const INSTRUCTION_ENUM: &str = "ProgramInstruction";
+/// Source holding the instruction enum and its `unpack`
+pub const INSTRUCTION_SOURCE: &str = "src/instruction.rs";
+/// Source holding the `process` dispatch and the handlers
+pub const PROCESS_SOURCE: &str = "src/process.rs";
+/// Custom error enum of generated programs
+pub const ERROR_ENUM: &str = "CustomProgramError";
+/// Source holding the custom error enum and its `print`
+pub const ERROR_SOURCE: &str = "src/error.rs";
+/// Source holding the account state structs
+pub const STATE_SOURCE: &str = "src/state.rs";
+/// Doc comment opening a state source
+pub const STATE_DOC: &str = "//! @brief Program account state management\n";
+/// Crate root declaring the program modules
+pub const LIB_SOURCE: &str = "src/lib.rs";
+/// Suffix of state struct names
+const STATE_SUFFIX: &str = "State";
+/// Fields every state starts with, the init/version header
+const STATE_HEADER: [(&str, &str); 2] = [("is_initialized", "bool"), ("data_version", "u8")];
+/// Source holding the program tests
+pub const TEST_SOURCE: &str = "src/entry_point.rs";
+/// Width rustfmt keeps lines to, longer inserted code is wrapped
+const MAX_WIDTH: usize = 100;
+/// Width rustfmt keeps the arguments of a call or macro on one line to
+const CALL_WIDTH: usize = 60;
+/// Types known by their path, imported where used
+const KNOWN_TYPES: [(&str, &str); 1] = [("Pubkey", "solana_program::pubkey::Pubkey")];
+
+/// An account an instruction takes, `name[:mut][:signer]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSpec {
+    pub name: String,
+    pub writable: bool,
+    pub signer: bool,
+}
+
+impl AccountSpec {
+    /// Flags as documented on instructions, e.g. `[signer, writable]`
+    fn flags(&self) -> String {
+        let flags = [(self.signer, "signer"), (self.writable, "writable")]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect::<Vec<_>>();
+        format!("[{}]", flags.join(", "))
+    }
+}
+
+/// Checks `name` is a Rust identifier in the casing `cased` gives it
+fn check_ident(name: &str, cased: impl Fn(&str) -> String) -> Result<(), String> {
+    match syn::parse_str::<syn::Ident>(name) {
+        Ok(_) if cased(name) == name => Ok(()),
+        _ => Err(format!("{} is not a valid name here", name)),
+    }
+}
+
+/// Checks an instruction name, an upper camel case identifier
+pub fn check_type_name(name: &str) -> Result<(), String> {
+    check_ident(name, |n| n.to_upper_camel_case())
+}
+
+/// A typed argument or field, `name:type[:max]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub ty: String,
+    /// Most items a variable size type holds
+    pub max: Option<usize>,
+}
+
+/// Spaces type text as rustfmt does, e.g. `[u8;16]` as `[u8; 16]`
+fn type_text(ty: &str) -> String {
+    let word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    let mut text = String::new();
+    let mut space = false;
+    for c in ty.chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && text.ends_with(word) && word(c) {
+            text.push(' ');
+        }
+        space = false;
+        text.push(c);
+        if c == ';' || c == ',' {
+            text.push(' ');
+        }
+    }
+    text.trim_end().to_string()
+}
+
+/// Parses a `name:type[:max]` field, checking the name and type
+pub fn parse_field(spec: &str) -> Result<FieldSpec, String> {
+    let (name, ty) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("expected NAME:TYPE, got {}", spec))?;
+    check_ident(name.trim(), |n| n.to_snake_case())?;
+    // A trailing `:N` is the max, paths only hold `::`
+    let (ty, max) = match ty.rsplit_once(':') {
+        Some((ty, max)) if !ty.ends_with(':') && max.trim().parse::<usize>().is_ok() => {
+            (ty, max.trim().parse::<usize>().ok())
+        }
+        _ => (ty, None),
+    };
+    syn::parse_str::<Type>(ty).map_err(|_| format!("{} is not a Rust type", ty))?;
+    Ok(FieldSpec {
+        name: name.trim().to_string(),
+        ty: type_text(ty),
+        max,
+    })
+}
+
+/// Parses a `name:type` instruction argument, which takes no max
+pub fn parse_arg(spec: &str) -> Result<FieldSpec, String> {
+    match parse_field(spec)? {
+        FieldSpec { max: Some(_), .. } => Err(format!("{} takes no max size", spec)),
+        arg => Ok(arg),
+    }
+}
+
+/// Parses a `name[:mut][:signer]` account
+pub fn parse_account(spec: &str) -> Result<AccountSpec, String> {
+    let mut parts = spec.split(':');
+    let name = parts.next().unwrap_or_default().trim();
+    check_ident(name, |n| n.to_snake_case())?;
+    let mut account = AccountSpec {
+        name: name.to_string(),
+        writable: false,
+        signer: false,
+    };
+    for flag in parts {
+        match flag.trim() {
+            "mut" => account.writable = true,
+            "signer" => account.signer = true,
+            other => {
+                return Err(format!(
+                    "unknown account flag {}, expected mut or signer",
+                    other
+                ))
+            }
+        }
+    }
+    Ok(account)
+}
+
+/// Known types among the identifiers of `types`, as (name, path) imports
+fn type_imports(types: &[&str]) -> Vec<(&'static str, &'static str)> {
+    KNOWN_TYPES
+        .iter()
+        .filter(|(name, _)| {
+            types.iter().any(|ty| {
+                ty.split(|c: char| !c.is_alphanumeric() && c != '_')
+                    .any(|word| word == *name)
+            })
+        })
+        .copied()
+        .collect()
+}
+
+/// Lays out `open`, the comma separated `items` and `close` on one line or,
+/// past the maximum width at `indent` or the call width for a `call`, with
+/// an item per line as rustfmt does. Lines come without the indent
+pub fn wrap(indent: &str, open: &str, items: &[String], close: &str, call: bool) -> Vec<String> {
+    let joined = items.join(", ");
+    let line = format!("{}{}{}", open, joined, close);
+    let fits = indent.len() + line.len() <= MAX_WIDTH && (!call || joined.len() <= CALL_WIDTH);
+    if fits || items.is_empty() {
+        return vec![line];
+    }
+    let mut lines = vec![open.to_string()];
+    lines.extend(items.iter().map(|item| format!("    {},", item)));
+    lines.push(close.to_string());
+    lines
+}
+
+/// Indents each of `lines` into a block of text
+fn indented(indent: &str, lines: &[String]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("{}{}\n", indent, line))
+        .collect()
+}
+
+/// Finds the `match` expressions over the variants of enum `ty`
+struct VariantMatches<'a> {
+    ty: &'a str,
+    found: Vec<&'a ExprMatch>,
+}
+
+/// The variant of enum `ty` a pattern names, if any
+fn variant_of<'a>(pat: &'a Pat, ty: &str) -> Option<&'a syn::Ident> {
+    let path = match pat {
+        Pat::Path(p) => &p.path,
+        Pat::TupleStruct(p) => &p.path,
+        Pat::Struct(p) => &p.path,
+        _ => return None,
+    };
+    match path.segments.len() == 2 && path.segments[0].ident == ty {
+        true => Some(&path.segments[1].ident),
+        false => None,
+    }
+}
+
+impl<'a> Visit<'a> for VariantMatches<'a> {
+    fn visit_expr_match(&mut self, m: &'a ExprMatch) {
+        // A match without arms is over an enum without variants
+        if m.arms.is_empty()
+            || m.arms
+                .iter()
+                .any(|arm| variant_of(&arm.pat, self.ty).is_some())
+        {
+            self.found.push(m);
+        }
+        syn::visit::visit_expr_match(self, m);
+    }
+}
+
+/// The match over the variants of enum `ty` in function `f`
+fn enum_match<'a>(source: &SourceFile, f: &'a ItemFn, ty: &'a str) -> CargoResult<&'a ExprMatch> {
+    let mut visitor = VariantMatches { ty, found: vec![] };
+    visitor.visit_item_fn(f);
+    visitor
+        .found
+        .first()
+        .copied()
+        .ok_or_else(|| source.error(format!("{} has no match over {}", f.sig.ident, ty)))
+}
+
+/// A method of an impl block of the file as a function
+fn find_method(items: &[Item], name: &str) -> Option<ItemFn> {
+    items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Impl(i) => Some(i),
+            _ => None,
+        })
+        .flat_map(|i| &i.items)
+        .find_map(|item| match item {
+            syn::ImplItem::Fn(f) if f.sig.ident == name => Some(ItemFn {
+                attrs: f.attrs.clone(),
+                vis: syn::Visibility::Inherited,
+                sig: f.sig.clone(),
+                block: Box::new(f.block.clone()),
+            }),
+            _ => None,
+        })
+}
+
+/// Plans a new arm in a match over the instruction enum, before any
+/// catch-all arm, adding the comma the previous arm may lack
+fn insert_arm(source: &mut SourceFile, m: &ExprMatch, pattern: &str, body: &str) {
+    let is_variant = |a: &&Arm| variant_of(&a.pat, INSTRUCTION_ENUM).is_some();
+    let last = m.arms.iter().take_while(is_variant).last();
+    let (at, indent) = match (last, m.arms.iter().find(|a| !is_variant(a))) {
+        (_, Some(catch_all)) => (
+            catch_all.span().start(),
+            source.indent(catch_all.span().start()),
+        ),
+        (Some(last), None) => (
+            m.brace_token.span.close().start(),
+            source.indent(last.span().start()),
+        ),
+        (None, None) => {
+            let close = m.brace_token.span.close().start();
+            (close, format!("{}    ", source.indent(close)))
+        }
+    };
+    if let Some(last) = last.filter(|a| needs_comma(a)) {
+        let end = source.offset(last.span().end());
+        source.insert(end, ",");
+    }
+    let arm = format!("{} => {},", pattern, body);
+    let lines = match indent.len() + arm.len() <= MAX_WIDTH {
+        true => vec![arm],
+        false => vec![
+            format!("{} => {{", pattern),
+            format!("    {}", body),
+            "}".to_string(),
+        ],
+    };
+    source.insert_lines_before(at, indented(&indent, &lines));
+}
+
+/// True when an arm is followed by another only with a comma
+fn needs_comma(arm: &Arm) -> bool {
+    arm.comma.is_none() && !matches!(*arm.body, syn::Expr::Block(_))
+}
+
+/// A top-level function of the file
+fn find_fn<'a>(items: &'a [Item], name: &str) -> Option<&'a ItemFn> {
+    items.iter().find_map(|item| match item {
+        Item::Fn(f) if f.sig.ident == name => Some(f),
+        _ => None,
+    })
+}
+
+/// Functions the arms of the `process` match over the instruction enum
+/// call, the instruction handlers
+pub fn dispatched(source: &SourceFile) -> Vec<String> {
+    let Some(process) = find_fn(&source.file.items, "process") else {
+        return vec![];
+    };
+    let Ok(m) = enum_match(source, process, INSTRUCTION_ENUM) else {
+        return vec![];
+    };
+    m.arms
+        .iter()
+        .filter_map(|arm| {
+            // The call may be the only statement of a block
+            let call = match &*arm.body {
+                syn::Expr::Block(b) => match &b.block.stmts[..] {
+                    [syn::Stmt::Expr(e, None)] => e,
+                    _ => return None,
+                },
+                e => e,
+            };
+            match call {
+                syn::Expr::Call(call) => match &*call.func {
+                    syn::Expr::Path(p) => p.path.get_ident().map(|i| i.to_string()),
+                    _ => None,
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Adds the variant and its `unpack` arm
+fn edit_instruction_source(
+    source: &mut SourceFile,
+    name: &str,
+    args: &[FieldSpec],
+    accounts: &[AccountSpec],
+) -> CargoResult<()> {
+    let items = source.file.items.clone();
+    let instruction = items
+        .iter()
+        .find_map(|item| match item {
+            Item::Enum(e) if e.ident == INSTRUCTION_ENUM => Some(e),
+            _ => None,
+        })
+        .ok_or_else(|| source.error(format!("no {} enum", INSTRUCTION_ENUM)))?;
+    if instruction.variants.iter().any(|v| v.ident == name) {
+        return Err(source.error(format!("{} already has {}", INSTRUCTION_ENUM, name)));
+    }
+    // The variant, documented with its accounts like the others
+    let close = instruction.brace_token.span.close().start();
+    let indent = match instruction.variants.last() {
+        Some(last) => source.indent(last.span().start()),
+        None => format!("{}    ", source.indent(close)),
+    };
+    if !instruction.variants.empty_or_trailing() {
+        let last = instruction.variants.last().unwrap();
+        let end = source.offset(last.span().end());
+        source.insert(end, ",");
+    }
+    let mut variant = format!("{}/// {}\n", indent, name);
+    for (index, account) in accounts.iter().enumerate() {
+        variant.push_str(&format!(
+            "{}/// {}. `{}` {}\n",
+            indent,
+            index,
+            account.flags(),
+            account.name
+        ));
+    }
+    let types = args.iter().map(|a| a.ty.as_str()).collect::<Vec<_>>();
+    match types.is_empty() {
+        true => variant.push_str(&format!("{}{},\n", indent, name)),
+        false => variant.push_str(&format!("{}{}({}),\n", indent, name, types.join(", "))),
+    }
+    source.insert_lines_before(close, variant);
+    // Its arm in unpack
+    if let Some(unpack) = find_method(&items, "unpack") {
+        let m = enum_match(source, &unpack, INSTRUCTION_ENUM)?;
+        let body = m
+            .arms
+            .iter()
+            .rev()
+            .find(|a| variant_of(&a.pat, INSTRUCTION_ENUM).is_some())
+            .map_or("Ok(payload)".to_string(), |a| {
+                source.source(a.body.span()).to_string()
+            });
+        let pattern = match args.is_empty() {
+            true => format!("{}::{}", INSTRUCTION_ENUM, name),
+            false => format!("{}::{}(..)", INSTRUCTION_ENUM, name),
+        };
+        let m = m.clone();
+        insert_arm(source, &m, &pattern, &body);
+    }
+    let in_scope = names_in_scope(&items, &[]);
+    source.import_missing(&items, &in_scope, &type_imports(&types), close);
+    Ok(())
+}
+
+/// Identifier of a function parameter
+fn param_name(arg: &FnArg) -> Option<String> {
+    match arg {
+        FnArg::Typed(t) => match &*t.pat {
+            Pat::Ident(i) => Some(i.ident.to_string()),
+            _ => None,
+        },
+        FnArg::Receiver(_) => None,
+    }
+}
+
+/// Adds the handler and its dispatch from `process`
+fn edit_process_source(
+    source: &mut SourceFile,
+    name: &str,
+    args: &[FieldSpec],
+    accounts: &[AccountSpec],
+) -> CargoResult<()> {
+    let items = source.file.items.clone();
+    let handler = name.to_snake_case();
+    if find_fn(&items, &handler).is_some() {
+        return Err(source.error(format!("a function {} exists", handler)));
+    }
+    let process = find_fn(&items, "process").ok_or_else(|| source.error("no process function"))?;
+    let params = process
+        .sig
+        .inputs
+        .iter()
+        .filter_map(param_name)
+        .collect::<Vec<_>>();
+    let (program_id, accounts_param) = match &params[..] {
+        [program_id, accounts_param, ..] => (program_id.clone(), accounts_param.clone()),
+        _ => return Err(source.error("process takes no program id and accounts")),
+    };
+    // Dispatch, binding the arguments by name
+    let arg_names = args.iter().map(|a| a.name.as_str()).collect::<Vec<_>>();
+    let mut call_args = vec![program_id.as_str(), accounts_param.as_str()];
+    call_args.extend(&arg_names);
+    let pattern = match args.is_empty() {
+        true => format!("{}::{}", INSTRUCTION_ENUM, name),
+        false => format!("{}::{}({})", INSTRUCTION_ENUM, name, arg_names.join(", ")),
+    };
+    let m = enum_match(source, process, INSTRUCTION_ENUM)?.clone();
+    let call = format!("{}({})", handler, call_args.join(", "));
+    insert_arm(source, &m, &pattern, &call);
+    // The handler stub before process, checking signers and logging what
+    // it received until it is written
+    let mut params = vec![
+        "_program_id: &Pubkey".to_string(),
+        match accounts.is_empty() {
+            true => "_accounts: &[AccountInfo]".to_string(),
+            false => "accounts: &[AccountInfo]".to_string(),
+        },
+    ];
+    params.extend(args.iter().map(|a| format!("{}: {}", a.name, a.ty)));
+    let mut stub = format!("/// {}\n", name);
+    for (index, account) in accounts.iter().enumerate() {
+        stub.push_str(&format!(
+            "/// {}. `{}` {}\n",
+            index,
+            account.flags(),
+            account.name
+        ));
+    }
+    let open = format!("fn {}(", handler);
+    stub.push_str(&indented(
+        "",
+        &wrap("", &open, &params, ") -> ProgramResult {", false),
+    ));
+    if !accounts.is_empty() {
+        stub.push_str("    let account_info_iter = &mut accounts.iter();\n");
+    }
+    for account in accounts {
+        stub.push_str(&format!(
+            "    let {} = next_account_info(account_info_iter)?;\n",
+            account.name
+        ));
+    }
+    for account in accounts.iter().filter(|a| a.signer) {
+        stub.push_str(&format!(
+            "    if !{}.is_signer {{\n        return Err(ProgramError::MissingRequiredSignature);\n    }}\n",
+            account.name
+        ));
+    }
+    let mut logged = vec![name.to_string()];
+    let mut values = vec![String::new()];
+    for arg in args {
+        logged.push(format!("{} {{:?}}", arg.name));
+        values.push(arg.name.clone());
+    }
+    for account in accounts {
+        logged.push(format!("{} {{}}", account.name));
+        values.push(format!("{}.key", account.name));
+    }
+    values[0] = format!("\"{}\"", logged.join(" "));
+    stub.push_str(&indented(
+        "    ",
+        &wrap("    ", "msg!(", &values, ");", true),
+    ));
+    stub.push_str("    Ok(())\n}\n\n");
+    let at = process
+        .attrs
+        .first()
+        .map_or(process.span().start(), |a| a.span().start());
+    source.insert_lines_before(at, stub);
+    // What the stub uses
+    let mut needed = vec![
+        ("AccountInfo", "solana_program::account_info::AccountInfo"),
+        ("ProgramResult", "solana_program::entrypoint::ProgramResult"),
+        ("Pubkey", "solana_program::pubkey::Pubkey"),
+        ("msg", "solana_program::msg"),
+    ];
+    if !accounts.is_empty() {
+        needed.push((
+            "next_account_info",
+            "solana_program::account_info::next_account_info",
+        ));
+    }
+    if accounts.iter().any(|a| a.signer) {
+        needed.push((
+            "ProgramError",
+            "solana_program::program_error::ProgramError",
+        ));
+    }
+    let types = args.iter().map(|a| a.ty.as_str()).collect::<Vec<_>>();
+    needed.extend(type_imports(&types));
+    let in_scope = names_in_scope(&items, &[]);
+    source.import_missing(&items, &in_scope, &needed, at);
+    Ok(())
+}
+
+/// The inline test module of the file
+pub fn test_module(items: &[Item]) -> Option<&ItemMod> {
+    items.iter().find_map(|item| match item {
+        Item::Mod(m)
+            if m.content.is_some()
+                && m.attrs.iter().any(|a| {
+                    a.path().is_ident("cfg")
+                        && a.parse_args::<syn::Ident>().is_ok_and(|i| i == "test")
+                }) =>
+        {
+            Some(m)
+        }
+        _ => None,
+    })
+}
+
+/// Adds a test sending the instruction to the program
+fn edit_test_source(
+    source: &mut SourceFile,
+    name: &str,
+    args: &[FieldSpec],
+    accounts: &[AccountSpec],
+) -> CargoResult<()> {
+    let items = source.file.items.clone();
+    let module = test_module(&items).ok_or_else(|| source.error("no test module"))?;
+    let (brace, module_items) = module.content.as_ref().unwrap();
+    let test = format!("test_{}_pass", name.to_snake_case());
+    if find_fn(module_items, &test).is_some() {
+        return Err(source.error(format!("a test {} exists", test)));
+    }
+    let parent = names_in_scope(&items, &[]);
+    if !parent.iter().any(|n| n == "entry_point") {
+        return Err(source.error("no entry_point function"));
+    }
+    let program_name = match parent.iter().any(|n| n == "NAME") {
+        true => "NAME",
+        false => "env!(\"CARGO_CRATE_NAME\")",
+    };
+    let close = brace.span.close().start();
+    let indent = format!("{}    ", source.indent(close));
+    let body_indent = format!("{}    ", indent);
+    let mut body = vec!["let program_id = Pubkey::new_unique();".to_string()];
+    body.extend(wrap(
+        &body_indent,
+        "let program_test = ProgramTest::new(",
+        &[
+            program_name.to_string(),
+            "program_id".to_string(),
+            "processor!(entry_point)".to_string(),
+        ],
+        ");",
+        true,
+    ));
+    body.push(
+        "let (mut banks_client, payer, recent_blockhash) = program_test.start().await;".to_string(),
+    );
+    for account in accounts {
+        match account.signer {
+            true => body.push(format!("let {} = Keypair::new();", account.name)),
+            false => body.push(format!("let {} = Pubkey::new_unique();", account.name)),
+        }
+    }
+    body.push("// Set up the accounts and arguments, then check the effects".to_string());
+    let instruction = match args.is_empty() {
+        true => format!("{}::{}", INSTRUCTION_ENUM, name),
+        false => format!(
+            "{}::{}({})",
+            INSTRUCTION_ENUM,
+            name,
+            vec!["Default::default()"; args.len()].join(", ")
+        ),
+    };
+    body.push("let ix = [Instruction::new_with_borsh(".to_string());
+    body.push("    program_id,".to_string());
+    body.push(format!("    &{},", instruction));
+    let metas = accounts
+        .iter()
+        .map(|account| {
+            let key = match account.signer {
+                true => format!("{}.pubkey()", account.name),
+                false => account.name.clone(),
+            };
+            let meta = match account.writable {
+                true => "new",
+                false => "new_readonly",
+            };
+            format!("AccountMeta::{}({}, {})", meta, key, account.signer)
+        })
+        .collect::<Vec<_>>();
+    let vec_indent = format!("{}    ", body_indent);
+    body.extend(
+        wrap(&vec_indent, "vec![", &metas, "],", true)
+            .iter()
+            .map(|line| format!("    {}", line)),
+    );
+    body.push(")];".to_string());
+    body.push(
+        "let mut transaction = Transaction::new_with_payer(&ix, Some(&payer.pubkey()));"
+            .to_string(),
+    );
+    let mut signers = vec!["&payer".to_string()];
+    signers.extend(
+        accounts
+            .iter()
+            .filter(|a| a.signer)
+            .map(|a| format!("&{}", a.name)),
+    );
+    body.push(format!(
+        "transaction.sign(&[{}], recent_blockhash);",
+        signers.join(", ")
+    ));
+    body.push("banks_client.process_transaction(transaction).await.unwrap();".to_string());
+    let mut text = format!("\n{0}#[tokio::test]\n{0}async fn {1}() {{\n", indent, test);
+    text.push_str(&indented(&body_indent, &body));
+    text.push_str(&format!("{}}}\n", indent));
+    source.insert_lines_before(close, text);
+    // What the test uses
+    let mut needed = vec![
+        ("Pubkey", "solana_program::pubkey::Pubkey"),
+        ("AccountMeta", "solana_program::instruction::AccountMeta"),
+        ("Instruction", "solana_program::instruction::Instruction"),
+        ("ProgramTest", "solana_program_test::ProgramTest"),
+        ("processor", "solana_program_test::processor"),
+        ("tokio", "solana_program_test::tokio"),
+        ("Signer", "solana_sdk::signer::Signer"),
+        ("Transaction", "solana_sdk::transaction::Transaction"),
+        (INSTRUCTION_ENUM, "crate::instruction::ProgramInstruction"),
+    ];
+    if accounts.iter().any(|a| a.signer) {
+        needed.push(("Keypair", "solana_sdk::signature::Keypair"));
+    }
+    let in_scope = names_in_scope(module_items, &parent);
+    let module_items = module_items.clone();
+    source.import_missing(&module_items, &in_scope, &needed, close);
+    Ok(())
+}
+
+/// Edited sources as (path, original, edited), new sources have no original
+pub type SourceEdits = Vec<(PathBuf, Option<String>, String)>;
+
+/// Sources of a program folder with the edits made so far, so that
+/// several additions build on each other before anything is written
+pub struct ProgramSources {
+    dir: PathBuf,
+    files: SourceEdits,
+}
+
+impl ProgramSources {
+    /// Starts with the sources of `program_dir` as they are on disk
+    pub fn new(program_dir: &Path) -> Self {
+        ProgramSources {
+            dir: program_dir.to_path_buf(),
+            files: vec![],
+        }
+    }
+
+    /// True when the source at `path` below the program folder exists
+    pub fn exists(&self, path: &str) -> bool {
+        let path = self.dir.join(path);
+        self.files.iter().any(|(p, ..)| *p == path) || path.is_file()
+    }
+
+    /// Parses the source at `path` below the program folder, as edited
+    pub fn read(&self, path: &str) -> CargoResult<SourceFile> {
+        let path = self.dir.join(path);
+        match self.files.iter().find(|(p, ..)| *p == path) {
+            Some((_, _, text)) => SourceFile::parse(&path, text.clone()),
+            None => SourceFile::read(&path),
+        }
+    }
+
+    /// Replaces the text of the source at `path`
+    pub fn write(&mut self, path: &Path, text: String) -> CargoResult<()> {
+        if let Some(file) = self.files.iter_mut().find(|(p, ..)| p == path) {
+            file.2 = text;
+            return Ok(());
+        }
+        let original = match path.is_file() {
+            true => Some(fs::read_to_string(path)?),
+            false => None,
+        };
+        self.files.push((path.to_path_buf(), original, text));
+        Ok(())
+    }
+
+    /// Writes the edits planned on `source`
+    pub fn save(&mut self, source: &SourceFile) -> CargoResult<()> {
+        self.write(&source.path, source.edited())
+    }
+
+    /// The sources which changed, in the order they were first edited
+    pub fn edits(self) -> SourceEdits {
+        self.files
+            .into_iter()
+            .filter(|(_, original, text)| original.as_ref() != Some(text))
+            .collect()
+    }
+}
+
+/// Plans the edits of one source for an instruction
+type InstructionEdit = fn(&mut SourceFile, &str, &[FieldSpec], &[AccountSpec]) -> CargoResult<()>;
+
+/// Adds instruction `name` taking `args` and `accounts` to the program
+/// `sources`
+pub fn add_instruction(
+    sources: &mut ProgramSources,
+    name: &str,
+    args: &[FieldSpec],
+    accounts: &[AccountSpec],
+) -> CargoResult<()> {
+    check_type_name(name)
+        .map_err(|e| ProgramError::SourceError(INSTRUCTION_SOURCE.to_string(), e))?;
+    let edits: [(&str, InstructionEdit); 3] = [
+        (INSTRUCTION_SOURCE, edit_instruction_source),
+        (PROCESS_SOURCE, edit_process_source),
+        (TEST_SOURCE, edit_test_source),
+    ];
+    for (path, edit) in edits {
+        let mut source = sources.read(path)?;
+        edit(&mut source, name, args, accounts)?;
+        sources.save(&source)?;
+    }
+    Ok(())
+}
+
+/// An error code which changes, as (variant, code before, code after)
+pub type Renumbered = Vec<(String, u32, u32)>;
+
+/// Error codes of the variants of an enum, `None` past a discriminant which
+/// is not a literal
+fn error_codes(variants: &[&syn::Variant]) -> Vec<Option<u32>> {
+    let mut next = Some(0u32);
+    let mut codes = vec![];
+    for variant in variants {
+        let code = match &variant.discriminant {
+            Some((
+                _,
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(value),
+                    ..
+                }),
+            )) => value.base10_parse::<u32>().ok(),
+            Some(_) => None,
+            None => next,
+        };
+        codes.push(code);
+        next = code.and_then(|c| c.checked_add(1));
+    }
+    codes
+}
+
+/// Message of an error variant, from its `#[error("...")]`
+fn error_message(variant: &syn::Variant) -> Option<String> {
+    variant
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("error"))
+        .and_then(|a| a.parse_args::<syn::LitStr>().ok())
+        .map(|lit| lit.value())
+}
+
+/// A `print` arm reporting `message`, a format string
+fn print_arm(name: &str, message: &str) -> Vec<String> {
+    vec![
+        format!("{}::{} => {{", ERROR_ENUM, name),
+        format!("    println!({:?})", format!("ERROR: {}", message)),
+        "}".to_string(),
+    ]
+}
+
+/// Adds error variant `name` reporting `message` to the program `sources`,
+/// after the last variant or `before` another, along with its `print` arm
+/// and those of any variant `print` lacks. Returns the error codes this
+/// changes
+pub fn add_error(
+    sources: &mut ProgramSources,
+    name: &str,
+    message: &str,
+    before: Option<&str>,
+) -> CargoResult<Renumbered> {
+    let mut source = sources.read(ERROR_SOURCE)?;
+    check_type_name(name).map_err(|e| source.error(e))?;
+    let items = source.file.items.clone();
+    let errors = items
+        .iter()
+        .find_map(|item| match item {
+            Item::Enum(e) if e.ident == ERROR_ENUM => Some(e),
+            _ => None,
+        })
+        .ok_or_else(|| source.error(format!("no {} enum", ERROR_ENUM)))?;
+    if errors.variants.iter().any(|v| v.ident == name) {
+        return Err(source.error(format!("{} already has {}", ERROR_ENUM, name)));
+    }
+    let variants = errors.variants.iter().collect::<Vec<_>>();
+    let position = match before {
+        Some(before) => variants
+            .iter()
+            .position(|v| v.ident == before)
+            .ok_or_else(|| source.error(format!("{} has no {}", ERROR_ENUM, before)))?,
+        None => variants.len(),
+    };
+    // Braces are literal in messages, which are format strings
+    let message = message.replace('{', "{{").replace('}', "}}");
+    let variant = syn::parse_str::<syn::Variant>(&format!("#[error({:?})] {}", message, name))
+        .map_err(|e| source.error(e.to_string()))?;
+    let mut after = variants.clone();
+    after.insert(position, &variant);
+    let renumbered = variants
+        .iter()
+        .zip(error_codes(&variants))
+        .filter_map(|(v, code)| {
+            let index = after.iter().position(|a| a.ident == v.ident)?;
+            match (code, error_codes(&after)[index]) {
+                (Some(from), Some(to)) if from != to => Some((v.ident.to_string(), from, to)),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    // The variant, after the last one or before the one given
+    let indent = match variants.first() {
+        Some(first) => source.indent(first.span().start()),
+        None => format!(
+            "{}    ",
+            source.indent(errors.brace_token.span.close().start())
+        ),
+    };
+    let text = indented(
+        &indent,
+        &[format!("#[error({:?})]", message), format!("{},", name)],
+    );
+    match variants.get(position) {
+        Some(next) => source.insert_lines_before(next.span().start(), text),
+        None => {
+            if !errors.variants.empty_or_trailing() {
+                let end = source.offset(variants[variants.len() - 1].span().end());
+                source.insert(end, ",");
+            }
+            let at = match variants.last() {
+                Some(last) => source.line_end(last.span().end()),
+                None => source.line_start(errors.brace_token.span.close().start()),
+            };
+            source.insert(at, text);
+        }
+    }
+    // Its print arm, and those of variants print does not report yet
+    let print = find_method(&items, "print").ok_or_else(|| source.error("no print function"))?;
+    let m = enum_match(&source, &print, ERROR_ENUM)?.clone();
+    let arm_of = |variant: &syn::Ident| {
+        m.arms
+            .iter()
+            .find(|a| variant_of(&a.pat, ERROR_ENUM) == Some(variant))
+    };
+    let catch_all = m
+        .arms
+        .iter()
+        .find(|a| variant_of(&a.pat, ERROR_ENUM).is_none());
+    let close = m.brace_token.span.close().start();
+    let indent = match m.arms.first() {
+        Some(first) => source.indent(first.span().start()),
+        None => format!("{}    ", source.indent(close)),
+    };
+    // Without arms the match may close on its opening line, `match *self {}`
+    let inline = m.arms.is_empty() && m.brace_token.span.open().start().line == close.line;
+    if inline {
+        let at = source.offset(close);
+        source.insert(at, "\n");
+    }
+    for (index, variant) in after.iter().enumerate() {
+        if arm_of(&variant.ident).is_some() {
+            continue;
+        }
+        let message = error_message(variant).unwrap_or_else(|| variant.ident.to_string());
+        // Placed before the arm of the next variant, or after the others
+        let next = after[index + 1..]
+            .iter()
+            .find_map(|v| arm_of(&v.ident))
+            .or(catch_all);
+        let at = match next {
+            Some(next) => source.line_start(next.span().start()),
+            None => match m.arms.last() {
+                Some(last) => source.line_end(last.span().end()),
+                None if inline => source.offset(close),
+                None => source.line_start(close),
+            },
+        };
+        if next.is_none() {
+            if let Some(last) = m.arms.last().filter(|a| needs_comma(a)) {
+                let end = source.offset(last.span().end());
+                source.insert(end, ",");
+            }
+        }
+        let arm = print_arm(&variant.ident.to_string(), &message);
+        source.insert(at, indented(&indent, &arm));
+    }
+    if inline {
+        let (at, indent) = (source.offset(close), source.indent(close));
+        source.insert(at, indent);
+    }
+    sources.save(&source)?;
+    Ok(renumbered)
+}
+
+/// Matches the errors by value in `print` while there are none, as an
+/// empty match over a reference does not compile
+pub fn match_no_errors(sources: &mut ProgramSources) -> CargoResult<()> {
+    let mut source = sources.read(ERROR_SOURCE)?;
+    let items = source.file.items.clone();
+    let print = find_method(&items, "print").ok_or_else(|| source.error("no print function"))?;
+    let m = enum_match(&source, &print, ERROR_ENUM)?;
+    if !m.arms.is_empty() {
+        return Ok(());
+    }
+    let (start, end) = (
+        source.offset(m.span().start()),
+        source.offset(m.span().end()),
+    );
+    source.replace(start, end, "match *self {}");
+    sources.save(&source)
+}
+
+/// Size of a fixed size primitive type, by name
+fn primitive_size(name: &str) -> Option<usize> {
+    match name {
+        "bool" | "u8" | "i8" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" | "f32" => Some(4),
+        "u64" | "i64" | "f64" => Some(8),
+        "u128" | "i128" => Some(16),
+        "Pubkey" => Some(32),
+        _ => None,
+    }
+}
+
+/// The type argument of `Vec<T>` or `Option<T>`
+fn type_argument(segment: &syn::PathSegment) -> Option<&Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+            match args.args.first() {
+                Some(syn::GenericArgument::Type(ty)) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Literal length of an array type
+fn array_len(array: &syn::TypeArray) -> Option<usize> {
+    match &array.len {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(len),
+            ..
+        }) => len.base10_parse().ok(),
+        _ => None,
+    }
+}
+
+/// Most bytes borsh takes for a value of `ty`, or `None` when unknown.
+/// Strings and vectors take a length then their items, up to `max` items
+fn borsh_size(ty: &Type, max: Option<usize>) -> Option<usize> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last()?;
+            let name = segment.ident.to_string();
+            match (name.as_str(), type_argument(segment)) {
+                (_, None) if primitive_size(&name).is_some() => primitive_size(&name),
+                ("String", None) => Some(4 + max?),
+                ("Vec", Some(item)) => Some(4 + max? * borsh_size(item, max)?),
+                ("Option", Some(item)) => Some(1 + borsh_size(item, max)?),
+                _ => None,
+            }
+        }
+        Type::Array(array) => Some(array_len(array)? * borsh_size(&array.elem, max)?),
+        Type::Tuple(tuple) => tuple.elems.iter().map(|t| borsh_size(t, max)).sum(),
+        Type::Paren(paren) => borsh_size(&paren.elem, max),
+        _ => None,
+    }
+}
+
+/// Borsh size of a state field of type `ty` as an expression, the length
+/// prefix of a string or vector plus its `max` items, fixed sizes folded
+fn size_expression(ty: &Type, max: Option<usize>) -> Option<String> {
+    let Type::Path(path) = ty else {
+        return borsh_size(ty, max).map(|size| size.to_string());
+    };
+    let segment = path.path.segments.last()?;
+    match (segment.ident.to_string().as_str(), type_argument(segment)) {
+        ("String", None) => Some(format!("4 + {}", max?)),
+        ("Vec", Some(item)) => Some(format!("4 + {} * {}", max?, borsh_size(item, None)?)),
+        _ => borsh_size(ty, max).map(|size| size.to_string()),
+    }
+}
+
+/// True for a type holding a string or vector, whose size varies
+fn is_variable(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+            segment.ident == "String"
+                || segment.ident == "Vec"
+                || type_argument(segment).is_some_and(is_variable)
+        }),
+        Type::Array(array) => is_variable(&array.elem),
+        Type::Tuple(tuple) => tuple.elems.iter().any(is_variable),
+        Type::Paren(paren) => is_variable(&paren.elem),
+        _ => false,
+    }
+}
+
+/// True for a type whose string or vector is not the value itself, such as
+/// `Vec<String>` or `Option<String>`. Setters only check the length of
+/// the value against the max
+fn is_nested_variable(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+            match (segment.ident == "Vec", type_argument(segment)) {
+                (true, Some(item)) => is_variable(item),
+                (_, item) => item.is_some_and(is_variable),
+            }
+        }),
+        _ => is_variable(ty),
+    }
+}
+
+/// True for the types getters return by value rather than by reference
+fn is_primitive(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .get_ident()
+            .is_some_and(|i| i != "Pubkey" && primitive_size(&i.to_string()).is_some()),
+        _ => false,
+    }
+}
+
+/// True when `ty` implements `Default`, which arrays past 32 items do not
+fn has_default(ty: &Type) -> bool {
+    match ty {
+        Type::Array(array) => array_len(array).is_some_and(|n| n <= 32) && has_default(&array.elem),
+        Type::Tuple(tuple) => tuple.elems.iter().all(has_default),
+        Type::Paren(paren) => has_default(&paren.elem),
+        _ => true,
+    }
+}
+
+/// Initial value of a field of type `ty`, written out for arrays past 32
+/// items, whose fixed size items are `Copy`
+fn initial_value(ty: &Type) -> String {
+    match ty {
+        Type::Array(array) if !has_default(ty) => format!(
+            "[{}; {}]",
+            initial_value(&array.elem),
+            array_len(array).unwrap_or_default()
+        ),
+        _ => "Default::default()".to_string(),
+    }
+}
+
+/// Plural suffix of a count of bytes
+fn plural(count: usize) -> &'static str {
+    match count {
+        1 => "",
+        _ => "s",
+    }
+}
+
+/// A state field with its parsed type and borsh size
+struct StateField<'a> {
+    spec: &'a FieldSpec,
+    ty: Type,
+    size: usize,
+}
+
+/// Checks the state fields and sizes them
+fn state_fields<'a>(source: &str, fields: &'a [FieldSpec]) -> CargoResult<Vec<StateField<'a>>> {
+    let error = |reason: String| ProgramError::SourceError(source.to_string(), reason);
+    let mut checked: Vec<StateField> = vec![];
+    for spec in fields {
+        if STATE_HEADER.iter().any(|(name, _)| *name == spec.name)
+            || checked.iter().any(|f| f.spec.name == spec.name)
+        {
+            return Err(error(format!(
+                "field {} is given twice or is part of the header",
+                spec.name
+            )));
+        }
+        let ty = syn::parse_str::<Type>(&spec.ty).map_err(|e| error(e.to_string()))?;
+        if is_nested_variable(&ty) {
+            return Err(error(format!(
+                "{} holds a string or vector its setter can not bound, only a String or a Vec \
+                 of fixed size items takes a max",
+                spec.ty
+            )));
+        }
+        if spec.max.is_some() && !is_variable(&ty) {
+            return Err(error(format!(
+                "{} has a fixed size, it takes no max",
+                spec.ty
+            )));
+        }
+        let size = borsh_size(&ty, spec.max).ok_or_else(|| {
+            error(match is_variable(&ty) && spec.max.is_none() {
+                true => format!(
+                    "{} has a variable size, declare its max as {}:{}:MAX",
+                    spec.ty, spec.name, spec.ty
+                ),
+                false => format!("{} has no known borsh size", spec.ty),
+            })
+        })?;
+        checked.push(StateField { spec, ty, size });
+    }
+    Ok(checked)
+}
+
+/// The struct, impls and space constant of state `state`
+fn state_text(state: &str, fields: &[StateField], version_error: &str, version: bool) -> String {
+    let space = format!("{}_SPACE", state.to_shouty_snake_case());
+    let derives = match fields.iter().all(|f| has_default(&f.ty)) {
+        true => "BorshDeserialize, BorshSerialize, Debug, Default, PartialEq",
+        false => "BorshDeserialize, BorshSerialize, Debug, PartialEq",
+    };
+    let mut text = String::new();
+    if version {
+        text.push_str(
+            "\n/// Declaration of the current data version.\nconst DATA_VERSION: u8 = 1;\n",
+        );
+    }
+    text.push_str(&format!(
+        "\n/// Maintains {} data\n#[derive({})]\npub struct {} {{\n",
+        state
+            .trim_end_matches(STATE_SUFFIX)
+            .to_snake_case()
+            .replace('_', " "),
+        derives,
+        state
+    ));
+    for (name, ty) in STATE_HEADER {
+        text.push_str(&format!("    {}: {},\n", name, ty));
+    }
+    for field in fields {
+        text.push_str(&format!("    {}: {},\n", field.spec.name, field.spec.ty));
+    }
+    // Accessors like those of the templates
+    text.push_str(&format!(
+        "}}\n\nimpl {} {{\n    /// Signal initialized\n    pub fn set_initialized(&mut self) {{\n        \
+         self.is_initialized = true;\n    }}\n    /// Gets the current data version\n    \
+         pub fn version(&self) -> u8 {{\n        self.data_version\n    }}\n",
+        state
+    ));
+    for field in fields {
+        let (name, ty) = (&field.spec.name, &field.spec.ty);
+        // Strings and vectors are read as slices, checked against their max
+        // when set
+        let slice = match &field.ty {
+            Type::Path(p) if ty == "String" && p.path.is_ident("String") => Some("str".to_string()),
+            Type::Path(p) if p.path.segments.last().is_some_and(|s| s.ident == "Vec") => ty
+                .find('<')
+                .zip(ty.rfind('>'))
+                .map(|(open, close)| format!("[{}]", ty[open + 1..close].trim())),
+            _ => None,
+        };
+        let (returned, borrow) = match &slice {
+            Some(slice) => (format!("&{}", slice), "&"),
+            None if is_primitive(&field.ty) => (ty.clone(), ""),
+            None => (format!("&{}", ty), "&"),
+        };
+        text.push_str(&format!(
+            "    /// Get the {0}\n    pub fn {0}(&self) -> {1} {{\n        {2}self.{0}\n    }}\n",
+            name, returned, borrow
+        ));
+        match (field.spec.max, &slice) {
+            (Some(max), Some(_)) => text.push_str(&format!(
+                "    /// Set the {0}, of at most {1} items\n    \
+                 pub fn set_{0}(&mut self, {0}: {2}) -> Result<(), ProgramError> {{\n        \
+                 if {0}.len() > {1} {{\n            return Err(ProgramError::InvalidArgument);\n        \
+                 }}\n        self.{0} = {0};\n        Ok(())\n    }}\n",
+                name, max, ty
+            )),
+            _ => text.push_str(&format!(
+                "    /// Set the {0}\n    pub fn set_{0}(&mut self, {0}: {1}) {{\n        \
+                 self.{0} = {0};\n    }}\n",
+                name, ty
+            )),
+        }
+    }
+    text.push_str("}\n\n");
+    // The space, summed from the borsh sizes
+    text.push_str(&format!(
+        "/// Need size for {}\n",
+        state.to_snake_case().replace('_', " ")
+    ));
+    let mut terms = vec![];
+    for (name, ty) in STATE_HEADER {
+        let size = primitive_size(ty).unwrap_or_default();
+        terms.push(size.to_string());
+        text.push_str(&format!(
+            "/// {} byte{} for `{}`\n",
+            size,
+            plural(size),
+            name
+        ));
+    }
+    for field in fields {
+        terms.extend(size_expression(&field.ty, field.spec.max));
+        let bound = match field.spec.max {
+            Some(max) => format!(", at most {} items", max),
+            None => String::new(),
+        };
+        text.push_str(&format!(
+            "/// {} byte{} for `{}`{}\n",
+            field.size,
+            plural(field.size),
+            field.spec.name,
+            bound
+        ));
+    }
+    let initial = fields
+        .iter()
+        .map(|f| {
+            format!(
+                "                {}: {},\n",
+                f.spec.name,
+                initial_value(&f.ty)
+            )
+        })
+        .collect::<String>();
+    let total = terms.join(" + ");
+    text.push_str(&format!(
+        "const {space}: usize = {total};
+
+/// Implement Sealed trait for {state}
+/// to satisfy Pack trait constraints
+impl Sealed for {state} {{}}
+
+/// Implement IsInitialized trait for {state}
+/// to satisfy Pack trait constraints
+impl IsInitialized for {state} {{
+    fn is_initialized(&self) -> bool {{
+        self.is_initialized
+    }}
+}}
+
+impl Pack for {state} {{
+    const LEN: usize = {space};
+
+    /// Store 'state' of account to its data area
+    fn pack_into_slice(&self, dst: &mut [u8]) {{
+        let mut bw = BufWriter::new(dst);
+        self.serialize(&mut bw).unwrap();
+    }}
+
+    /// Retrieve 'state' of account from account data area
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {{
+        let initialized = src[0] != 0;
+        // Check initialized
+        if initialized {{
+            // Version check
+            if src[1] == DATA_VERSION {{
+                msg!(\"Processing consistent version data\");
+                Ok(try_from_slice_unchecked::<{state}>(src)?)
+            }} else {{
+                msg!(\"Incoherrent data version detected\");
+                Err({version_error})
+            }}
+        }} else {{
+            msg!(\"Processing pre-initialized data\");
+            Ok({state} {{
+                is_initialized: false,
+                data_version: DATA_VERSION,
+{initial}            }})
+        }}
+    }}
+}}
+"
+    ));
+    text
+}
+
+/// Paths sharing a prefix, for grouped `use` declarations
+#[derive(Default)]
+struct UseTree {
+    /// The prefix itself is imported
+    named: bool,
+    children: Vec<(String, UseTree)>,
+}
+
+impl UseTree {
+    fn add(&mut self, path: &str) {
+        let Some(first) = path.split("::").next() else {
+            return;
+        };
+        let rest = path.strip_prefix(first).unwrap_or_default();
+        let index = match self.children.iter().position(|(name, _)| name == first) {
+            Some(index) => index,
+            None => {
+                self.children.push((first.to_string(), UseTree::default()));
+                self.children.len() - 1
+            }
+        };
+        let child = &mut self.children[index].1;
+        match rest.strip_prefix("::") {
+            Some(rest) => child.add(rest),
+            None => child.named = true,
+        }
+    }
+
+    /// The items below this tree, in rustfmt order: `self`, `super` and
+    /// `crate`, modules and functions, types, constants and then globs
+    fn items(&self) -> Vec<String> {
+        let mut children = self.children.iter().collect::<Vec<_>>();
+        children.sort_by_key(|(name, _)| {
+            let rank = match name.as_str() {
+                "self" => 0,
+                "super" => 1,
+                "crate" => 2,
+                "*" => 6,
+                n if n.starts_with(char::is_lowercase) => 3,
+                n if n.chars().all(|c| !c.is_lowercase()) => 5,
+                _ => 4,
+            };
+            (rank, name.clone())
+        });
+        let mut items = vec![];
+        for (name, child) in children {
+            // Kept apart from its children, `self` would not import a macro
+            if child.named {
+                items.push(name.clone());
+            }
+            if child.children.is_empty() {
+                continue;
+            }
+            let inner = child.items();
+            items.push(match inner.len() {
+                1 => format!("{}::{}", name, inner[0]),
+                _ => format!("{}::{{{}}}", name, inner.join(", ")),
+            });
+        }
+        items
+    }
+}
+
+/// Lines of the `use` declaration of `items` below `root` at `indent`, laid
+/// out as rustfmt does: on one line when they fit and no nested group has
+/// several items, one per line when one does, and otherwise filling lines.
+/// rustfmt keeps imports a column or two short of the width
+fn use_lines(indent: &str, root: &str, items: &[String]) -> Vec<String> {
+    let open = format!("use {}::{{", root);
+    let nested = items.iter().any(|item| item.contains(", "));
+    let line = format!("{}{}}};", open, items.join(", "));
+    if indent.len() + line.len() < MAX_WIDTH - 1 && !nested {
+        return vec![line];
+    }
+    let mut lines = vec![open];
+    for item in items {
+        let fits = lines.len() > 1
+            && !nested
+            && indent.len() + lines[lines.len() - 1].len() + item.len() + 2 < MAX_WIDTH;
+        match fits {
+            true => lines.last_mut().unwrap().push_str(&format!(" {},", item)),
+            false => lines.push(format!("    {},", item)),
+        }
+    }
+    lines.push("};".to_string());
+    lines
+}
+
+/// `use` declarations of `paths` at `indent`, grouped by crate as rustfmt
+/// lays them out
+fn use_block(indent: &str, paths: &[&str]) -> String {
+    let mut tree = UseTree::default();
+    for path in paths {
+        tree.add(path);
+    }
+    let mut text = String::new();
+    for item in tree.items() {
+        let lines = match item.split_once("::{") {
+            Some((root, group)) => {
+                // Items of the outer group, which may go on several lines
+                let group = &group[..group.len() - 1];
+                let mut items = vec![];
+                let (mut depth, mut start) = (0, 0);
+                for (i, c) in group.char_indices() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        ',' if depth == 0 => {
+                            items.push(group[start..i].trim().to_string());
+                            start = i + 1;
+                        }
+                        _ => {}
+                    }
+                }
+                items.push(group[start..].trim().to_string());
+                use_lines(indent, root, &items)
+            }
+            _ => vec![format!("use {};", item)],
+        };
+        text.push_str(&indented(indent, &lines));
+    }
+    text
+}
+
+/// Merges the last group of `use` items of `items`, which additions grow
+/// a declaration at a time, into grouped declarations. Groups holding
+/// renames, attributes or re-exports are left as they are
+pub fn group_imports(source: &mut SourceFile, items: &[Item]) {
+    let group = use_group(items);
+    let (Some(first), Some(last)) = (group.first(), group.last()) else {
+        return;
+    };
+    let mut paths = vec![];
+    for u in &group {
+        let plain = u.attrs.is_empty() && matches!(u.vis, syn::Visibility::Inherited);
+        if !plain || !use_paths(&u.tree, "", &mut paths) {
+            return;
+        }
+    }
+    let paths = paths.iter().map(String::as_str).collect::<Vec<_>>();
+    let indent = source.indent(first.span().start());
+    let start = source.line_start(first.span().start());
+    let end = source.line_end(last.span().end());
+    source.replace(start, end, use_block(&indent, &paths));
+}
+
+/// Position just past the end of a source
+fn end_of(source: &SourceFile) -> LineColumn {
+    LineColumn {
+        line: source.text.matches('\n').count() + 1,
+        column: 0,
+    }
+}
+
+/// Declares module `name` in the crate root after the other declared
+/// modules, or the imports when there are none
+pub fn declare_module(sources: &mut ProgramSources, name: &str) -> CargoResult<()> {
+    let mut lib = sources.read(LIB_SOURCE)?;
+    let items = lib.file.items.clone();
+    if names_in_scope(&items, &[]).iter().any(|n| n == name) {
+        return Ok(());
+    }
+    let declared = items.iter().rev().find(|item| match item {
+        Item::Mod(m) => m.content.is_none(),
+        _ => false,
+    });
+    let imported = items.iter().rev().find(|item| matches!(item, Item::Use(_)));
+    match (declared, imported, items.first()) {
+        (Some(last), _, _) => {
+            let at = lib.line_end(last.span().end());
+            lib.insert(at, format!("pub mod {};\n", name));
+        }
+        (None, Some(last), _) => {
+            let at = lib.line_end(last.span().end());
+            lib.insert(at, format!("\npub mod {};\n", name));
+        }
+        (None, None, Some(first)) => {
+            lib.insert_lines_before(first.span().start(), format!("pub mod {};\n\n", name))
+        }
+        (None, None, None) => {
+            let at = lib.text.len();
+            lib.insert(at, format!("\npub mod {};\n", name));
+        }
+    }
+    sources.save(&lib)
+}
+
+/// True when the program declares the error variant `name`
+fn has_error(sources: &ProgramSources, name: &str) -> CargoResult<bool> {
+    if !sources.exists(ERROR_SOURCE) {
+        return Ok(false);
+    }
+    let errors = sources.read(ERROR_SOURCE)?;
+    Ok(errors.file.items.iter().any(|item| match item {
+        Item::Enum(e) => e.ident == ERROR_ENUM && e.variants.iter().any(|v| v.ident == name),
+        _ => false,
+    }))
+}
+
+/// Adds account state `name` with `fields` to the program `sources`, a
+/// struct after the init/version header with its `Sealed`, `IsInitialized`
+/// and `Pack` impls. `Pack::LEN` is the sum of the borsh sizes of the fields
+pub fn add_state(
+    sources: &mut ProgramSources,
+    name: &str,
+    fields: &[FieldSpec],
+) -> CargoResult<()> {
+    check_type_name(name).map_err(|e| ProgramError::SourceError(STATE_SOURCE.to_string(), e))?;
+    let state = match name.ends_with(STATE_SUFFIX) {
+        true => name.to_string(),
+        false => format!("{}{}", name, STATE_SUFFIX),
+    };
+    let fields = state_fields(STATE_SOURCE, fields)?;
+    let version_error = match has_error(sources, "DataVersionMismatchError")? {
+        true => "CustomProgramError::DataVersionMismatchError.into()",
+        false => "ProgramError::InvalidAccountData",
+    };
+    let mut needed = vec![
+        ("BorshDeserialize", "borsh::BorshDeserialize"),
+        ("BorshSerialize", "borsh::BorshSerialize"),
+        (
+            "try_from_slice_unchecked",
+            "solana_program::borsh::try_from_slice_unchecked",
+        ),
+        ("msg", "solana_program::msg"),
+        (
+            "ProgramError",
+            "solana_program::program_error::ProgramError",
+        ),
+        (
+            "IsInitialized",
+            "solana_program::program_pack::IsInitialized",
+        ),
+        ("Pack", "solana_program::program_pack::Pack"),
+        ("Sealed", "solana_program::program_pack::Sealed"),
+        ("BufWriter", "std::io::BufWriter"),
+    ];
+    if version_error.starts_with(ERROR_ENUM) {
+        needed.push((ERROR_ENUM, "crate::error::CustomProgramError"));
+    }
+    let types = fields
+        .iter()
+        .map(|f| f.spec.ty.as_str())
+        .collect::<Vec<_>>();
+    needed.extend(type_imports(&types));
+    let created = !sources.exists(STATE_SOURCE);
+    let mut source = match created {
+        false => sources.read(STATE_SOURCE)?,
+        true => {
+            // A new module starts with all it needs in scope
+            let paths = needed.iter().map(|(_, path)| *path).collect::<Vec<_>>();
+            let text = format!("{}\n{}", STATE_DOC, use_block("", &paths));
+            SourceFile::parse(&sources.dir.join(STATE_SOURCE), text)?
+        }
+    };
+    let items = source.file.items.clone();
+    let in_scope = names_in_scope(&items, &[]);
+    if in_scope.contains(&state) {
+        return Err(source.error(format!("{} exists", state)));
+    }
+    let fallback = items.first().map_or(end_of(&source), |i| i.span().start());
+    source.import_missing(&items, &in_scope, &needed, fallback);
+    // The state goes last, sharing the data version of the others
+    let end = source.text.len();
+    if !source.text.ends_with('\n') {
+        source.insert(end, "\n");
+    }
+    let version = !in_scope.iter().any(|n| n == "DATA_VERSION");
+    source.insert(end, state_text(&state, &fields, version_error, version));
+    if created {
+        declare_module(sources, "state")?;
+    }
+    sources.save(&source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_test_program;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Adds an instruction and writes the edited sources back
+    fn add(dir: &Path, name: &str, args: &[&str], accounts: &[&str]) -> CargoResult<()> {
+        let args = args
+            .iter()
+            .map(|a| parse_field(a).unwrap())
+            .collect::<Vec<_>>();
+        let accounts = accounts
+            .iter()
+            .map(|a| parse_account(a).unwrap())
+            .collect::<Vec<_>>();
+        let mut sources = ProgramSources::new(dir);
+        add_instruction(&mut sources, name, &args, &accounts)?;
+        write_edits(sources);
+        Ok(())
+    }
+
+    /// Writes the edited sources back
+    fn write_edits(sources: ProgramSources) {
+        for (path, _, edited) in sources.edits() {
+            fs::write(path, edited).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_specs_pass() {
+        assert_eq!(
+            parse_field("amount:u64"),
+            Ok(FieldSpec {
+                name: "amount".to_string(),
+                ty: "u64".to_string(),
+                max: None,
+            })
+        );
+        assert_eq!(parse_field("seeds:Vec<[u8; 32]>:4").unwrap().max, Some(4));
+        assert_eq!(parse_field("tags:[u8;16]").unwrap().ty, "[u8; 16]");
+        assert_eq!(
+            parse_field("pair: ( u8 ,&'a  str )").unwrap().ty,
+            "(u8, &'a str)"
+        );
+        assert_eq!(
+            parse_field("owner:solana_program::pubkey::Pubkey")
+                .unwrap()
+                .ty,
+            "solana_program::pubkey::Pubkey"
+        );
+        assert_eq!(
+            parse_account("source:mut:signer"),
+            Ok(AccountSpec {
+                name: "source".to_string(),
+                writable: true,
+                signer: true,
+            })
+        );
+        assert_eq!(parse_account("mint").unwrap().flags(), "[]");
+        assert_eq!(parse_account("owner:signer").unwrap().flags(), "[signer]");
+        assert!(check_type_name("Transfer").is_ok());
+    }
+
+    #[test]
+    fn parse_specs_fail() {
+        assert!(parse_field("amount").is_err());
+        assert!(parse_field("Amount:u64").is_err());
+        assert!(parse_field("amount:u 64").is_err());
+        assert!(parse_arg("label:String:32").is_err());
+        assert!(parse_account("source:owner").is_err());
+        assert!(parse_account("fn").is_err());
+        assert!(check_type_name("transfer").is_err());
+    }
+
+    #[test]
+    fn wrap_pass() {
+        let items = ["a".to_string(), "b".to_string()];
+        assert_eq!(wrap("", "f(", &items, ");", true), vec!["f(a, b);"]);
+        let long = ["x".repeat(40), "y".repeat(40)];
+        assert_eq!(
+            wrap("", "f(", &long, ");", true),
+            vec![
+                "f(".to_string(),
+                format!("    {},", long[0]),
+                format!("    {},", long[1]),
+                ");".to_string()
+            ]
+        );
+        assert_eq!(wrap("", "fn f(", &long, ") {", false).len(), 1);
+        assert_eq!(wrap("", "vec![", &[], "],", true), vec!["vec![],"]);
+    }
+
+    #[test]
+    fn add_instruction_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        add(
+            dir.path(),
+            "Transfer",
+            &["amount:u64", "to:Pubkey"],
+            &["source:mut:signer", "destination:mut"],
+        )
+        .unwrap();
+        add(dir.path(), "Close", &[], &[]).unwrap();
+        let read = |file: &str| {
+            let text = fs::read_to_string(dir.path().join(file)).unwrap();
+            // The edited sources still parse
+            syn::parse_file(&text).unwrap();
+            text
+        };
+        let instruction = read(INSTRUCTION_SOURCE);
+        assert!(instruction.contains(
+            "    SetContent(u8),\n    /// Transfer\n    /// 0. `[signer, writable]` source\n    \
+             /// 1. `[writable]` destination\n    Transfer(u64, Pubkey),\n    /// Close\n    Close,\n}"
+        ));
+        assert!(instruction.contains(
+            "            ProgramInstruction::Transfer(..) => Ok(payload),\n            \
+             ProgramInstruction::Close => Ok(payload),\n"
+        ));
+        assert!(instruction.contains("use solana_program::pubkey::Pubkey;\n"));
+        let process = read(PROCESS_SOURCE);
+        assert!(process.contains(
+            "        ProgramInstruction::Transfer(amount, to) => transfer(program_id, accounts, amount, to),\n"
+        ));
+        assert!(
+            process.contains("        ProgramInstruction::Close => close(program_id, accounts),\n")
+        );
+        assert!(process.contains(
+            "fn transfer(\n    _program_id: &Pubkey,\n    accounts: &[AccountInfo],\n    amount: u64,\n    \
+             to: Pubkey,\n) -> ProgramResult {\n"
+        ));
+        assert!(process.contains(
+            "    if !source.is_signer {\n        return Err(ProgramError::MissingRequiredSignature);\n    }\n"
+        ));
+        assert!(process.contains(
+            "fn close(_program_id: &Pubkey, _accounts: &[AccountInfo]) -> ProgramResult {\n    \
+             msg!(\"Close\");\n    Ok(())\n}\n\n/// Main processing"
+        ));
+        let tests = read(TEST_SOURCE);
+        assert!(tests.contains("    #[tokio::test]\n    async fn test_transfer_pass() {\n"));
+        assert!(tests.contains("        let source = Keypair::new();\n"));
+        assert!(tests.contains("                AccountMeta::new(destination, false),\n"));
+        assert!(tests.contains("        transaction.sign(&[&payer, &source], recent_blockhash);\n"));
+        assert!(tests.contains("            &ProgramInstruction::Close,\n            vec![],\n"));
+    }
+
+    #[test]
+    fn add_instruction_imports_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "token-transfer", "foo");
+        add(dir.path(), "Approve", &["amount:u64"], &["owner:signer"]).unwrap();
+        let process = fs::read_to_string(dir.path().join(PROCESS_SOURCE)).unwrap();
+        // Sorted into the last group of imports, as rustfmt would
+        let at = process.find("use solana_program::program_error::ProgramError;\n");
+        assert!(at.is_some() && at < process.find("use solana_program::{"));
+    }
+
+    #[test]
+    fn add_instruction_fail() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            add(dir.path(), "Transfer", &[], &[]),
+            Err(ProgramError::SourceError(..))
+        ));
+        write_test_program(dir.path(), "counter", "foo");
+        for name in ["SetContent", "transfer"] {
+            assert!(matches!(
+                add(dir.path(), name, &[], &[]),
+                Err(ProgramError::SourceError(..))
+            ));
+        }
+        // A clashing handler leaves the program as it was
+        let before = fs::read_to_string(dir.path().join(INSTRUCTION_SOURCE)).unwrap();
+        assert!(matches!(
+            add(dir.path(), "CheckAccountOwnership", &[], &[]),
+            Err(ProgramError::SourceError(..))
+        ));
+        assert_eq!(
+            before,
+            fs::read_to_string(dir.path().join(INSTRUCTION_SOURCE)).unwrap()
+        );
+    }
+
+    #[test]
+    fn add_error_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "vault", "foo");
+        let path = dir.path().join(ERROR_SOURCE);
+        // print has drifted, missing the arm of InvalidVaultAddress
+        let drifted = fs::read_to_string(&path).unwrap().replace(
+            "            CustomProgramError::InvalidVaultAddress => {\n                \
+             println!(\"ERROR: Vault is not the PDA of the owner\")\n            }\n",
+            "",
+        );
+        fs::write(&path, drifted).unwrap();
+        let mut sources = ProgramSources::new(dir.path());
+        let renumbered = add_error(&mut sources, "NotAdmin", "Not {admin}", None).unwrap();
+        let edits = sources.edits();
+        assert!(renumbered.is_empty());
+        let errors = &edits[0].2;
+        syn::parse_file(errors).unwrap();
+        assert!(errors
+            .contains("    InsufficientFunds,\n    #[error(\"Not {{admin}}\")]\n    NotAdmin,\n"));
+        assert!(errors.contains(
+            "            CustomProgramError::InvalidVaultAddress => {\n                \
+             println!(\"ERROR: Vault is not the PDA of the owner\")\n            }\n            \
+             CustomProgramError::NotVaultOwner => {\n"
+        ));
+        assert!(errors.contains(
+            "            CustomProgramError::NotAdmin => {\n                \
+             println!(\"ERROR: Not {{admin}}\")\n            }\n        }\n"
+        ));
+        fs::write(&path, errors).unwrap();
+        let mut sources = ProgramSources::new(dir.path());
+        let renumbered =
+            add_error(&mut sources, "BadSeed", "Bad seed", Some("NotVaultOwner")).unwrap();
+        let edits = sources.edits();
+        assert_eq!(
+            renumbered,
+            vec![
+                ("NotVaultOwner".to_string(), 3, 4),
+                ("InsufficientFunds".to_string(), 4, 5),
+                ("NotAdmin".to_string(), 5, 6)
+            ]
+        );
+        assert!(edits[0].2.contains(
+            "    #[error(\"Bad seed\")]\n    BadSeed,\n    #[error(\"Signer does not own"
+        ));
+    }
+
+    #[test]
+    fn add_error_no_errors_pass() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(ERROR_SOURCE);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "pub enum CustomProgramError {\n}\n\nimpl PrintProgramError for CustomProgramError {\n    \
+             fn print<E>(&self) {\n        match self {\n        }\n    }\n}\n",
+        )
+        .unwrap();
+        let mut sources = ProgramSources::new(dir.path());
+        match_no_errors(&mut sources).unwrap();
+        let errors = sources.edits()[0].2.clone();
+        assert!(errors.contains("    fn print<E>(&self) {\n        match *self {}\n    }\n"));
+        fs::write(&path, errors).unwrap();
+        let mut sources = ProgramSources::new(dir.path());
+        add_error(&mut sources, "NotAdmin", "Not admin", None).unwrap();
+        match_no_errors(&mut sources).unwrap();
+        let errors = &sources.edits()[0].2;
+        assert!(errors.contains("{\n    #[error(\"Not admin\")]\n    NotAdmin,\n}\n"));
+        assert!(errors.contains(
+            "        match *self {\n            CustomProgramError::NotAdmin => {\n                \
+             println!(\"ERROR: Not admin\")\n            }\n        }\n    }\n"
+        ));
+    }
+
+    #[test]
+    fn error_codes_pass() {
+        let item = syn::parse_str::<syn::ItemEnum>("enum E { A, B = 5, C, D = X, E }").unwrap();
+        let variants = item.variants.iter().collect::<Vec<_>>();
+        assert_eq!(
+            error_codes(&variants),
+            vec![Some(0), Some(5), Some(6), None, None]
+        );
+    }
+
+    #[test]
+    fn add_error_fail() {
+        let dir = tempdir().unwrap();
+        let mut sources = ProgramSources::new(dir.path());
+        assert!(matches!(
+            add_error(&mut sources, "NotAdmin", "Not admin", None),
+            Err(ProgramError::SourceError(..))
+        ));
+        write_test_program(dir.path(), "counter", "foo");
+        for (name, before) in [
+            ("DataVersionMismatchError", None),
+            ("not_admin", None),
+            ("NotAdmin", Some("Missing")),
+        ] {
+            assert!(matches!(
+                add_error(&mut sources, name, "Not admin", before),
+                Err(ProgramError::SourceError(..))
+            ));
+        }
+    }
+
+    /// Adds state and writes the edited sources back
+    fn add_fields(dir: &Path, name: &str, fields: &[&str]) -> CargoResult<()> {
+        let fields = fields
+            .iter()
+            .map(|f| parse_field(f).unwrap())
+            .collect::<Vec<_>>();
+        let mut sources = ProgramSources::new(dir);
+        add_state(&mut sources, name, &fields)?;
+        write_edits(sources);
+        Ok(())
+    }
+
+    #[test]
+    fn use_block_pass() {
+        assert_eq!(
+            use_block(
+                "",
+                &["std::io::BufWriter", "borsh::BorshSerialize", "borsh"]
+            ),
+            "use borsh;\nuse borsh::BorshSerialize;\nuse std::io::BufWriter;\n"
+        );
+        assert_eq!(
+            use_block("", &["a::b::c", "a::b::d", "a::e"]),
+            "use a::{\n    b::{c, d},\n    e,\n};\n"
+        );
+        let long = ["x".repeat(40), "y".repeat(40), "z".repeat(40)];
+        assert_eq!(
+            use_lines("", "a", &long),
+            vec![
+                "use a::{".to_string(),
+                format!("    {}, {},", long[0], long[1]),
+                format!("    {},", long[2]),
+                "};".to_string()
+            ]
+        );
+        let items = |len| ["a".repeat(len), "b".to_string()];
+        assert_eq!(use_lines("", "crate", &items(81))[0].len(), 98);
+        assert_eq!(use_lines("", "crate", &items(82)).len(), 3);
+        assert_eq!(
+            use_block(
+                "    ",
+                &[
+                    "solana_program::pubkey::Pubkey",
+                    "solana_program::msg",
+                    "solana_program::program_pack::Sealed",
+                    "solana_program::program_pack::Pack",
+                    "solana_program::program_error::ProgramError",
+                    "crate::error::CustomProgramError",
+                    "super::*",
+                ]
+            ),
+            "    use super::*;\n    use crate::error::CustomProgramError;\n    \
+             use solana_program::{\n        msg,\n        program_error::ProgramError,\n        \
+             program_pack::{Pack, Sealed},\n        pubkey::Pubkey,\n    };\n"
+        );
+    }
+
+    #[test]
+    fn add_state_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        add_fields(
+            dir.path(),
+            "Vault",
+            &["owner:Pubkey", "balance:u64", "tags:[u8;16]"],
+        )
+        .unwrap();
+        add_fields(dir.path(), "ProfileState", &["name:String:32"]).unwrap();
+        let text = fs::read_to_string(dir.path().join(STATE_SOURCE)).unwrap();
+        syn::parse_file(&text).unwrap();
+        assert_eq!(text.matches("const DATA_VERSION").count(), 1);
+        assert!(text.contains(
+            "#[derive(BorshDeserialize, BorshSerialize, Debug, Default, PartialEq)]\n\
+             pub struct VaultState {\n    is_initialized: bool,\n    data_version: u8,\n    \
+             owner: Pubkey,\n    balance: u64,\n    tags: [u8; 16],\n}\n"
+        ));
+        assert!(text.contains("const VAULT_STATE_SPACE: usize = 1 + 1 + 32 + 8 + 16;\n"));
+        assert!(text.contains("pub fn owner(&self) -> &Pubkey {"));
+        assert!(text.contains("pub fn balance(&self) -> u64 {"));
+        assert!(text.contains("Err(CustomProgramError::DataVersionMismatchError.into())"));
+        assert!(text.contains("/// 36 bytes for `name`, at most 32 items\n"));
+        assert!(text.contains("const PROFILE_STATE_SPACE: usize = 1 + 1 + 4 + 32;\n"));
+        assert!(
+            text.contains("pub fn set_name(&mut self, name: String) -> Result<(), ProgramError> {")
+        );
+        assert!(!text.contains("ProfileStateState"));
+    }
+
+    #[test]
+    fn add_state_module_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "token-transfer", "foo");
+        let mut sources = ProgramSources::new(dir.path());
+        add_state(
+            &mut sources,
+            "Vault",
+            &[parse_field("owner:Pubkey").unwrap()],
+        )
+        .unwrap();
+        let edits = sources.edits();
+        let (path, original, lib) = &edits[0];
+        assert!(path.ends_with(LIB_SOURCE) && original.is_some());
+        assert!(lib.ends_with("pub mod process;\npub mod state;\n"));
+        let (path, original, text) = &edits[1];
+        assert!(path.ends_with(STATE_SOURCE) && original.is_none());
+        assert!(text.starts_with(
+            "//! @brief Program account state management\n\n\
+             use borsh::{BorshDeserialize, BorshSerialize};\n"
+        ));
+        assert!(text.contains("Err(ProgramError::InvalidAccountData)"));
+        assert!(!text.contains("CustomProgramError"));
+        syn::parse_file(text).unwrap();
+    }
+
+    #[test]
+    fn state_field_values_pass() {
+        let ty = |ty: &str| syn::parse_str::<Type>(ty).unwrap();
+        for (field, max, expression) in [
+            ("u64", None, "8"),
+            ("[u8; 16]", None, "16"),
+            ("String", Some(32), "4 + 32"),
+            ("Vec<(u8, u16)>", Some(4), "4 + 4 * 3"),
+            ("Option<[u32; 2]>", None, "9"),
+        ] {
+            assert_eq!(
+                size_expression(&ty(field), max).as_deref(),
+                Some(expression),
+                "{}",
+                field
+            );
+        }
+        assert_eq!(initial_value(&ty("[u8; 32]")), "Default::default()");
+        assert_eq!(initial_value(&ty("[u8; 64]")), "[Default::default(); 64]");
+        assert_eq!(
+            initial_value(&ty("[[Pubkey; 40]; 2]")),
+            "[[Default::default(); 40]; 2]"
+        );
+    }
+
+    /// Value of a constant expression of sums and products
+    fn eval(expr: &syn::Expr) -> usize {
+        match expr {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(int),
+                ..
+            }) => int.base10_parse().unwrap(),
+            syn::Expr::Paren(paren) => eval(&paren.expr),
+            syn::Expr::Binary(b) => match b.op {
+                syn::BinOp::Add(_) => eval(&b.left) + eval(&b.right),
+                syn::BinOp::Mul(_) => eval(&b.left) * eval(&b.right),
+                _ => panic!("unexpected operator"),
+            },
+            _ => panic!("unexpected expression"),
+        }
+    }
+
+    #[test]
+    fn add_state_max_size_pass() {
+        use borsh::BorshSerialize;
+
+        /// The state generated below, at the max of each field
+        #[derive(BorshSerialize)]
+        struct BookState {
+            is_initialized: bool,
+            data_version: u8,
+            tags: Vec<u16>,
+            title: String,
+            pair: (u8, [u32; 2]),
+            flag: Option<u64>,
+            hashes: [u8; 40],
+        }
+
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        add_fields(
+            dir.path(),
+            "Book",
+            &[
+                "tags:Vec<u16>:4",
+                "title:String:8",
+                "pair:(u8, [u32; 2])",
+                "flag:Option<u64>",
+                "hashes:[u8; 40]",
+            ],
+        )
+        .unwrap();
+        let text = fs::read_to_string(dir.path().join(STATE_SOURCE)).unwrap();
+        assert!(text.contains(
+            "pub struct BookState {\n    is_initialized: bool,\n    data_version: u8,\n    \
+             tags: Vec<u16>,\n    title: String,\n    pair: (u8, [u32; 2]),\n    \
+             flag: Option<u64>,\n    hashes: [u8; 40],\n}\n"
+        ));
+        assert!(text.contains("if tags.len() > 4 {"));
+        assert!(text.contains("if title.len() > 8 {"));
+        let space = syn::parse_file(&text)
+            .unwrap()
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                Item::Const(c) if c.ident == "BOOK_STATE_SPACE" => Some(eval(&c.expr)),
+                _ => None,
+            })
+            .unwrap();
+        // The largest value the setters allow packs into exactly LEN bytes
+        let book = BookState {
+            is_initialized: true,
+            data_version: 1,
+            tags: vec![u16::MAX; 4],
+            title: "8 bytes!".to_string(),
+            pair: (1, [2, 3]),
+            flag: Some(4),
+            hashes: [5; 40],
+        };
+        let mut data = vec![0; space];
+        let mut dst = data.as_mut_slice();
+        book.serialize(&mut dst).unwrap();
+        assert!(dst.is_empty());
+    }
+
+    #[test]
+    fn add_state_fail() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            add_fields(dir.path(), "Vault", &[]),
+            Err(ProgramError::SourceError(..))
+        ));
+        write_test_program(dir.path(), "counter", "foo");
+        for (name, fields) in [
+            ("vault", vec!["owner:Pubkey"]),
+            ("ProgramAccount", vec!["owner:Pubkey"]),
+            ("Vault", vec!["label:String"]),
+            ("Vault", vec!["balance:u64:8"]),
+            ("Vault", vec!["pool:PoolState"]),
+            ("Vault", vec!["data_version:u8"]),
+            ("Vault", vec!["owner:Pubkey", "owner:u8"]),
+            ("Vault", vec!["names:[String; 40]:8"]),
+            ("Vault", vec!["tags:Vec<String>:4"]),
+            ("Vault", vec!["pair:(u8, Vec<u8>):8"]),
+            ("Vault", vec!["label:Option<String>:8"]),
+        ] {
+            assert!(matches!(
+                add_fields(dir.path(), name, &fields),
+                Err(ProgramError::SourceError(..))
+            ));
+        }
+    }
+}
@@ -0,0 +1,1379 @@
+//! Program specs
+//!
+//! A spec declares the accounts, instructions and errors of a program in
+//! TOML or YAML. Generation writes the program sources in the layout of the
+//! built-in templates, growing skeleton sources with the edits of
+//! `cargo solana add` so both produce the same code.
+//!
+//! The error, instruction and state sources are the spec's own, while the
+//! handlers and tests are written by hand once generated: generating again
+//! keeps the bodies of the handlers and tests of the instructions the spec
+//! still declares, and the other items of the process and entry point
+//! sources along with the imports they use. Errors and states the spec does
+//! not declare are kept too, after those it does.
+//!
+//! ```toml
+//! [accounts.Vault]
+//! fields = ["owner:Pubkey", "balance:u64"]
+//!
+//! [instructions.Deposit]
+//! args = ["amount:u64"]
+//! accounts = ["owner:signer", "vault:mut"]
+//!
+//! [errors]
+//! NotVaultOwner = "Signer does not own the vault"
+//! ```
+
+use crate::{
+    edit::{names_in_scope, use_paths, SourceFile},
+    error::{CargoResult, ProgramError},
+    scaffold::{
+        add_error, add_instruction, add_state, check_type_name, declare_module, dispatched,
+        group_imports, match_no_errors, parse_account, parse_arg, parse_field, test_module, wrap,
+        AccountSpec, FieldSpec, ProgramSources, ERROR_ENUM, ERROR_SOURCE, INSTRUCTION_ENUM,
+        INSTRUCTION_SOURCE, LIB_SOURCE, PROCESS_SOURCE, STATE_DOC, STATE_SOURCE, TEST_SOURCE,
+    },
+    template::{render, TemplateVars},
+    utils::PROGRAM_TEMPLATE,
+};
+use heck::ToSnakeCase;
+use minijinja::Value;
+use proc_macro2::{LineColumn, TokenStream, TokenTree};
+use std::{fs, path::Path, str};
+use syn::{spanned::Spanned, FnArg, Item, ItemFn, ReturnType};
+use toml_edit::DocumentMut;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Sections of a spec
+const SPEC_SECTIONS: [&str; 3] = ["accounts", "instructions", "errors"];
+/// Keys of an account entry
+const ACCOUNT_KEYS: [&str; 1] = ["fields"];
+/// Keys of an instruction entry
+const INSTRUCTION_KEYS: [&str; 2] = ["args", "accounts"];
+/// Modules the crate root declares in the layout generation writes
+const PROGRAM_MODULES: [&str; 4] = ["entry_point", "error", "instruction", "process"];
+/// Skeleton of the process module, dispatching no instruction yet
+const PROCESS_SKELETON: &str = include_str!("../resources/spec/process.rs");
+/// Skeleton of the entry point module, with an empty test module
+const ENTRY_POINT_SKELETON: &str = include_str!("../resources/spec/entry_point.rs");
+
+/// An account state of the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDef {
+    pub name: String,
+    pub fields: Vec<FieldSpec>,
+}
+
+/// An instruction of the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionDef {
+    pub name: String,
+    pub args: Vec<FieldSpec>,
+    pub accounts: Vec<AccountSpec>,
+}
+
+/// A custom error of the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDef {
+    pub name: String,
+    pub message: String,
+}
+
+/// A parsed program spec, entries in the order they are declared
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramSpec {
+    pub accounts: Vec<AccountDef>,
+    pub instructions: Vec<InstructionDef>,
+    pub errors: Vec<ErrorDef>,
+}
+
+/// What generating again carried over of the handwritten code
+#[derive(Debug, Default, PartialEq)]
+pub struct Regenerated {
+    /// Handlers and tests which kept their bodies
+    pub kept: Vec<String>,
+    /// Handlers and tests of instructions the spec no longer declares
+    pub removed: Vec<String>,
+    /// Errors and state items the spec does not declare, kept as written
+    pub carried: Vec<String>,
+    /// Handlers whose bodies were kept under a new signature, as
+    /// (handler, signature before, signature after)
+    pub conflicts: Vec<(String, String, String)>,
+}
+
+/// What generating again keeps of an item, by name
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Keep {
+    /// The signature and body of a handler
+    Body,
+    /// The whole item, a test
+    Item,
+    /// Nothing, the item of an instruction the spec no longer declares
+    Stale,
+}
+
+/// A spec value as read from either format
+enum Node {
+    Text(String),
+    List(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+/// Converts a TOML item into a spec value
+fn toml_node(item: &toml_edit::Item) -> Option<Node> {
+    fn from_value(v: &toml_edit::Value) -> Option<Node> {
+        match v {
+            toml_edit::Value::String(s) => Some(Node::Text(s.value().clone())),
+            toml_edit::Value::Array(a) => a
+                .iter()
+                .map(from_value)
+                .collect::<Option<_>>()
+                .map(Node::List),
+            toml_edit::Value::InlineTable(t) => t
+                .iter()
+                .map(|(k, v)| from_value(v).map(|v| (k.to_string(), v)))
+                .collect::<Option<_>>()
+                .map(Node::Map),
+            _ => None,
+        }
+    }
+    match item {
+        toml_edit::Item::Value(v) => from_value(v),
+        toml_edit::Item::Table(t) => t
+            .iter()
+            .map(|(k, v)| toml_node(v).map(|v| (k.to_string(), v)))
+            .collect::<Option<_>>()
+            .map(Node::Map),
+        toml_edit::Item::ArrayOfTables(_) | toml_edit::Item::None => None,
+    }
+}
+
+/// Converts a YAML value into a spec value, an empty entry being a map
+fn yaml_node(yaml: &Yaml) -> Option<Node> {
+    match yaml {
+        Yaml::String(s) => Some(Node::Text(s.clone())),
+        Yaml::Array(a) => a
+            .iter()
+            .map(yaml_node)
+            .collect::<Option<_>>()
+            .map(Node::List),
+        Yaml::Hash(h) => h
+            .iter()
+            .map(|(k, v)| Some((k.as_str()?.to_string(), yaml_node(v)?)))
+            .collect::<Option<_>>()
+            .map(Node::Map),
+        Yaml::Null => Some(Node::Map(vec![])),
+        _ => None,
+    }
+}
+
+impl ProgramSpec {
+    /// Parses spec text, YAML when `yaml` is set and TOML otherwise.
+    /// `origin` names the spec in errors
+    pub fn parse(origin: &str, text: &str, yaml: bool) -> CargoResult<Self> {
+        let format_error = |what: String| ProgramError::SpecFormatError(origin.into(), what);
+        let node = match yaml {
+            true => YamlLoader::load_from_str(text)?
+                .first()
+                .map_or(Some(Node::Map(vec![])), yaml_node),
+            false => toml_node(text.parse::<DocumentMut>()?.as_item()),
+        };
+        let Some(Node::Map(sections)) = node else {
+            return Err(format_error(
+                "expected tables of accounts, instructions and errors".into(),
+            ));
+        };
+        // Each section maps names to entries
+        let entries = |section: &str, node: Node| -> CargoResult<Vec<(String, Node)>> {
+            let Node::Map(entries) = node else {
+                return Err(format_error(format!("{} must be a table", section)));
+            };
+            for (name, _) in &entries {
+                check_type_name(name)
+                    .map_err(|e| format_error(format!("{}.{}: {}", section, name, e)))?;
+            }
+            Ok(entries)
+        };
+        // Entries hold lists of `add` style specs
+        let specs =
+            |what: &str, node: Node, keys: &[&str]| -> CargoResult<Vec<(String, Vec<String>)>> {
+                let Node::Map(values) = node else {
+                    return Err(format_error(format!("{} must be a table", what)));
+                };
+                let mut lists = vec![];
+                for (key, value) in values {
+                    if !keys.contains(&key.as_str()) {
+                        return Err(format_error(format!("unknown key {}.{}", what, key)));
+                    }
+                    let list = match value {
+                        Node::List(items) => items
+                            .into_iter()
+                            .map(|item| match item {
+                                Node::Text(text) => Some(text),
+                                _ => None,
+                            })
+                            .collect::<Option<Vec<_>>>(),
+                        _ => None,
+                    }
+                    .ok_or_else(|| {
+                        format_error(format!("{}.{} must be a list of strings", what, key))
+                    })?;
+                    lists.push((key, list));
+                }
+                Ok(lists)
+            };
+        let parsed = |what: &str, spec: &str, parser: fn(&str) -> Result<FieldSpec, String>| {
+            parser(spec).map_err(|e| format_error(format!("{}: {}", what, e)))
+        };
+        let mut spec = ProgramSpec::default();
+        for (section, node) in sections {
+            match section.as_str() {
+                "accounts" => {
+                    for (name, entry) in entries(&section, node)? {
+                        let what = format!("accounts.{}", name);
+                        let mut fields = vec![];
+                        for (_, list) in specs(&what, entry, &ACCOUNT_KEYS)? {
+                            for field in list {
+                                fields.push(parsed(&what, &field, parse_field)?);
+                            }
+                        }
+                        spec.accounts.push(AccountDef { name, fields });
+                    }
+                }
+                "instructions" => {
+                    for (name, entry) in entries(&section, node)? {
+                        let what = format!("instructions.{}", name);
+                        let mut instruction = InstructionDef {
+                            name,
+                            args: vec![],
+                            accounts: vec![],
+                        };
+                        for (key, list) in specs(&what, entry, &INSTRUCTION_KEYS)? {
+                            for item in list {
+                                match key.as_str() {
+                                    "args" => {
+                                        instruction.args.push(parsed(&what, &item, parse_arg)?)
+                                    }
+                                    _ => {
+                                        instruction.accounts.push(parse_account(&item).map_err(
+                                            |e| format_error(format!("{}: {}", what, e)),
+                                        )?)
+                                    }
+                                }
+                            }
+                        }
+                        spec.instructions.push(instruction);
+                    }
+                }
+                "errors" => {
+                    for (name, message) in entries(&section, node)? {
+                        let Node::Text(message) = message else {
+                            return Err(format_error(format!("errors.{} must be a message", name)));
+                        };
+                        spec.errors.push(ErrorDef { name, message });
+                    }
+                }
+                other => {
+                    return Err(format_error(format!(
+                        "unknown section {}, expected one of {}",
+                        other,
+                        SPEC_SECTIONS.join(", ")
+                    )))
+                }
+            }
+        }
+        if spec.instructions.is_empty() {
+            return Err(format_error("declares no instructions".into()));
+        }
+        Ok(spec)
+    }
+
+    /// Reads the spec at `path`, YAML for a `.yaml` or `.yml` file and
+    /// TOML for a `.toml` one
+    pub fn load(path: &Path) -> CargoResult<Self> {
+        let origin = path.display().to_string();
+        let yaml = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => true,
+            Some("toml") => false,
+            _ => {
+                return Err(ProgramError::SpecFormatError(
+                    origin,
+                    "expected a .toml, .yaml or .yml file".into(),
+                ))
+            }
+        };
+        Self::parse(&origin, &fs::read_to_string(path)?, yaml)
+    }
+}
+
+/// A source of the built-in program template rendered with no instructions
+/// or errors
+fn program_source(name: &str) -> CargoResult<String> {
+    let (_, bytes) = PROGRAM_TEMPLATE
+        .iter()
+        .find(|(n, _)| *n == name)
+        .expect("program template source");
+    let mut vars = TemplateVars::new("", "");
+    for list in ["instructions", "errors"] {
+        vars.extra
+            .insert(list.to_string(), Value::from(Vec::<Value>::new()));
+    }
+    render(name, str::from_utf8(bytes).unwrap(), &vars)
+}
+
+/// Source text without whitespace, to compare code
+fn compact(text: &str) -> String {
+    text.split_whitespace().collect()
+}
+
+/// Name generating again matches an item by, none for imports
+fn item_key(source: &SourceFile, item: &Item) -> Option<String> {
+    let ident = match item {
+        Item::Use(_) => return None,
+        Item::Const(i) => &i.ident,
+        Item::Enum(i) => &i.ident,
+        Item::Fn(i) => &i.sig.ident,
+        Item::Mod(i) => &i.ident,
+        Item::Static(i) => &i.ident,
+        Item::Struct(i) => &i.ident,
+        Item::Trait(i) => &i.ident,
+        Item::Type(i) => &i.ident,
+        // An impl is generated again for the same trait and type
+        Item::Impl(i) => {
+            let target = compact(source.source(i.self_ty.span()));
+            return Some(match &i.trait_ {
+                Some((_, path, _)) => {
+                    format!(
+                        "impl {} for {}",
+                        compact(source.source(path.span())),
+                        target
+                    )
+                }
+                None => format!("impl {}", target),
+            });
+        }
+        _ => return Some(compact(source.source(item.span()))),
+    };
+    Some(ident.to_string())
+}
+
+/// Lines of an item, with its attributes
+fn item_text<'a>(source: &'a SourceFile, item: &Item) -> &'a str {
+    let span = item.span();
+    &source.text[source.line_start(span.start())..source.line_end(span.end())]
+}
+
+/// Collects the identifiers of source text
+fn idents(tokens: TokenStream, names: &mut Vec<String>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => names.push(ident.to_string()),
+            TokenTree::Group(group) => idents(group.stream(), names),
+            _ => {}
+        }
+    }
+}
+
+/// Start of a function past its attributes
+fn fn_start(f: &ItemFn) -> LineColumn {
+    match f.vis {
+        syn::Visibility::Inherited => f.sig.span().start(),
+        _ => f.vis.span().start(),
+    }
+}
+
+/// Signature of a function on one line
+fn signature(source: &SourceFile, f: &ItemFn) -> String {
+    let text = source
+        .source(f.sig.span())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.replace("( ", "(").replace(", )", ")")
+}
+
+/// True for a handler body as `add instruction` writes it, which takes its
+/// accounts, checks its signers and logs what it got
+fn is_stub(source: &SourceFile, f: &ItemFn) -> bool {
+    let Some((last, rest)) = f.block.stmts.split_last() else {
+        return false;
+    };
+    compact(source.source(last.span())) == "Ok(())"
+        && rest.iter().all(|stmt| {
+            let text = compact(source.source(stmt.span()));
+            text == "letaccount_info_iter=&mutaccounts.iter();"
+                || (text.starts_with("let")
+                    && text.ends_with("=next_account_info(account_info_iter)?;"))
+                || (text.starts_with("if!")
+                    && text.ends_with(
+                        ".is_signer{returnErr(ProgramError::MissingRequiredSignature);}",
+                    ))
+                || text.starts_with("msg!(")
+        })
+}
+
+/// Spans of the parameter patterns of a function
+fn params(f: &ItemFn) -> Vec<proc_macro2::Span> {
+    f.sig
+        .inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Typed(t) => t.pat.span(),
+            FnArg::Receiver(r) => r.span(),
+        })
+        .collect()
+}
+
+/// Parameter patterns of a function, as written
+fn param_names(source: &SourceFile, f: &ItemFn) -> Vec<String> {
+    params(f)
+        .into_iter()
+        .map(|span| source.source(span).to_string())
+        .collect()
+}
+
+/// Parameter and return types of a function, with the parameter names a
+/// leading underscore aside, which callers depend on
+fn call_shape(source: &SourceFile, f: &ItemFn) -> Vec<String> {
+    let mut shape = f
+        .sig
+        .inputs
+        .iter()
+        .map(|input| match input {
+            FnArg::Typed(t) => format!(
+                "{}:{}",
+                compact(source.source(t.pat.span())).trim_start_matches('_'),
+                compact(source.source(t.ty.span()))
+            ),
+            FnArg::Receiver(_) => "self".to_string(),
+        })
+        .collect::<Vec<_>>();
+    shape.push(match &f.sig.output {
+        ReturnType::Default => String::new(),
+        ReturnType::Type(_, ty) => compact(source.source(ty.span())),
+    });
+    shape
+}
+
+/// Carries the handwritten code of the `old` items of a module over into
+/// its `fresh` ones. Items `rules` names keep their body, the whole item or
+/// nothing, other items generated again are the fresh ones and items no
+/// longer generated are kept in place, with the imports all these use. A
+/// kept handler body is given the new signature when `force` is set. The
+/// keys of the items kept in place are returned
+fn carry_over(
+    fresh: &mut SourceFile,
+    fresh_items: &[Item],
+    old: &SourceFile,
+    old_items: &[Item],
+    rules: &[(String, Keep)],
+    force: bool,
+    report: &mut Regenerated,
+) -> Vec<String> {
+    let fresh_keys = fresh_items
+        .iter()
+        .map(|item| item_key(fresh, item))
+        .collect::<Vec<_>>();
+    let rule = |key: &str| rules.iter().find(|(name, _)| name == key).map(|(_, k)| *k);
+    let (mut carried, mut in_place, mut trailing) = (vec![], vec![], String::new());
+    // Items no longer generated follow the fresh item of the one before
+    // them, or go before the fresh items when none is, or after the imports
+    // of a module with no items
+    let mut anchor = match fresh_items.iter().find(|i| !matches!(i, Item::Use(_))) {
+        Some(first) => (fresh.line_start(first.span().start()), false),
+        None => (fresh.text.len(), true),
+    };
+    for item in old_items {
+        let Some(key) = item_key(old, item) else {
+            continue;
+        };
+        let Some(index) = fresh_keys.iter().position(|k| k.as_ref() == Some(&key)) else {
+            match rule(&key) {
+                Some(Keep::Stale) => report.removed.push(key),
+                _ => {
+                    let text = item_text(old, item);
+                    carried.push(text.to_string());
+                    in_place.push(key);
+                    match anchor.1 {
+                        _ if fresh_items.is_empty() => trailing.push_str(&format!("\n{}", text)),
+                        true => fresh.insert(anchor.0, format!("\n{}", text)),
+                        false => fresh.insert(anchor.0, format!("{}\n", text)),
+                    }
+                }
+            }
+            continue;
+        };
+        let now = &fresh_items[index];
+        anchor = (fresh.line_end(now.span().end()), true);
+        match (rule(&key), item, now) {
+            (Some(Keep::Body), Item::Fn(was), Item::Fn(now)) => {
+                let start = fresh.offset(fn_start(now));
+                let end = fresh.offset(now.block.span().end());
+                let body = old.source(was.block.span());
+                if call_shape(old, was) == call_shape(fresh, now) {
+                    // Parameters renamed to be used or a visibility stay
+                    let text =
+                        &old.text[old.offset(fn_start(was))..old.offset(was.block.span().end())];
+                    fresh.replace(start, end, text);
+                } else {
+                    report.conflicts.push((
+                        key.clone(),
+                        signature(old, was),
+                        signature(fresh, now),
+                    ));
+                    if force {
+                        // Parameters the body used keep their names, in a
+                        // signature wrapped as `add instruction` writes it
+                        let used = param_names(old, was);
+                        let params = now
+                            .sig
+                            .inputs
+                            .iter()
+                            .map(|input| match input {
+                                FnArg::Typed(t) => {
+                                    let name = fresh.source(t.pat.span());
+                                    let plain = name.trim_start_matches('_');
+                                    let name = match used.iter().any(|u| u == plain) {
+                                        true => plain,
+                                        false => name,
+                                    };
+                                    format!("{}: {}", name, fresh.source(t.ty.span()))
+                                }
+                                FnArg::Receiver(r) => fresh.source(r.span()).to_string(),
+                            })
+                            .collect::<Vec<_>>();
+                        let close = match &now.sig.output {
+                            ReturnType::Default => ") {".to_string(),
+                            ReturnType::Type(_, ty) => {
+                                format!(") -> {} {{", fresh.source(ty.span()))
+                            }
+                        };
+                        let open = format!("fn {}(", now.sig.ident);
+                        let lines = wrap("", &open, &params, &close, false);
+                        fresh.replace(start, end, format!("{}{}", lines.join("\n"), &body[1..]));
+                    }
+                }
+                carried.push(body.to_string());
+                report.kept.push(key);
+            }
+            (Some(Keep::Item), ..) => {
+                let span = now.span();
+                let (start, end) = (fresh.line_start(span.start()), fresh.line_end(span.end()));
+                let text = item_text(old, item);
+                fresh.replace(start, end, text);
+                carried.push(text.to_string());
+                report.kept.push(key);
+            }
+            _ => {}
+        }
+    }
+    // The imports of the old items the kept code uses, and those the old
+    // code does not name, such as traits it calls methods of
+    let (mut used, mut named) = (vec![], vec![]);
+    for text in &carried {
+        if let Ok(tokens) = text.parse::<TokenStream>() {
+            idents(tokens, &mut used);
+        }
+    }
+    for item in old_items.iter().filter(|i| !matches!(i, Item::Use(_))) {
+        if let Ok(tokens) = item_text(old, item).parse::<TokenStream>() {
+            idents(tokens, &mut named);
+        }
+    }
+    let mut fresh_paths = vec![];
+    for item in fresh_items {
+        if let Item::Use(u) = item {
+            use_paths(&u.tree, "", &mut fresh_paths);
+        }
+    }
+    let mut needed = vec![];
+    for item in old_items {
+        let Item::Use(u) = item else {
+            continue;
+        };
+        let mut paths = vec![];
+        if !use_paths(&u.tree, "", &mut paths) {
+            continue;
+        }
+        for path in paths {
+            let name = path.rsplit("::").next().unwrap_or_default().to_string();
+            let needed_by_kept = name == "*" || used.contains(&name) || !named.contains(&name);
+            if needed_by_kept && !fresh_paths.contains(&path) {
+                needed.push((name, path));
+            }
+        }
+    }
+    let needed = needed
+        .iter()
+        .map(|(name, path)| (name.as_str(), path.as_str()))
+        .collect::<Vec<_>>();
+    let in_scope = names_in_scope(fresh_items, &[]);
+    let end = LineColumn {
+        line: fresh.text.matches('\n').count() + 1,
+        column: 0,
+    };
+    let fallback = fresh_items.first().map_or(end, |i| i.span().start());
+    fresh.import_missing(fresh_items, &in_scope, &needed, fallback);
+    if !trailing.is_empty() {
+        fresh.insert(fresh.text.len(), trailing);
+    }
+    in_place
+}
+
+/// Whether `item` names one of the instruction variants `dropped`
+fn sends(source: &SourceFile, item: &Item, dropped: &[String]) -> bool {
+    let mut names = vec![];
+    if let Ok(tokens) = item_text(source, item).parse::<TokenStream>() {
+        idents(tokens, &mut names);
+    }
+    names
+        .windows(2)
+        .any(|w| w[0] == INSTRUCTION_ENUM && dropped.contains(&w[1]))
+}
+
+/// Variants of enum `name` of `source`
+fn variants(source: &SourceFile, name: &str) -> Vec<String> {
+    source
+        .file
+        .items
+        .iter()
+        .find_map(|item| match item {
+            Item::Enum(e) if e.ident == name => Some(&e.variants),
+            _ => None,
+        })
+        .into_iter()
+        .flatten()
+        .map(|v| v.ident.to_string())
+        .collect()
+}
+
+/// Unit variants of the error enum of `source` with their messages
+fn written_errors(source: &SourceFile) -> Vec<ErrorDef> {
+    let variants = source.file.items.iter().find_map(|item| match item {
+        Item::Enum(e) if e.ident == ERROR_ENUM => Some(&e.variants),
+        _ => None,
+    });
+    variants
+        .into_iter()
+        .flatten()
+        .filter(|v| matches!(v.fields, syn::Fields::Unit))
+        .map(|v| {
+            let message = v
+                .attrs
+                .iter()
+                .filter(|a| a.path().is_ident("error"))
+                .find_map(|a| a.parse_args::<syn::LitStr>().ok())
+                .map_or(v.ident.to_string(), |m| {
+                    m.value().replace("{{", "{").replace("}}", "}")
+                });
+            ErrorDef {
+                name: v.ident.to_string(),
+                message,
+            }
+        })
+        .collect()
+}
+
+/// Generates the error, instruction, process and entry point sources of the
+/// program in `program_dir` from `spec`, and its state when the spec has
+/// accounts or the program a state module. The crate root must declare the
+/// modules of the built-in templates. Handlers and tests written by hand
+/// are carried over, failing on a handler signature the spec changes or
+/// a handwritten handler of an instruction it drops unless `force` is set,
+/// and so are the errors and state items the spec does not declare
+pub fn generate(
+    program_dir: &Path,
+    spec: &ProgramSpec,
+    force: bool,
+) -> CargoResult<(ProgramSources, Regenerated)> {
+    let mut sources = ProgramSources::new(program_dir);
+    let lib = sources.read(LIB_SOURCE)?;
+    for module in PROGRAM_MODULES {
+        let declared = lib
+            .file
+            .items
+            .iter()
+            .any(|item| matches!(item, Item::Mod(m) if m.ident == module));
+        if !declared {
+            return Err(lib.error(format!(
+                "no {} module, generate writes the layout of the built-in templates",
+                module
+            )));
+        }
+    }
+    // Sources holding handwritten code, as they were
+    let mut handwritten = vec![];
+    for path in [PROCESS_SOURCE, TEST_SOURCE, STATE_SOURCE] {
+        if sources.exists(path) {
+            handwritten.push((path, sources.read(path)?));
+        }
+    }
+    // Instructions the program had which the spec dropped
+    let dropped = match sources.exists(INSTRUCTION_SOURCE) {
+        true => variants(&sources.read(INSTRUCTION_SOURCE)?, INSTRUCTION_ENUM),
+        false => vec![],
+    };
+    let dropped = dropped
+        .into_iter()
+        .filter(|v| !spec.instructions.iter().any(|i| i.name == *v))
+        .collect::<Vec<_>>();
+    // Errors written by hand follow those of the spec
+    let mut report = Regenerated::default();
+    let mut errors = spec.errors.iter().collect::<Vec<_>>();
+    let written = match sources.exists(ERROR_SOURCE) {
+        true => written_errors(&sources.read(ERROR_SOURCE)?),
+        false => vec![],
+    };
+    for error in &written {
+        if !spec.errors.iter().any(|e| e.name == error.name) {
+            report.carried.push(error.name.clone());
+            errors.push(error);
+        }
+    }
+    let mut generated = vec![
+        (ERROR_SOURCE, program_source("error.rs")?),
+        (INSTRUCTION_SOURCE, program_source("instruction.rs")?),
+        (PROCESS_SOURCE, PROCESS_SKELETON.to_string()),
+        (TEST_SOURCE, ENTRY_POINT_SKELETON.to_string()),
+    ];
+    if !spec.accounts.is_empty() || sources.exists(STATE_SOURCE) {
+        generated.push((STATE_SOURCE, format!("{}\n", STATE_DOC)));
+    }
+    for (path, text) in &generated {
+        sources.write(&program_dir.join(path), text.clone())?;
+    }
+    // Errors first, the states report a data version mismatch with theirs
+    for error in &errors {
+        add_error(&mut sources, &error.name, &error.message, None)?;
+    }
+    if errors.is_empty() {
+        match_no_errors(&mut sources)?;
+    }
+    if !spec.accounts.is_empty() {
+        declare_module(&mut sources, "state")?;
+    }
+    for account in &spec.accounts {
+        add_state(&mut sources, &account.name, &account.fields)?;
+    }
+    for instruction in &spec.instructions {
+        add_instruction(
+            &mut sources,
+            &instruction.name,
+            &instruction.args,
+            &instruction.accounts,
+        )?;
+    }
+    let handlers = spec
+        .instructions
+        .iter()
+        .map(|i| i.name.to_snake_case())
+        .collect::<Vec<_>>();
+    // Handlers the program dispatched to for instructions the spec dropped
+    let stale = handwritten
+        .iter()
+        .filter(|(path, _)| *path == PROCESS_SOURCE)
+        .flat_map(|(_, old)| dispatched(old))
+        .filter(|h| !handlers.contains(h))
+        .collect::<Vec<_>>();
+    // Of which those written by hand are only removed under force
+    let written = handwritten
+        .iter()
+        .filter(|(path, _)| *path == PROCESS_SOURCE)
+        .flat_map(|(_, old)| {
+            old.file.items.iter().filter_map(|item| match item {
+                Item::Fn(f) if stale.contains(&f.sig.ident.to_string()) && !is_stub(old, f) => {
+                    Some(f.sig.ident.to_string())
+                }
+                _ => None,
+            })
+        })
+        .collect::<Vec<_>>();
+    if !force && !written.is_empty() {
+        return Err(ProgramError::HandlerRemoved(written.join(", ")));
+    }
+    for (path, old) in &handwritten {
+        let mut fresh = sources.read(path)?;
+        let items = fresh.file.items.clone();
+        match *path {
+            PROCESS_SOURCE => {
+                let rules = handlers
+                    .iter()
+                    .map(|h| (h.clone(), Keep::Body))
+                    .chain(stale.iter().map(|h| (h.clone(), Keep::Stale)))
+                    .collect::<Vec<_>>();
+                carry_over(
+                    &mut fresh,
+                    &items,
+                    old,
+                    &old.file.items,
+                    &rules,
+                    force,
+                    &mut report,
+                );
+            }
+            STATE_SOURCE => {
+                let carried = carry_over(
+                    &mut fresh,
+                    &items,
+                    old,
+                    &old.file.items,
+                    &[],
+                    force,
+                    &mut report,
+                );
+                report.carried.extend(carried);
+            }
+            _ => {
+                carry_over(
+                    &mut fresh,
+                    &items,
+                    old,
+                    &old.file.items,
+                    &[],
+                    force,
+                    &mut report,
+                );
+                if let (Some(now), Some(was)) = (test_module(&items), test_module(&old.file.items))
+                {
+                    let test = |h: &String| format!("test_{}_pass", h);
+                    let (_, now_items) = now.content.as_ref().unwrap();
+                    let (_, was_items) = was.content.as_ref().unwrap();
+                    // Tests sending a dropped instruction go with it
+                    let sending = was_items.iter().filter_map(|item| match item {
+                        Item::Fn(f) if sends(old, item, &dropped) => Some(f.sig.ident.to_string()),
+                        _ => None,
+                    });
+                    let rules = handlers
+                        .iter()
+                        .map(|h| (test(h), Keep::Item))
+                        .chain(stale.iter().map(|h| (test(h), Keep::Stale)))
+                        .chain(sending.map(|t| (t, Keep::Stale)))
+                        .collect::<Vec<_>>();
+                    carry_over(
+                        &mut fresh,
+                        now_items,
+                        old,
+                        was_items,
+                        &rules,
+                        force,
+                        &mut report,
+                    );
+                }
+            }
+        }
+        sources.save(&fresh)?;
+    }
+    if !force && !report.conflicts.is_empty() {
+        let handlers = report
+            .conflicts
+            .iter()
+            .map(|(handler, ..)| handler.as_str())
+            .collect::<Vec<_>>();
+        return Err(ProgramError::HandlerConflict(handlers.join(", ")));
+    }
+    // The additions import a path at a time
+    for (path, _) in generated {
+        let mut source = sources.read(path)?;
+        let items = source.file.items.clone();
+        group_imports(&mut source, &items);
+        if let Some(module) = test_module(&items) {
+            let (_, module_items) = module.content.as_ref().unwrap();
+            group_imports(&mut source, module_items);
+        }
+        sources.save(&source)?;
+    }
+    Ok((sources, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_test_program;
+    use tempfile::tempdir;
+
+    const TOML_SPEC: &str = r#"
+[accounts.Vault]
+fields = ["owner:Pubkey", "label:String:32"]
+
+[instructions.Initialize]
+accounts = ["owner:signer", "vault:mut"]
+
+[instructions.Deposit]
+args = ["amount:u64"]
+accounts = ["owner:signer", "vault:mut"]
+
+[errors]
+NotVaultOwner = "Signer does not own the vault"
+"#;
+
+    const YAML_SPEC: &str = r#"
+accounts:
+  Vault:
+    fields: ["owner:Pubkey", "label:String:32"]
+instructions:
+  Initialize:
+    accounts: ["owner:signer", "vault:mut"]
+  Deposit:
+    args: ["amount:u64"]
+    accounts: ["owner:signer", "vault:mut"]
+errors:
+  NotVaultOwner: Signer does not own the vault
+"#;
+
+    /// Names in scope of a whole crate
+    #[derive(Default)]
+    struct Names {
+        /// Items, generic parameters and imports of any module
+        defined: Vec<String>,
+        /// Enums, whose paths are checked for their members
+        enums: Vec<String>,
+        /// Enum variants and inherent functions, as `Enum::Variant`
+        variants: Vec<String>,
+        /// Paths used, with the enums checked for their variants
+        used: Vec<Vec<String>>,
+    }
+
+    impl<'a> syn::visit::Visit<'a> for Names {
+        fn visit_item(&mut self, item: &'a Item) {
+            match item {
+                Item::Use(u) => {
+                    let mut paths = vec![];
+                    use_paths(&u.tree, "", &mut paths);
+                    for path in paths {
+                        let name = path.rsplit("::").next().unwrap_or_default();
+                        self.defined.push(name.to_string());
+                    }
+                }
+                Item::Enum(e) => {
+                    self.enums.push(e.ident.to_string());
+                    for v in &e.variants {
+                        self.variants.push(format!("{}::{}", e.ident, v.ident));
+                    }
+                }
+                Item::Impl(i) if i.trait_.is_none() => {
+                    if let syn::Type::Path(ty) = &*i.self_ty {
+                        let ty = &ty.path.segments.last().unwrap().ident;
+                        for f in &i.items {
+                            if let syn::ImplItem::Fn(f) = f {
+                                self.variants.push(format!("{}::{}", ty, f.sig.ident));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            let ident = match item {
+                Item::Const(i) => Some(&i.ident),
+                Item::Enum(i) => Some(&i.ident),
+                Item::Fn(i) => Some(&i.sig.ident),
+                Item::Mod(i) => Some(&i.ident),
+                Item::Static(i) => Some(&i.ident),
+                Item::Struct(i) => Some(&i.ident),
+                Item::Trait(i) => Some(&i.ident),
+                Item::Type(i) => Some(&i.ident),
+                _ => None,
+            };
+            self.defined.extend(ident.map(|i| i.to_string()));
+            syn::visit::visit_item(self, item);
+        }
+
+        fn visit_generic_param(&mut self, param: &'a syn::GenericParam) {
+            if let syn::GenericParam::Type(t) = param {
+                self.defined.push(t.ident.to_string());
+            }
+            syn::visit::visit_generic_param(self, param);
+        }
+
+        fn visit_path(&mut self, path: &'a syn::Path) {
+            let segments = path
+                .segments
+                .iter()
+                .map(|s| s.ident.to_string())
+                .collect::<Vec<_>>();
+            self.used.push(segments);
+            syn::visit::visit_path(self, path);
+        }
+    }
+
+    /// Paths the sources of the program in `dir` use which neither the
+    /// crate, its dependencies nor the prelude define. Names bound locally
+    /// are lowercase and left out
+    fn unresolved(dir: &Path) -> Vec<String> {
+        let mut names = Names::default();
+        for entry in fs::read_dir(dir.join("src")).unwrap() {
+            let text = fs::read_to_string(entry.unwrap().path()).unwrap();
+            syn::visit::Visit::visit_file(&mut names, &syn::parse_file(&text).unwrap());
+        }
+        let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        let manifest = manifest.parse::<DocumentMut>().unwrap();
+        let crates = ["dependencies", "dev-dependencies"]
+            .iter()
+            .filter_map(|t| manifest.get(t).and_then(|t| t.as_table_like()))
+            .flat_map(|t| t.iter().map(|(k, _)| k.replace('-', "_")))
+            .collect::<Vec<_>>();
+        let known = [
+            "crate",
+            "self",
+            "super",
+            "Self",
+            "std",
+            "Ok",
+            "Err",
+            "Some",
+            "None",
+            "Result",
+            "Option",
+            "Vec",
+            "String",
+            "Box",
+            "Default",
+            "From",
+            "Into",
+            "Debug",
+            "PartialEq",
+            "Clone",
+            "Copy",
+            "Eq",
+            "Error",
+            "Send",
+            "Sync",
+            "Sized",
+        ];
+        let mut unresolved = vec![];
+        for path in &names.used {
+            let first = &path[0];
+            let lower = first.starts_with(|c: char| c.is_lowercase());
+            let resolves = known.contains(&first.as_str())
+                || crates.contains(first)
+                || names.defined.contains(first)
+                || (lower && path.len() == 1);
+            let variant = match path.as_slice() {
+                [e, v] if names.enums.contains(e) => {
+                    names.variants.contains(&format!("{}::{}", e, v))
+                }
+                _ => true,
+            };
+            if !resolves || !variant {
+                unresolved.push(path.join("::"));
+            }
+        }
+        unresolved.sort();
+        unresolved.dedup();
+        unresolved
+    }
+
+    #[test]
+    fn parse_spec_pass() {
+        let spec = ProgramSpec::parse("vault.toml", TOML_SPEC, false).unwrap();
+        assert_eq!(
+            spec,
+            ProgramSpec::parse("vault.yaml", YAML_SPEC, true).unwrap()
+        );
+        assert_eq!(spec.accounts[0].name, "Vault");
+        assert_eq!(spec.accounts[0].fields[1].max, Some(32));
+        let names = spec
+            .instructions
+            .iter()
+            .map(|i| i.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Initialize", "Deposit"]);
+        assert_eq!(spec.instructions[1].args[0].name, "amount");
+        assert!(spec.instructions[1].accounts[0].signer);
+        assert_eq!(
+            spec.errors,
+            vec![ErrorDef {
+                name: "NotVaultOwner".into(),
+                message: "Signer does not own the vault".into()
+            }]
+        );
+        let spec = ProgramSpec::parse("ping.yaml", "instructions:\n  Ping:\n", true).unwrap();
+        assert!(spec.instructions[0].args.is_empty());
+    }
+
+    #[test]
+    fn parse_spec_fail() {
+        for (text, yaml) in [
+            ("[instructions.Ping]\n[events.Pinged]\n", false),
+            ("[instructions.Ping]\nsigners = [\"owner\"]\n", false),
+            ("[instructions.ping]\n", false),
+            ("[instructions.Ping]\nargs = [\"amount\"]\n", false),
+            ("[instructions.Ping]\nargs = \"amount:u64\"\n", false),
+            ("[accounts.Vault]\nfields = [\"owner:Pubkey\"]\n", false),
+            ("instructions:\n  Ping:\nerrors:\n  NotOwner: [1]\n", true),
+            ("- Ping\n", true),
+        ] {
+            assert!(
+                matches!(
+                    ProgramSpec::parse("spec", text, yaml),
+                    Err(ProgramError::SpecFormatError(..))
+                ),
+                "{}",
+                text
+            );
+        }
+        assert!(ProgramSpec::parse("spec", "[instructions", false).is_err());
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spec.json");
+        fs::write(&path, "{}").unwrap();
+        assert!(matches!(
+            ProgramSpec::load(&path),
+            Err(ProgramError::SpecFormatError(..))
+        ));
+    }
+
+    #[test]
+    fn generate_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "token-transfer", "foo");
+        let spec_path = dir.path().join("foo.toml");
+        fs::write(&spec_path, TOML_SPEC).unwrap();
+        let spec = ProgramSpec::load(&spec_path).unwrap();
+        // Replacing the handlers of the template
+        let edits = generate(dir.path(), &spec, true).unwrap().0.edits();
+        for (path, _, text) in &edits {
+            syn::parse_file(text).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        assert!(read(LIB_SOURCE).contains("pub mod process;\npub mod state;\n"));
+        assert!(read(STATE_SOURCE).starts_with(STATE_DOC));
+        assert!(read(STATE_SOURCE).contains("pub struct VaultState {"));
+        let process = read(PROCESS_SOURCE);
+        assert!(process.contains(
+            "        ProgramInstruction::Initialize => initialize(program_id, accounts),\n        \
+             ProgramInstruction::Deposit(amount) => deposit(program_id, accounts, amount),\n"
+        ));
+        assert!(!process.contains("transfer"));
+        assert!(read(ERROR_SOURCE).contains("    NotVaultOwner,\n"));
+        assert!(read(TEST_SOURCE).contains("async fn test_deposit_pass() {"));
+        // Generating again from the same spec changes nothing
+        assert!(generate(dir.path(), &spec, false)
+            .unwrap()
+            .0
+            .edits()
+            .is_empty());
+    }
+
+    #[test]
+    fn regenerate_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        let counter = "[instructions.InitializeAccount]\naccounts = [\"counter:mut\"]\n\n\
+                       [instructions.SetContent]\nargs = [\"new_content:u8\"]\n";
+        let spec = ProgramSpec::parse("counter.toml", counter, false).unwrap();
+        // The handlers of the template take no program id
+        assert!(matches!(
+            generate(dir.path(), &spec, false),
+            Err(ProgramError::HandlerConflict(handlers))
+                if handlers == "initialize_account, set_content"
+        ));
+        let (sources, report) = generate(dir.path(), &spec, true).unwrap();
+        assert_eq!(report.kept, vec!["initialize_account", "set_content"]);
+        assert!(report.removed.is_empty());
+        assert_eq!(
+            report.conflicts[1],
+            (
+                "set_content".to_string(),
+                "fn set_content(accounts: &[AccountInfo], new_content: u8) -> ProgramResult"
+                    .to_string(),
+                "fn set_content(_program_id: &Pubkey, _accounts: &[AccountInfo], new_content: u8) \
+                 -> ProgramResult"
+                    .to_string()
+            )
+        );
+        for (path, _, text) in sources.edits() {
+            syn::parse_file(&text).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        assert_eq!(unresolved(dir.path()), Vec::<String>::new());
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        let process = read(PROCESS_SOURCE);
+        assert!(process.contains(
+            "/// SetContent\nfn set_content(_program_id: &Pubkey, accounts: &[AccountInfo], \
+             new_content: u8) -> ProgramResult {\n    let account_info_iter"
+        ));
+        assert!(process
+            .contains("    let previous_content = account_state.set_content(new_content);\n"));
+        // Handwritten helpers stay before the handlers, with their imports
+        assert!(
+            process.find("fn check_account_ownership(") < process.find("fn initialize_account(")
+        );
+        assert!(process.contains("    program_pack::{IsInitialized, Pack},\n"));
+        assert!(read(TEST_SOURCE).contains("async fn test_setting_content_pass() {"));
+        // Dropping an instruction drops its handler and generated test
+        let spec = ProgramSpec::parse(
+            "counter.toml",
+            &counter
+                .replace("SetContent", "Close")
+                .replace("args = [\"new_content:u8\"]", ""),
+            false,
+        )
+        .unwrap();
+        // The handler of the template is written by hand
+        assert!(matches!(
+            generate(dir.path(), &spec, false),
+            Err(ProgramError::HandlerRemoved(handlers)) if handlers == "set_content"
+        ));
+        let (sources, report) = generate(dir.path(), &spec, true).unwrap();
+        // With the tests written by hand which send it
+        assert_eq!(
+            report.removed,
+            vec![
+                "set_content",
+                "test_setting_content_pass",
+                "test_setting_content_not_initialized_fail",
+                "test_set_content_pass"
+            ]
+        );
+        assert!(report.conflicts.is_empty());
+        for (path, _, text) in sources.edits() {
+            fs::write(path, text).unwrap();
+        }
+        assert_eq!(unresolved(dir.path()), Vec::<String>::new());
+        let process = read(PROCESS_SOURCE);
+        assert!(!process.contains("fn set_content("));
+        assert!(process.contains("ProgramAccountState::unpack_unchecked(&account_data)?;"));
+        assert!(process.contains("fn close(_program_id: &Pubkey, _accounts: &[AccountInfo])"));
+        let tests = read(TEST_SOURCE);
+        assert!(!tests.contains("async fn test_set_content_pass() {"));
+        assert!(tests.contains("async fn test_close_pass() {"));
+        // A handler renamed to use its program id keeps its signature
+        fs::write(
+            dir.path().join(PROCESS_SOURCE),
+            process.replace("fn close(_program_id", "fn close(program_id"),
+        )
+        .unwrap();
+        let (sources, report) = generate(dir.path(), &spec, false).unwrap();
+        assert!(sources.edits().is_empty());
+        assert!(report.removed.is_empty() && report.conflicts.is_empty());
+        // A generated handler goes when its instruction is renamed, unless
+        // written since
+        let spec = ProgramSpec::parse(
+            "counter.toml",
+            &counter
+                .replace("SetContent", "Shut")
+                .replace("args = [\"new_content:u8\"]", ""),
+            false,
+        )
+        .unwrap();
+        let (_, report) = generate(dir.path(), &spec, false).unwrap();
+        assert_eq!(report.removed, vec!["close", "test_close_pass"]);
+        let process = read(PROCESS_SOURCE).replace("    Ok(())\n}", "    check()?;\n    Ok(())\n}");
+        fs::write(dir.path().join(PROCESS_SOURCE), &process).unwrap();
+        assert!(matches!(
+            generate(dir.path(), &spec, false),
+            Err(ProgramError::HandlerRemoved(handlers)) if handlers == "close"
+        ));
+    }
+
+    #[test]
+    fn regenerate_wrapped_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        let spec = |args: &str| {
+            let text = format!(
+                "[instructions.InitializeAccount]\naccounts = [\"counter:mut\"]\n\n\
+                 [instructions.Deposit]\nargs = [{}]\naccounts = [\"vault:mut\"]\n",
+                args
+            );
+            ProgramSpec::parse("vault.toml", &text, false).unwrap()
+        };
+        let (sources, _) = generate(dir.path(), &spec("\"amount:u64\""), true).unwrap();
+        for (path, _, text) in sources.edits() {
+            fs::write(path, text).unwrap();
+        }
+        let process = fs::read_to_string(dir.path().join(PROCESS_SOURCE)).unwrap();
+        fs::write(
+            dir.path().join(PROCESS_SOURCE),
+            process.replace("fn deposit(_program_id", "fn deposit(program_id"),
+        )
+        .unwrap();
+        // A new argument rewrites the signature past the line width
+        let spec = spec("\"amount:u64\", \"memo:u8\", \"destination:Pubkey\"");
+        let (sources, report) = generate(dir.path(), &spec, true).unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        let edits = sources.edits();
+        let (_, _, process) = edits
+            .iter()
+            .find(|(path, ..)| path.ends_with(PROCESS_SOURCE))
+            .unwrap();
+        assert!(process.contains(
+            "fn deposit(\n    program_id: &Pubkey,\n    accounts: &[AccountInfo],\n    \
+             amount: u64,\n    memo: u8,\n    destination: Pubkey,\n) -> ProgramResult {\n"
+        ));
+        assert!(process.lines().all(|line| line.len() <= 100));
+        syn::parse_file(process).unwrap();
+    }
+
+    #[test]
+    fn regenerate_written_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "counter", "foo");
+        let spec = "accounts:\n  Vault:\n    fields: [\"owner:Pubkey\"]\n\
+                    instructions:\n  InitializeAccount:\n";
+        let spec = ProgramSpec::parse("vault.yaml", spec, true).unwrap();
+        let (sources, report) = generate(dir.path(), &spec, true).unwrap();
+        // The errors and state of the template are not the spec's
+        assert_eq!(
+            report.carried,
+            vec![
+                "AccountAlreadyInitializedError",
+                "DataVersionMismatchError",
+                "ProgramAccountState",
+                "impl ProgramAccountState",
+                "ACCOUNT_STATE_SPACE",
+                "impl Sealed for ProgramAccountState",
+                "impl IsInitialized for ProgramAccountState",
+                "impl Pack for ProgramAccountState",
+            ]
+        );
+        for (path, _, text) in sources.edits() {
+            fs::write(path, text).unwrap();
+        }
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        let errors = read(ERROR_SOURCE);
+        assert!(errors.contains(
+            "    #[error(\"Account Already Initialized\")]\n    AccountAlreadyInitializedError,\n"
+        ));
+        assert!(errors.contains("            CustomProgramError::DataVersionMismatchError => {\n"));
+        let state = read(STATE_SOURCE);
+        assert_eq!(state.matches("const DATA_VERSION: u8 = 1;").count(), 1);
+        assert!(
+            state.find("pub struct ProgramAccountState {") < state.find("pub struct VaultState {")
+        );
+        assert!(state.contains("impl Pack for ProgramAccountState {"));
+        assert_eq!(unresolved(dir.path()), Vec::<String>::new());
+        // Generating again keeps them as they are
+        let (sources, report) = generate(dir.path(), &spec, true).unwrap();
+        assert!(sources.edits().is_empty());
+        assert_eq!(report.carried.len(), 8);
+    }
+
+    #[test]
+    fn generate_no_errors_pass() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "token-transfer", "foo");
+        fs::write(
+            dir.path().join(ERROR_SOURCE),
+            program_source("error.rs").unwrap(),
+        )
+        .unwrap();
+        let spec = ProgramSpec::parse("ping.yaml", "instructions:\n  Ping:\n", true).unwrap();
+        let edits = generate(dir.path(), &spec, true).unwrap().0.edits();
+        let path = dir.path().join(ERROR_SOURCE);
+        let (.., errors) = edits.iter().find(|(p, ..)| *p == path).unwrap();
+        assert!(errors.contains("        match *self {}\n"));
+        assert!(!dir.path().join(STATE_SOURCE).exists());
+    }
+
+    #[test]
+    fn generate_fail() {
+        let dir = tempdir().unwrap();
+        write_test_program(dir.path(), "minimal", "foo");
+        let spec = ProgramSpec::parse("ping.yaml", "instructions:\n  Ping:\n", true).unwrap();
+        assert!(matches!(
+            generate(dir.path(), &spec, false),
+            Err(ProgramError::SourceError(..))
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+//! Template rendering
+//!
+//! Program sources and manifests are minijinja templates. Besides the
+//! usual `{{ variable }}`, `{% if %}` and `{% for %}` they are rendered
+//! with the variables of `TemplateVars`. Values written into string
+//! literals go through the `toml_string` filter in manifests and the
+//! `format_literal` filter in Rust sources, which quote and escape them.
+
+use crate::{
+    cli::Configuration,
+    error::{CargoResult, ProgramError},
+};
+use heck::{ToKebabCase, ToSnakeCase, ToUpperCamelCase};
+use minijinja::{context, Environment, Value};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// Base58 encoded 32 byte public key
+const PUBKEY_PATTERN: &str = r"^[1-9A-HJ-NP-Za-km-z]{32,44}$";
+
+/// Values available to templates
+#[derive(Debug, Clone)]
+pub struct TemplateVars {
+    pub name: String,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub program_id: Option<String>,
+    pub solana_version: String,
+    /// Variables declared by the template descriptor
+    pub extra: BTreeMap<String, Value>,
+}
+
+impl TemplateVars {
+    /// Variables for program `name` on `solana_version`, other values unset
+    pub fn new(name: &str, solana_version: &str) -> Self {
+        TemplateVars {
+            name: name.to_string(),
+            author: None,
+            license: None,
+            program_id: None,
+            solana_version: solana_version.to_string(),
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Builds the render context. Besides the fields, the name is offered
+    /// as `crate_name` (as cargo names the library), `name_snake`,
+    /// `name_camel` and `name_kebab`. Descriptor variables follow, without
+    /// replacing the built-in ones
+    pub fn context(&self) -> Value {
+        context! {
+            name => self.name,
+            crate_name => self.name.replace('-', "_"),
+            name_snake => self.name.to_snake_case(),
+            name_camel => self.name.to_upper_camel_case(),
+            name_kebab => self.name.to_kebab_case(),
+            author => self.author,
+            license => self.license,
+            program_id => self.program_id,
+            solana_version => self.solana_version,
+            ..Value::from_iter(self.extra.clone())
+        }
+    }
+}
+
+impl From<&Configuration> for TemplateVars {
+    fn from(config: &Configuration) -> Self {
+        TemplateVars {
+            author: config.author.clone(),
+            license: config.license.clone(),
+            program_id: config.program_id.clone(),
+            ..TemplateVars::new(&config.progname, &config.solana_version)
+        }
+    }
+}
+
+/// Checks a program name is a cargo package name whose library name is a
+/// Rust identifier, so it can be written into manifests and sources
+pub fn validate_program_name(name: &str) -> CargoResult<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && syn::parse_str::<syn::Ident>(&name.replace('-', "_")).is_ok();
+    match valid {
+        true => Ok(()),
+        false => Err(ProgramError::InvalidProgramName(name.to_string())),
+    }
+}
+
+/// Checks a program id is a base58 encoded public key
+pub fn validate_program_id(id: &str) -> CargoResult<()> {
+    match Regex::new(PUBKEY_PATTERN).unwrap().is_match(id) {
+        true => Ok(()),
+        false => Err(ProgramError::InvalidProgramId(id.to_string())),
+    }
+}
+
+/// Quotes `value` as a TOML string
+fn toml_string(value: &str) -> String {
+    toml_edit::Value::from(value).to_string()
+}
+
+/// Quotes `value` as a Rust string literal used as a format string, as in
+/// `println!` or `#[error]`, so its braces are literal
+fn format_literal(value: &str) -> String {
+    format!("{:?}", value.replace('{', "{{").replace('}', "}}"))
+}
+
+/// Renders template `source`, named `name` in errors, with `vars`
+pub fn render(name: &str, source: &str, vars: &TemplateVars) -> CargoResult<String> {
+    let mut env = Environment::new();
+    env.set_keep_trailing_newline(true);
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.add_filter("toml_string", toml_string);
+    env.add_filter("format_literal", format_literal);
+    env.render_named_str(name, source, vars.context())
+        .map_err(|e| ProgramError::TemplateRenderError(name.to_string(), e.to_string()))
+}
+
+/// Evaluates a condition expression such as `program_id and license`
+pub fn is_true(expr: &str, vars: &TemplateVars) -> CargoResult<bool> {
+    let env = Environment::new();
+    env.compile_expression(expr)
+        .and_then(|e| e.eval(vars.context()))
+        .map(|v| v.is_true())
+        .map_err(|e| ProgramError::TemplateRenderError(expr.to_string(), e.to_string()))
+}
+
+/// Renders a template file, returning `None` when content is left out
+/// entirely by a condition
+pub fn render_file(name: &str, source: &str, vars: &TemplateVars) -> CargoResult<Option<String>> {
+    let rendered = render(name, source, vars)?;
+    match rendered.trim().is_empty() && !source.trim().is_empty() {
+        true => Ok(None),
+        false => Ok(Some(rendered)),
+    }
+}
+
+/// Openings of template syntax, escaped when turning text into a template
+const SYNTAX_OPENINGS: [&str; 3] = ["{{", "{%", "{#"];
+
+/// Turns text generated for program `name` back into a template, the
+/// reverse of `render`. Each spelling of the name the context offers
+/// becomes its variable, matched as a whole word or as the head of a
+/// camel case word, and any template syntax in the text is escaped. Words
+/// run on through `_` and `-`, and a word after `::` belongs to the path
+/// before it, so `spl-token` or `spl::token` keep their `token`. A spelling
+/// several variables share, as all do for a single word name, goes to the
+/// name in manifests and to the crate name in `rust` sources, where the
+/// name must stay an identifier
+pub fn templatize(text: &str, name: &str, rust: bool) -> String {
+    let vars = TemplateVars::new(name, "");
+    let context = vars.context();
+    let mut spellings: Vec<(&str, String)> = vec![];
+    let order = match rust {
+        true => [
+            "crate_name",
+            "name_snake",
+            "name_camel",
+            "name",
+            "name_kebab",
+        ],
+        false => [
+            "name",
+            "crate_name",
+            "name_camel",
+            "name_snake",
+            "name_kebab",
+        ],
+    };
+    for var in order {
+        let spelling = context
+            .get_attr(var)
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        if !spelling.is_empty() && spellings.iter().all(|(_, s)| *s != spelling) {
+            spellings.push((var, spelling));
+        }
+    }
+    // Longest first so `my_prog` wins over `my` for instance
+    spellings.sort_by_key(|(_, s)| std::cmp::Reverse(s.len()));
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut previous: Option<char> = None;
+    'scan: while let Some(c) = rest.chars().next() {
+        if let Some(opening) = SYNTAX_OPENINGS.iter().find(|o| rest.starts_with(*o)) {
+            out.push_str(&format!("{{{{ \"{}\" }}}}", opening));
+            rest = &rest[opening.len()..];
+            previous = opening.chars().last();
+            continue;
+        }
+        let in_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if !previous.is_some_and(|p| in_word(p) || p == ':') {
+            for (var, spelling) in &spellings {
+                let next = rest[spelling.len().min(rest.len())..].chars().next();
+                if rest.starts_with(spelling.as_str())
+                    && !next.is_some_and(|n| in_word(n) && !n.is_ascii_uppercase())
+                {
+                    out.push_str(&format!("{{{{ {} }}}}", var));
+                    rest = &rest[spelling.len()..];
+                    previous = spelling.chars().last();
+                    continue 'scan;
+                }
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+        previous = Some(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_variables_pass() {
+        let mut vars = TemplateVars::new("my-prog", "1.16.0");
+        vars.author = Some("Jo <jo@example.com>".to_string());
+        let out = render(
+            "t",
+            "{{ name }} {{ crate_name }} {{ name_snake }} {{ name_camel }} {{ name_kebab }} \
+             {{ author }} {{ solana_version }}\n",
+            &vars,
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "my-prog my_prog my_prog MyProg my-prog Jo <jo@example.com> 1.16.0\n"
+        );
+    }
+
+    #[test]
+    fn render_conditions_and_loops_pass() {
+        let mut vars = TemplateVars::new("foo", "1.16.0");
+        let errors = ["AccountAlreadyInitializedError", "DataVersionMismatchError"]
+            .iter()
+            .map(|name| context! { name })
+            .collect::<Vec<_>>();
+        vars.extra.insert("errors".to_string(), Value::from(errors));
+        vars.extra
+            .insert("name".to_string(), Value::from("shadowed"));
+        assert!(!is_true("license", &vars).unwrap());
+        assert!(is_true("errors | length == 2 and name == 'foo'", &vars).unwrap());
+        let source = "enum E {\n\
+            {% for e in errors %}\n    {{ e.name }},\n{% endfor %}\n\
+            }\n\
+            {% if license %}license = \"{{ license }}\"\n{% endif %}";
+        assert_eq!(
+            render("t", source, &vars).unwrap(),
+            "enum E {\n    AccountAlreadyInitializedError,\n    DataVersionMismatchError,\n}\n"
+        );
+    }
+
+    #[test]
+    fn render_file_conditional_pass() {
+        let mut vars = TemplateVars::new("foo", "1.16.0");
+        let source = "{% if program_id %}declare_id!(\"{{ program_id }}\");\n{% endif %}";
+        assert_eq!(render_file("id.rs", source, &vars).unwrap(), None);
+        assert_eq!(
+            render_file("empty.rs", "", &vars).unwrap(),
+            Some(String::new())
+        );
+        vars.program_id = Some("11111111111111111111111111111111".to_string());
+        assert_eq!(
+            render_file("id.rs", source, &vars).unwrap(),
+            Some("declare_id!(\"11111111111111111111111111111111\");\n".to_string())
+        );
+    }
+
+    #[test]
+    fn render_escaped_pass() {
+        let mut vars = TemplateVars::new("foo", "1.16.0");
+        vars.author = Some("Jo \"JJ\" <jo@example.com>\\".to_string());
+        vars.license = Some("MIT\nApache".to_string());
+        let source = "[package]\nauthors = [{{ author | toml_string }}]\n\
+            license = {{ license | toml_string }}\n";
+        let manifest = render("Cargo.toml", source, &vars).unwrap();
+        let doc = manifest.parse::<toml_edit::DocumentMut>().unwrap();
+        assert_eq!(
+            doc["package"]["authors"][0].as_str(),
+            vars.author.as_deref()
+        );
+        assert_eq!(doc["package"]["license"].as_str(), vars.license.as_deref());
+        vars.extra
+            .insert("message".to_string(), Value::from("Bad \"{key}\"\\"));
+        let out = render("e.rs", "println!({{ message | format_literal }})", &vars).unwrap();
+        assert_eq!(out, r#"println!("Bad \"{{key}}\"\\")"#);
+        syn::parse_str::<syn::Macro>(&out).unwrap();
+    }
+
+    #[test]
+    fn render_fail() {
+        let vars = TemplateVars::new("foo", "1.16.0");
+        assert!(matches!(
+            render("bad.rs", "{% if %}", &vars),
+            Err(ProgramError::TemplateRenderError(name, _)) if name == "bad.rs"
+        ));
+    }
+
+    #[test]
+    fn templatize_pass() {
+        let text = "[package]\nname = \"my-prog\"\n\n\
+            //! my_prog: MyProgError, MyProgram, my_program\n\
+            println!(\"{{}}\", x); {# {%\n";
+        let template = templatize(text, "my-prog", false);
+        assert_eq!(
+            template,
+            "[package]\nname = \"{{ name }}\"\n\n\
+             //! {{ crate_name }}: {{ name_camel }}Error, MyProgram, my_program\n\
+             println!(\"{{ \"{{\" }}}}\", x); {{ \"{#\" }} {{ \"{%\" }}\n"
+        );
+        // Rendering for the same name gives the text back
+        assert_eq!(
+            render("t", &template, &TemplateVars::new("my-prog", "")).unwrap(),
+            text
+        );
+        assert_eq!(
+            render("t", &template, &TemplateVars::new("vault", "")).unwrap(),
+            text.replace("my-prog", "vault")
+                .replace("my_prog:", "vault:")
+                .replace("MyProgError", "VaultError")
+        );
+        // Dependencies spelled with the name are theirs
+        let text = "[package]\nname = \"token\"\n\n[dependencies]\nspl-token = \"4.0\"\n\
+            token-metadata = \"1.0\"\n";
+        assert_eq!(
+            templatize(text, "token", false),
+            "[package]\nname = \"{{ name }}\"\n\n[dependencies]\nspl-token = \"4.0\"\n\
+             token-metadata = \"1.0\"\n"
+        );
+        let text = "use spl_token::instruction;\nlet id = spl::token::id();\n\
+            token::process(token_id);\nstruct TokenState;\nconst NAME: &str = \"token\";\n";
+        let template = templatize(text, "token", true);
+        assert_eq!(
+            template,
+            "use spl_token::instruction;\nlet id = spl::token::id();\n\
+             {{ crate_name }}::process(token_id);\nstruct {{ name_camel }}State;\n\
+             const NAME: &str = \"{{ crate_name }}\";\n"
+        );
+        // Sources of a single word name stay Rust for hyphenated names
+        assert_eq!(
+            render("t", &template, &TemplateVars::new("my-token", "")).unwrap(),
+            "use spl_token::instruction;\nlet id = spl::token::id();\n\
+             my_token::process(token_id);\nstruct MyTokenState;\nconst NAME: &str = \"my_token\";\n"
+        );
+    }
+
+    #[test]
+    fn validate_program_name_pass() {
+        for name in ["foo", "my-prog", "my_prog2", "_prog"] {
+            validate_program_name(name).unwrap();
+        }
+        for name in [
+            "", "a\"b", "my prog", "1abc", "fn", "prog.rs", "-prog", "prög",
+        ] {
+            assert!(
+                matches!(
+                    validate_program_name(name),
+                    Err(ProgramError::InvalidProgramName(n)) if n == name
+                ),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn validate_program_id_pass() {
+        assert!(validate_program_id("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").is_ok());
+        assert!(validate_program_id("not-a-key").is_err());
+        assert!(validate_program_id("0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl").is_err());
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by the tests of several modules
+
+use crate::{
+    descriptor::{Contents, Descriptor, TemplateSource},
+    error::CargoResult,
+    template::TemplateVars,
+    utils::builtin_template,
+};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Renders `source` with the default value of each descriptor variable into
+/// its text files, keyed by destination
+pub fn render_test_program(
+    source: &TemplateSource,
+    vars: &TemplateVars,
+) -> CargoResult<HashMap<String, String>> {
+    let mut vars = vars.clone();
+    let descriptor = Descriptor::load(source)?;
+    vars.extra = descriptor.resolve_variables(&[], None)?;
+    Ok(descriptor
+        .render_files(source, &vars)?
+        .into_iter()
+        .filter_map(|(dest, contents)| match contents {
+            Contents::Text(text) => Some((dest, text)),
+            Contents::Binary(_) => None,
+        })
+        .collect())
+}
+
+/// Renders built-in template `template` as program `name` into `dir`
+pub fn write_test_program(dir: &Path, template: &str, name: &str) {
+    let source = builtin_template(template).unwrap();
+    let vars = TemplateVars::new(name, "1.18.0");
+    for (dest, text) in render_test_program(&source, &vars).unwrap() {
+        let path = dir.join(dest);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+}
@@ -1,143 +1,483 @@
 //! Utility functions
 
-use crate::error::{CargoResult, ProgramError};
-use cargo_toml::{Dependency, Manifest};
-use dirs::home_dir;
-use regex::Regex;
-use std::{collections::HashMap, fs::File, io::Read, path::PathBuf, str};
-use yaml_rust::YamlLoader;
-
-/// Mac and linux solana install location for current active version
-const SOLANA_INSTALL: &str = ".local/share/solana/install/active_release/version.yml";
-/// Keyword to the version.yml semver version id
-const VER_STRING: &str = "channel";
-/// Regex Substitution variable
-const PROG_IDENTIFIER: &str = r"PROGNAME";
-
-/// Load entry point template and substitute in program name
-fn get_entry_point_resource(new_name: String) -> String {
-    let in_str = str::from_utf8(include_bytes!("../resources/program/entry_point.rs")).unwrap();
-    let re = Regex::new(PROG_IDENTIFIER).unwrap();
-    str::from_utf8(re.replace_all(in_str, new_name).as_bytes())
-        .unwrap()
-        .to_string()
-}
+use crate::{
+    archive::archive_template,
+    cli::Configuration,
+    compat::compatible_versions,
+    descriptor::{EmbeddedFiles, TemplateSource},
+    error::CargoResult,
+    template::{render, TemplateVars},
+    workspace::{set_dependency_version, set_solana_versions},
+};
+use std::{fs, path::Path, str};
+use toml_edit::DocumentMut;
 
-/// Load the program error.rs resource file
-fn get_error_resource() -> String {
-    str::from_utf8(include_bytes!("../resources/program/error.rs"))
-        .unwrap()
-        .to_string()
-}
-/// Load the program instruction.rs resource file
-fn get_instruction_resource() -> String {
-    str::from_utf8(include_bytes!("../resources/program/instruction.rs"))
-        .unwrap()
-        .to_string()
-}
-/// Load the program process.rs resource file
-fn get_process_resource() -> String {
-    str::from_utf8(include_bytes!("../resources/program/process.rs"))
-        .unwrap()
-        .to_string()
+/// Program dependencies which may be hoisted into `[workspace.dependencies]`
+pub const SHARED_DEPENDENCIES: [&str; 7] = [
+    "borsh",
+    "num-derive",
+    "num-traits",
+    "solana-program",
+    "solana-program-test",
+    "solana-sdk",
+    "thiserror",
+];
+/// Embeds template files, each taken from the folder given before it
+/// below `resources`
+macro_rules! template_files {
+    ($($dir:literal: [$($file:literal),* $(,)?]),* $(,)?) => {
+        &[$($(
+            ($file, include_bytes!(concat!("../resources/", $dir, "/", $file)) as &[u8]),
+        )*)*]
+    };
 }
-/// Load the program state.rs resource file
-fn get_state_resource() -> String {
-    str::from_utf8(include_bytes!("../resources/program/state.rs"))
-        .unwrap()
-        .to_string()
-}
-/// Load the program lib.rs resource file
-fn get_lib_resource() -> String {
-    str::from_utf8(include_bytes!("../resources/program/lib.rs"))
-        .unwrap()
-        .to_string()
+
+/// Files of the built-in program template
+pub const PROGRAM_TEMPLATE: EmbeddedFiles = template_files!(
+    "program": [
+        "template.toml",
+        "prog.cargo.toml",
+        "entry_point.rs",
+        "error.rs",
+        "instruction.rs",
+        "lib.rs",
+        "process.rs",
+        "state.rs",
+    ],
+);
+
+/// Built-in template used without `--template`
+pub const DEFAULT_TEMPLATE: &str = "counter";
+
+/// Built-in templates by name. Flavors share the files of the program
+/// template they do not replace
+pub const BUILTIN_TEMPLATES: [(&str, EmbeddedFiles); 6] = [
+    (
+        "minimal",
+        template_files!("templates/minimal": ["template.toml", "prog.cargo.toml", "lib.rs"]),
+    ),
+    ("counter", PROGRAM_TEMPLATE),
+    (
+        "vault",
+        template_files!(
+            "templates/vault": ["template.toml", "entry_point.rs", "process.rs", "state.rs"],
+            "program": ["prog.cargo.toml", "error.rs", "instruction.rs", "lib.rs"],
+        ),
+    ),
+    (
+        "escrow",
+        template_files!(
+            "templates/escrow": ["template.toml", "entry_point.rs", "process.rs", "state.rs"],
+            "program": ["prog.cargo.toml", "error.rs", "instruction.rs", "lib.rs"],
+        ),
+    ),
+    (
+        "token-transfer",
+        template_files!(
+            "templates/token-transfer": [
+                "template.toml",
+                "prog.cargo.toml",
+                "entry_point.rs",
+                "lib.rs",
+                "process.rs",
+            ],
+            "program": ["error.rs", "instruction.rs"],
+        ),
+    ),
+    (
+        "registry",
+        template_files!(
+            "templates/registry": ["template.toml", "entry_point.rs", "process.rs", "state.rs"],
+            "program": ["prog.cargo.toml", "error.rs", "instruction.rs", "lib.rs"],
+        ),
+    ),
+];
+
+/// Looks up a built-in template by name
+pub fn builtin_template(name: &str) -> Option<TemplateSource> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, files)| TemplateSource::Embedded(files))
 }
 
-/// Collect all program resource files into a map
-pub fn get_program_resources(new_name: String) -> HashMap<&'static str, String> {
-    let mut prog_resources = HashMap::<&str, String>::new();
-    prog_resources.insert("entry_point.rs", get_entry_point_resource(new_name));
-    prog_resources.insert("error.rs", get_error_resource());
-    prog_resources.insert("instruction.rs", get_instruction_resource());
-    prog_resources.insert("lib.rs", get_lib_resource());
-    prog_resources.insert("process.rs", get_process_resource());
-    prog_resources.insert("state.rs", get_state_resource());
-    prog_resources
+/// Resolves a template given by the user, a git revision `repo#revision`
+/// or a tarball, a folder relative to `base`, the name of a built-in
+/// template or else of a user template in `user_dir`. An existing folder
+/// takes precedence over names
+pub fn resolve_template(
+    template: &str,
+    base: &Path,
+    user_dir: Option<&Path>,
+) -> CargoResult<TemplateSource> {
+    if let Some(archive) = archive_template(template, base)? {
+        return Ok(archive);
+    }
+    let dir = base.join(template);
+    if dir.is_dir() {
+        return Ok(TemplateSource::Folder(dir));
+    }
+    Ok(
+        match (
+            builtin_template(template),
+            user_dir.map(|d| d.join(template)),
+        ) {
+            (Some(builtin), _) => builtin,
+            (None, Some(user)) if user.is_dir() => TemplateSource::Folder(user),
+            _ => TemplateSource::Folder(dir),
+        },
+    )
 }
 
-/// Locates the solana install, returns Option<active version string> if found
-/// otherwise None
-pub fn get_solana_installed_version() -> CargoResult<String> {
-    match home_dir() {
-        Some(p) => {
-            let mut path = PathBuf::new();
-            path.push(p);
-            path.push(SOLANA_INSTALL);
-            match path.exists() {
-                true => {
-                    let mut contents = String::new();
-                    let mut file = File::open(path)?;
-                    file.read_to_string(&mut contents)?;
-                    let docs = YamlLoader::load_from_str(&contents)?;
-                    let res = docs[0][VER_STRING].as_str().unwrap();
-                    Ok(res[1..].to_string())
-                }
-                false => Err(ProgramError::SolanaNotFound(path.display().to_string())),
-            }
+/// User templates by name, one folder each in `user_dir`
+pub fn user_templates(user_dir: &Path) -> CargoResult<Vec<(String, TemplateSource)>> {
+    if !user_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut templates = vec![];
+    for entry in fs::read_dir(user_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Hidden folders, such as staging of a template being saved, are left out
+        if path.is_dir() && !name.starts_with('.') {
+            templates.push((name.to_string(), TemplateSource::Folder(path.clone())));
         }
-        None => Err(ProgramError::NoHomeFound),
     }
+    templates.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(templates)
+}
+
+/// Source of the program template, the configured one or the default
+pub fn program_template(config: &Configuration) -> TemplateSource {
+    match &config.template {
+        Some(template) => template.clone(),
+        None => builtin_template(DEFAULT_TEMPLATE).unwrap(),
+    }
+}
+
+/// Renders the resource program cargo file and finishes it as below
+pub fn build_program_manifest(vars: &TemplateVars) -> CargoResult<DocumentMut> {
+    finish_program_manifest(program_template_as_manifest(vars)?, vars)
 }
 
-/// Loads the resource program cargo file and substitute in the
-/// versions of Solana for dependencies and dev-dependencies
-pub fn build_program_manifest(name: String) -> CargoResult<Manifest> {
-    // Get version substitution variable
-    let solver = get_solana_installed_version()?;
-
-    // Load program template and substitute placeholders
-    let mut prog_man = program_template_as_manifest()?;
-    let deps = &mut prog_man.dependencies;
-    let dev_deps = &mut prog_man.dev_dependencies;
-    match &mut prog_man.package {
-        Some(p) => p.name = name.to_string(),
-        None => todo!(),
-    }
-    *deps.get_mut("solana-program").unwrap() = Dependency::Simple(solver.clone());
-    *dev_deps.get_mut("solana-program-test").unwrap() = Dependency::Simple(solver.clone());
-    *dev_deps.get_mut("solana-sdk").unwrap() = Dependency::Simple(solver);
+/// Sets the versions of Solana for dependencies and dev-dependencies of a
+/// rendered program manifest, along with the versions of other crates
+/// compatible with that release
+pub fn finish_program_manifest(
+    mut prog_man: DocumentMut,
+    vars: &TemplateVars,
+) -> CargoResult<DocumentMut> {
+    let solver = vars.solana_version.as_str();
+    set_solana_versions(&mut prog_man, solver);
+    for (krate, version) in compatible_versions(solver)? {
+        set_dependency_version(&mut prog_man, &krate, &version);
+    }
     Ok(prog_man)
 }
 
 #[inline]
-/// Loads the program template from resources
-pub fn program_template_as_manifest() -> CargoResult<Manifest> {
-    // Load template and substitute placeholders
-    Ok(Manifest::from_str(
-        str::from_utf8(include_bytes!("../resources/program/prog.cargo.toml")).unwrap(),
-    )?)
+/// Renders the program template from resources
+pub fn program_template_as_manifest(vars: &TemplateVars) -> CargoResult<DocumentMut> {
+    // Parse the rendered template keeping its layout and comments
+    let source = str::from_utf8(include_bytes!("../resources/program/prog.cargo.toml")).unwrap();
+    Ok(render("prog.cargo.toml", source, vars)?.parse::<DocumentMut>()?)
 }
 
 #[inline]
-/// Loads the project template from resources
-pub fn project_template_as_manifest() -> CargoResult<Manifest> {
-    // Load template and substitute placeholders
-    Ok(Manifest::from_str(
-        str::from_utf8(include_bytes!("../resources/proj.cargo.toml")).unwrap(),
-    )?)
+/// Renders the project template from resources
+pub fn build_project_manifest(vars: &TemplateVars) -> CargoResult<String> {
+    let source = str::from_utf8(include_bytes!("../resources/proj.cargo.toml")).unwrap();
+    render("proj.cargo.toml", source, vars)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        descriptor::{Descriptor, DESCRIPTOR_FILE, MANIFEST_DEST},
+        error::ProgramError,
+        scaffold::{
+            ERROR_ENUM, ERROR_SOURCE, INSTRUCTION_ENUM, INSTRUCTION_SOURCE, PROCESS_SOURCE,
+        },
+        test_support::render_test_program,
+    };
+
+    #[test]
+    fn build_program_manifest_pass() {
+        let manifest = build_program_manifest(&TemplateVars::new("foo", "1.10.0")).unwrap();
+        assert_eq!(manifest["package"]["name"].as_str(), Some("foo"));
+        assert_eq!(
+            manifest["dependencies"]["solana-program"].as_str(),
+            Some("1.10.0")
+        );
+        assert_eq!(
+            manifest["dev-dependencies"]["solana-sdk"].as_str(),
+            Some("1.10.0")
+        );
+        assert_eq!(manifest["dependencies"]["borsh"].as_str(), Some("0.9.3"));
+        // Template comments are kept
+        assert!(manifest
+            .to_string()
+            .contains("# Add additional dependencies Here if needed"));
+    }
+
     #[test]
-    fn test_version_info_pass() {
-        assert!(get_solana_installed_version().unwrap().len() > 0);
+    fn build_program_manifest_compat_pass() {
+        let manifest = build_program_manifest(&TemplateVars::new("foo", "1.16.3")).unwrap();
+        assert_eq!(manifest["dependencies"]["borsh"].as_str(), Some("0.10.3"));
+        assert_eq!(manifest["dependencies"]["num-derive"].as_str(), Some("0.3"));
+        assert_eq!(
+            manifest["dev-dependencies"]["solana-program-test"].as_str(),
+            Some("1.16.3")
+        );
+    }
+
+    #[test]
+    fn template_resources_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("state")).unwrap();
+        fs::write(dir.path().join("lib.rs"), "//! PROGNAME program\n").unwrap();
+        fs::write(
+            dir.path().join("state/mod.rs"),
+            "pub struct {{ name_camel }};\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("id.rs"), "{% if program_id %}id{% endif %}").unwrap();
+        let source = TemplateSource::Folder(dir.path().to_path_buf());
+        let resources = render_test_program(&source, &TemplateVars::new("foo-bar", "1.10.0"));
+        let resources = resources.unwrap();
+        assert_eq!(resources.len(), 2);
+        assert_eq!(resources["src/lib.rs"], "//! foo_bar program\n");
+        assert_eq!(resources["src/state/mod.rs"], "pub struct FooBar;\n");
+    }
+
+    #[test]
+    fn template_resources_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let vars = TemplateVars::new("foo", "1.10.0");
+        assert!(matches!(
+            render_test_program(&TemplateSource::Folder(dir.path().into()), &vars),
+            Err(ProgramError::TemplateEmpty(_))
+        ));
+        assert!(matches!(
+            render_test_program(&TemplateSource::Folder(dir.path().join("missing")), &vars),
+            Err(ProgramError::TemplateNotFound(_))
+        ));
     }
 
     #[test]
     fn entry_point_pass() {
-        println!("{:?}", get_entry_point_resource("foo".to_string()));
+        let source = TemplateSource::Embedded(PROGRAM_TEMPLATE);
+        let resources =
+            render_test_program(&source, &TemplateVars::new("foo-bar", "1.10.0")).unwrap();
+        assert!(resources["src/entry_point.rs"].contains("const NAME: &str = \"foo_bar\";"));
+    }
+
+    #[test]
+    fn program_resources_pass() {
+        let source = TemplateSource::Embedded(PROGRAM_TEMPLATE);
+        let mut vars = TemplateVars::new("foo", "1.10.0");
+        let resources = render_test_program(&source, &vars).unwrap();
+        assert_eq!(resources.len(), 7);
+        assert!(resources["src/instruction.rs"].contains(
+            "pub enum ProgramInstruction {\n    InitializeAccount,\n    SetContent(u8),\n}"
+        ));
+        assert!(resources["src/error.rs"].contains(
+            "    #[error(\"Data version mismatch\")]\n    DataVersionMismatchError,\n    // Add custom errors here"
+        ));
+        assert!(!resources["src/lib.rs"].contains("declare_id"));
+        vars.program_id = Some("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string());
+        let resources = render_test_program(&source, &vars).unwrap();
+        assert!(resources["src/lib.rs"]
+            .contains("declare_id!(\"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA\");"));
+    }
+
+    #[test]
+    fn build_program_manifest_metadata_pass() {
+        let mut vars = TemplateVars::new("foo", "1.10.0");
+        let manifest = build_program_manifest(&vars).unwrap();
+        assert!(manifest["package"].get("license").is_none());
+        vars.author = Some("Jo <jo@example.com>".to_string());
+        vars.license = Some("Apache-2.0".to_string());
+        let manifest = build_program_manifest(&vars).unwrap();
+        assert_eq!(manifest["package"]["license"].as_str(), Some("Apache-2.0"));
+        assert!(manifest
+            .to_string()
+            .contains("edition = \"2021\"\nauthors = [\"Jo <jo@example.com>\"]\nlicense"));
+    }
+
+    #[test]
+    fn builtin_templates_pass() {
+        let mut vars = TemplateVars::new("foo-bar", "1.18.0");
+        vars.program_id = Some("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string());
+        for (name, files) in BUILTIN_TEMPLATES {
+            let source = TemplateSource::Embedded(files);
+            let descriptor = Descriptor::load(&source).unwrap();
+            assert_eq!(descriptor.name, name);
+            assert!(!descriptor.description.is_empty(), "{}", name);
+            // Every embedded file is generated
+            for (file, _) in files.iter().filter(|(f, _)| *f != DESCRIPTOR_FILE) {
+                assert!(
+                    descriptor.files.iter().any(|f| f.source == *file),
+                    "{} does not use {}",
+                    name,
+                    file
+                );
+            }
+            let resources = render_test_program(&source, &vars).unwrap();
+            assert_eq!(resources.len(), descriptor.files.len(), "{}", name);
+            let manifest = resources[MANIFEST_DEST].parse::<DocumentMut>().unwrap();
+            let manifest = finish_program_manifest(manifest, &vars).unwrap();
+            assert_eq!(manifest["package"]["name"].as_str(), Some("foo-bar"));
+            assert!(resources["src/lib.rs"].contains("declare_id!"), "{}", name);
+            assert!(!resources.values().any(|r| r.contains("{{")), "{}", name);
+        }
+    }
+
+    /// Variants of the enums of a source, and the variants of
+    /// `ProgramInstruction` and `CustomProgramError` its match arms name,
+    /// with the `unwrap` calls outside its tests
+    #[derive(Default)]
+    struct Dispatch {
+        variants: Vec<String>,
+        arms: Vec<String>,
+        unwraps: usize,
+    }
+
+    impl<'a> syn::visit::Visit<'a> for Dispatch {
+        fn visit_item_mod(&mut self, module: &'a syn::ItemMod) {
+            if module.ident != "tests" {
+                syn::visit::visit_item_mod(self, module);
+            }
+        }
+
+        fn visit_item_enum(&mut self, item: &'a syn::ItemEnum) {
+            for variant in &item.variants {
+                self.variants
+                    .push(format!("{}::{}", item.ident, variant.ident));
+            }
+        }
+
+        fn visit_arm(&mut self, arm: &'a syn::Arm) {
+            let path = match &arm.pat {
+                syn::Pat::Path(p) => Some(&p.path),
+                syn::Pat::TupleStruct(p) => Some(&p.path),
+                _ => None,
+            };
+            if let Some(path) = path.filter(|p| p.segments.len() == 2) {
+                let (e, v) = (&path.segments[0].ident, &path.segments[1].ident);
+                if e == INSTRUCTION_ENUM || e == ERROR_ENUM {
+                    self.arms.push(format!("{}::{}", e, v));
+                }
+            }
+            syn::visit::visit_arm(self, arm);
+        }
+
+        fn visit_expr_method_call(&mut self, call: &'a syn::ExprMethodCall) {
+            self.unwraps += usize::from(call.method == "unwrap");
+            syn::visit::visit_expr_method_call(self, call);
+        }
+    }
+
+    /// Checks the sources of the built-in templates hang together. Their
+    /// `solana-program-test` tests in `entry_point.rs` need the Solana
+    /// toolchain, they run with `cargo test-sbf` in a program rendered with
+    /// `cargo solana template render <TEMPLATE> --out <FOLDER>`
+    #[test]
+    fn builtin_template_programs_pass() {
+        let vars = TemplateVars::new("foo", "1.18.0");
+        for (name, files) in BUILTIN_TEMPLATES {
+            let resources = render_test_program(&TemplateSource::Embedded(files), &vars).unwrap();
+            if !resources.contains_key(INSTRUCTION_SOURCE) {
+                continue;
+            }
+            let dispatch = |file: &str| {
+                let mut dispatch = Dispatch::default();
+                let file = syn::parse_file(&resources[file]).unwrap();
+                syn::visit::Visit::visit_file(&mut dispatch, &file);
+                dispatch
+            };
+            // Unpacking fails on bad data rather than panicking, and every
+            // instruction is unpacked and processed
+            let instruction = dispatch(INSTRUCTION_SOURCE);
+            assert!(!instruction.variants.is_empty(), "{}", name);
+            assert_eq!(instruction.unwraps, 0, "{}", name);
+            assert_eq!(instruction.arms, instruction.variants, "{}", name);
+            assert_eq!(
+                dispatch(PROCESS_SOURCE).arms,
+                instruction.variants,
+                "{}",
+                name
+            );
+            // Every error is printed
+            let error = dispatch(ERROR_SOURCE);
+            assert!(!error.variants.is_empty(), "{}", name);
+            assert_eq!(error.arms, error.variants, "{}", name);
+        }
+    }
+
+    #[test]
+    fn builtin_template_dependencies_pass() {
+        let source = builtin_template("token-transfer").unwrap();
+        let vars = TemplateVars::new("foo", "1.14.17");
+        let manifest = render_test_program(&source, &vars).unwrap()[MANIFEST_DEST].parse();
+        let manifest = finish_program_manifest(manifest.unwrap(), &vars).unwrap();
+        let spl_token = manifest["dependencies"]["spl-token"]
+            .as_inline_table()
+            .unwrap();
+        assert_eq!(spl_token["version"].as_str(), Some("3.5"));
+        assert!(spl_token.get("features").is_some());
+    }
+
+    #[test]
+    fn resolve_template_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            resolve_template("escrow", dir.path(), None).unwrap(),
+            builtin_template("escrow").unwrap()
+        );
+        assert_eq!(
+            resolve_template("house", dir.path(), None).unwrap(),
+            TemplateSource::Folder(dir.path().join("house"))
+        );
+        // A folder named like a built-in template takes precedence
+        fs::create_dir(dir.path().join("escrow")).unwrap();
+        assert_eq!(
+            resolve_template("escrow", dir.path(), None).unwrap(),
+            TemplateSource::Folder(dir.path().join("escrow"))
+        );
+        assert!(builtin_template(DEFAULT_TEMPLATE).is_some());
+    }
+
+    #[test]
+    fn user_templates_pass() {
+        let base = tempfile::tempdir().unwrap();
+        let user = tempfile::tempdir().unwrap();
+        assert!(user_templates(&user.path().join("missing"))
+            .unwrap()
+            .is_empty());
+        fs::create_dir(user.path().join("house")).unwrap();
+        fs::create_dir(user.path().join("counter")).unwrap();
+        fs::write(user.path().join("notes.txt"), "").unwrap();
+        fs::create_dir(user.path().join(".staging")).unwrap();
+        let names = user_templates(user.path())
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["counter", "house"]);
+        // User templates come after folders and built-in templates
+        assert_eq!(
+            resolve_template("house", base.path(), Some(user.path())).unwrap(),
+            TemplateSource::Folder(user.path().join("house"))
+        );
+        assert_eq!(
+            resolve_template("counter", base.path(), Some(user.path())).unwrap(),
+            builtin_template("counter").unwrap()
+        );
+        assert_eq!(
+            resolve_template("other", base.path(), Some(user.path())).unwrap(),
+            TemplateSource::Folder(base.path().join("other"))
+        );
     }
 }
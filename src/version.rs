@@ -0,0 +1,567 @@
+//! Solana version resolution
+//!
+//! The version used for the generated solana dependencies is taken from
+//! the first of these sources that yields one:
+//!
+//! 1. `--solana-version` on the command line
+//! 2. `SOLANA_VERSION` environment variable
+//! 3. The default install's `active_release/version.yml`
+//! 4. `solana --version`
+//! 5. `cargo-build-sbf --version`
+//! 6. `active_release/version.yml` below `SOLANA_INSTALL_ROOT`
+//!
+//! When adding a program to an existing workspace, a version the workspace
+//! already uses, from Cargo.lock or its manifests, takes the place of the
+//! installed one (3 to 6) so a single Cargo.lock does not mix releases.
+
+use crate::{
+    error::{CargoResult, ProgramError},
+    workspace::workspace_members,
+};
+use dirs::home_dir;
+use regex::Regex;
+use semver::Version;
+use std::{
+    env,
+    ffi::OsStr,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use toml_edit::{DocumentMut, Item};
+use yaml_rust::YamlLoader;
+
+/// Mac and linux solana install root
+const SOLANA_INSTALL: &str = ".local/share/solana/install";
+/// Version file of the active release below an install root
+const ACTIVE_RELEASE_VERSION: &str = "active_release/version.yml";
+/// Keyword to the version.yml semver version id
+const VER_STRING: &str = "channel";
+/// Other version.yml keywords which may carry the release version when
+/// the channel is a name such as `stable`, `beta` or `edge`
+const RELEASE_STRINGS: [&str; 2] = ["release", "target"];
+/// Binaries folder of the active release below an install root
+const ACTIVE_RELEASE_BIN: &str = "active_release/bin";
+/// Environment variable overriding the Solana version
+pub const SOLANA_VERSION_ENV: &str = "SOLANA_VERSION";
+/// Environment variable naming a custom Solana install root
+pub const SOLANA_INSTALL_ROOT_ENV: &str = "SOLANA_INSTALL_ROOT";
+/// Tools whose `--version` output reports the Solana release
+const VERSION_COMMANDS: [&str; 2] = ["solana", "cargo-build-sbf"];
+/// Crates whose versions in a workspace pin the Solana release
+const WORKSPACE_SOLANA_CRATES: [&str; 2] = ["solana-program", "solana-sdk"];
+/// Manifest tables searched for the workspace solana crates
+const WORKSPACE_DEPENDENCY_TABLES: [&str; 2] = ["dependencies", "dev-dependencies"];
+
+/// A solana crate version found in the workspace
+#[derive(Debug, PartialEq)]
+pub struct VersionUse {
+    /// File the version was found in
+    pub source: PathBuf,
+    /// Crate name
+    pub krate: String,
+    /// Version, completed to X.Y.Z for partial requirements
+    pub version: String,
+}
+
+/// Extracts the first `X.Y.Z` version, ignoring a leading `v`
+pub fn parse_version(text: &str) -> Option<String> {
+    let re = Regex::new(r"\bv?(\d+\.\d+\.\d+)\b").unwrap();
+    re.captures(text).map(|c| c[1].to_string())
+}
+
+/// Normalizes `text` to a semver version if it is one, ignoring a leading `v`
+fn semver_of(text: &str) -> Option<String> {
+    Version::parse(text.trim().trim_start_matches('v'))
+        .ok()
+        .map(|v| v.to_string())
+}
+
+/// Validates a version given by the user
+fn explicit_version(text: &str) -> CargoResult<String> {
+    semver_of(text).ok_or_else(|| ProgramError::InvalidSolanaVersion(text.to_string()))
+}
+
+/// Reads the active release version from the version.yml of an install root.
+/// A named channel is resolved through the other version.yml fields and
+/// then through the binaries of the active release
+pub fn version_from_install(install_root: &Path) -> CargoResult<String> {
+    install_version_with(install_root, |c| version_from_command(c))
+}
+
+/// Reads the install version, running the release binaries through `run`
+fn install_version_with<F>(install_root: &Path, run: F) -> CargoResult<String>
+where
+    F: Fn(&Path) -> Option<String>,
+{
+    let path = install_root.join(ACTIVE_RELEASE_VERSION);
+    if !path.exists() {
+        return Err(ProgramError::SolanaNotFound(path.display().to_string()));
+    }
+    let mut contents = String::new();
+    let mut file = File::open(&path)?;
+    file.read_to_string(&mut contents)?;
+    let docs = YamlLoader::load_from_str(&contents)?;
+    let channel = docs
+        .first()
+        .and_then(|d| d[VER_STRING].as_str())
+        .ok_or_else(|| ProgramError::SolanaVersionMissing(path.display().to_string()))?;
+    if let Some(v) = semver_of(channel) {
+        return Ok(v);
+    }
+    for field in RELEASE_STRINGS {
+        if let Some(v) = docs[0][field].as_str().and_then(semver_of) {
+            return Ok(v);
+        }
+    }
+    let bin = install_root.join(ACTIVE_RELEASE_BIN);
+    for command in VERSION_COMMANDS {
+        if let Some(v) = run(&bin.join(command)) {
+            return Ok(v);
+        }
+    }
+    Err(ProgramError::SolanaChannelUnresolved(
+        channel.to_string(),
+        path.display().to_string(),
+    ))
+}
+
+/// Runs `<command> --version` and extracts the version reported
+fn version_from_command<S: AsRef<OsStr>>(command: S) -> Option<String> {
+    let output = Command::new(command).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Locates the default solana install and returns the active version
+pub fn get_solana_installed_version() -> CargoResult<String> {
+    match home_dir() {
+        Some(p) => version_from_install(&p.join(SOLANA_INSTALL)),
+        None => Err(ProgramError::NoHomeFound),
+    }
+}
+
+/// Completes a version requirement such as `=1.14.17` or `~1.14` to X.Y.Z
+pub fn version_of_requirement(req: &str) -> Option<String> {
+    let re = Regex::new(r"(\d+)\.(\d+)(?:\.(\d+))?").unwrap();
+    re.captures(req).map(|c| {
+        format!(
+            "{}.{}.{}",
+            &c[1],
+            &c[2],
+            c.get(3).map_or("0", |m| m.as_str())
+        )
+    })
+}
+
+/// Collects the solana crate versions declared in a manifest's dependency
+/// tables, including `[workspace.dependencies]`
+fn manifest_solana_versions(path: &Path) -> CargoResult<Vec<VersionUse>> {
+    let doc = std::fs::read_to_string(path)?.parse::<DocumentMut>()?;
+    let mut tables = WORKSPACE_DEPENDENCY_TABLES
+        .iter()
+        .filter_map(|t| doc.get(t))
+        .collect::<Vec<_>>();
+    if let Some(deps) = doc.get("workspace").and_then(|w| w.get("dependencies")) {
+        tables.push(deps);
+    }
+    let mut uses = vec![];
+    for table in tables {
+        for krate in WORKSPACE_SOLANA_CRATES {
+            let req = match table.get(krate) {
+                Some(Item::Value(toml_edit::Value::String(s))) => Some(s.value().to_string()),
+                Some(dep) => dep
+                    .get("version")
+                    .and_then(Item::as_str)
+                    .map(str::to_string),
+                None => None,
+            };
+            if let Some(version) = req.as_deref().and_then(version_of_requirement) {
+                uses.push(VersionUse {
+                    source: path.to_path_buf(),
+                    krate: krate.to_string(),
+                    version,
+                });
+            }
+        }
+    }
+    Ok(uses)
+}
+
+/// Collects the locked solana crate versions from a Cargo.lock
+fn lock_solana_versions(path: &Path) -> CargoResult<Vec<VersionUse>> {
+    let doc = std::fs::read_to_string(path)?.parse::<DocumentMut>()?;
+    let mut uses = vec![];
+    if let Some(packages) = doc.get("package").and_then(Item::as_array_of_tables) {
+        for package in packages.iter() {
+            let name = package.get("name").and_then(Item::as_str);
+            let version = package.get("version").and_then(Item::as_str);
+            if let (Some(name), Some(version)) = (name, version) {
+                if WORKSPACE_SOLANA_CRATES.contains(&name) {
+                    uses.push(VersionUse {
+                        source: path.to_path_buf(),
+                        krate: name.to_string(),
+                        version: version.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(uses)
+}
+
+/// Finds the solana crate versions used in the workspace at `root`, locked
+/// versions first, then those declared by the root and member manifests
+pub fn workspace_solana_versions(root: &Path) -> CargoResult<Vec<VersionUse>> {
+    let mut uses = vec![];
+    let lock = root.join("Cargo.lock");
+    if lock.exists() {
+        uses.extend(lock_solana_versions(&lock)?);
+    }
+    let manifest = root.join("Cargo.toml");
+    if manifest.exists() {
+        uses.extend(manifest_solana_versions(&manifest)?);
+    }
+    for member in workspace_members(root)? {
+        uses.extend(manifest_solana_versions(&member.join("Cargo.toml"))?);
+    }
+    Ok(uses)
+}
+
+/// Describes any disagreement between the versions found in the workspace.
+/// Solana releases are compared by major and minor as a partial requirement
+/// such as `1.14` accepts any 1.14 patch release
+pub fn version_mismatches(uses: &[VersionUse]) -> Vec<String> {
+    let mut versions = uses
+        .iter()
+        .map(|u| {
+            u.version
+                .rsplit_once('.')
+                .map_or(u.version.as_str(), |v| v.0)
+        })
+        .collect::<Vec<_>>();
+    versions.sort();
+    versions.dedup();
+    if versions.len() < 2 {
+        return vec![];
+    }
+    uses.iter()
+        .map(|u| format!("{} {} in {}", u.krate, u.version, u.source.display()))
+        .collect()
+}
+
+/// Resolves the Solana version for a program added to the workspace at
+/// `root`, preferring what the workspace already uses over the install.
+/// An explicit version, or else the `SOLANA_VERSION` value in `from_env`,
+/// still wins. Mismatches are reported as warnings
+pub fn resolve_workspace_solana_version(
+    explicit: Option<&str>,
+    from_env: Option<&str>,
+    root: &Path,
+) -> CargoResult<String> {
+    workspace_version_with(explicit, from_env, root, installed_solana_version)
+}
+
+/// Resolves the workspace version, reading the install through `installed`
+fn workspace_version_with<F>(
+    explicit: Option<&str>,
+    from_env: Option<&str>,
+    root: &Path,
+    installed: F,
+) -> CargoResult<String>
+where
+    F: Fn() -> CargoResult<String>,
+{
+    let uses = workspace_solana_versions(root)?;
+    let mismatches = version_mismatches(&uses);
+    if !mismatches.is_empty() {
+        println!("Warning: workspace uses several Solana versions:");
+        for m in mismatches {
+            println!("    {}", m);
+        }
+    }
+    match (explicit.or(from_env), uses.first()) {
+        (Some(v), Some(used)) => {
+            let v = explicit_version(v)?;
+            if v != used.version {
+                println!(
+                    "Warning: Solana version {} differs from {} {} used in {}",
+                    v,
+                    used.krate,
+                    used.version,
+                    used.source.display()
+                );
+            }
+            Ok(v)
+        }
+        (Some(v), None) => explicit_version(v),
+        (None, Some(used)) => {
+            if let Ok(installed) = installed() {
+                if installed != used.version {
+                    println!(
+                        "Using Solana version {} from {} instead of installed {}",
+                        used.version,
+                        used.source.display(),
+                        installed
+                    );
+                }
+            }
+            Ok(used.version.clone())
+        }
+        (None, None) => installed(),
+    }
+}
+
+/// Resolves the Solana version from the first source that provides one,
+/// an explicit version, the `SOLANA_VERSION` value in `from_env` or the install
+pub fn resolve_solana_version(
+    explicit: Option<&str>,
+    from_env: Option<&str>,
+) -> CargoResult<String> {
+    match explicit.or(from_env) {
+        Some(v) => explicit_version(v),
+        None => installed_solana_version(),
+    }
+}
+
+/// Resolves the Solana version of the install, then of the binaries on the
+/// path, then of the install under `SOLANA_INSTALL_ROOT`
+fn installed_solana_version() -> CargoResult<String> {
+    let mut tried = vec![];
+    match get_solana_installed_version() {
+        Ok(v) => return Ok(v),
+        Err(e) => tried.push(e.to_string()),
+    }
+    for command in VERSION_COMMANDS {
+        match version_from_command(command) {
+            Some(v) => return Ok(v),
+            None => tried.push(format!("`{} --version` gave no version", command)),
+        }
+    }
+    match env::var(SOLANA_INSTALL_ROOT_ENV) {
+        Ok(root) => match version_from_install(&PathBuf::from(root)) {
+            Ok(v) => return Ok(v),
+            Err(e) => tried.push(e.to_string()),
+        },
+        Err(_) => tried.push(format!("{} not set", SOLANA_INSTALL_ROOT_ENV)),
+    }
+    Err(ProgramError::SolanaVersionUnresolved(tried.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Creates an install root with the given version.yml
+    fn install_with(version_yml: &str) -> tempfile::TempDir {
+        let root = tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join(ACTIVE_RELEASE_BIN)).unwrap();
+        std::fs::write(root.path().join(ACTIVE_RELEASE_VERSION), version_yml).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_version_info_pass() {
+        let root = install_with(
+            "---\nchannel: v1.10.0\ncommit: 1a2b3c\ntarget: x86_64-unknown-linux-gnu\n",
+        );
+        assert_eq!(version_from_install(root.path()).unwrap(), "1.10.0");
+    }
+
+    #[test]
+    fn named_channel_release_pass() {
+        let root = install_with("---\nchannel: stable\nrelease: 1.18.26\n");
+        assert_eq!(version_from_install(root.path()).unwrap(), "1.18.26");
+    }
+
+    #[test]
+    fn named_channel_binary_pass() {
+        let root = install_with("---\nchannel: beta\ntarget: x86_64-unknown-linux-gnu\n");
+        let solana = root.path().join(ACTIVE_RELEASE_BIN).join("solana");
+        let version = install_version_with(root.path(), |command| {
+            (command == solana)
+                .then(|| parse_version("solana-cli 1.17.3 (src:0a1b; feat:1)"))
+                .flatten()
+        });
+        assert_eq!(version.unwrap(), "1.17.3");
+    }
+
+    #[test]
+    fn named_channel_unresolved_fail() {
+        let root = install_with("---\nchannel: stable\ntarget: x86_64-unknown-linux-gnu\n");
+        assert!(matches!(
+            version_from_install(root.path()),
+            Err(ProgramError::SolanaChannelUnresolved(c, _)) if c == "stable"
+        ));
+    }
+
+    #[test]
+    fn channel_missing_fail() {
+        for yml in ["---\ncommit: 1a2b3c\n", "---\nchannel: 110\n", ""] {
+            let root = install_with(yml);
+            assert!(matches!(
+                version_from_install(root.path()),
+                Err(ProgramError::SolanaVersionMissing(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn install_missing_fail() {
+        let root = tempdir().unwrap();
+        assert!(matches!(
+            version_from_install(root.path()),
+            Err(ProgramError::SolanaNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn parse_version_output_pass() {
+        assert_eq!(
+            parse_version("solana-cli 1.14.17 (src:b29a37cf; feat:3488713414)").unwrap(),
+            "1.14.17"
+        );
+        assert_eq!(
+            parse_version("solana-cargo-build-sbf 1.18.26").unwrap(),
+            "1.18.26"
+        );
+        assert!(parse_version("solana-cli unknown").is_none());
+    }
+
+    #[test]
+    fn explicit_version_pass() {
+        assert_eq!(
+            resolve_solana_version(Some("1.16.3"), None).unwrap(),
+            "1.16.3"
+        );
+        assert_eq!(
+            resolve_solana_version(Some("v1.16.3"), None).unwrap(),
+            "1.16.3"
+        );
+        assert_eq!(
+            resolve_solana_version(None, Some("1.17.3")).unwrap(),
+            "1.17.3"
+        );
+        assert_eq!(
+            resolve_solana_version(Some("1.16.3"), Some("1.17.3")).unwrap(),
+            "1.16.3"
+        );
+    }
+
+    #[test]
+    fn explicit_version_fail() {
+        assert!(matches!(
+            resolve_solana_version(Some("latest"), None),
+            Err(ProgramError::InvalidSolanaVersion(_))
+        ));
+    }
+
+    #[test]
+    fn version_of_requirement_pass() {
+        assert_eq!(version_of_requirement("=1.14.17").unwrap(), "1.14.17");
+        assert_eq!(version_of_requirement("~1.14").unwrap(), "1.14.0");
+        assert!(version_of_requirement("*").is_none());
+    }
+
+    #[test]
+    fn workspace_solana_versions_pass() {
+        let root = tempdir().unwrap();
+        std::fs::create_dir(root.path().join("program")).unwrap();
+        std::fs::write(
+            root.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"program\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.path().join("program/Cargo.toml"),
+            "[package]\nname = \"a\"\n\n[dependencies]\nsolana-program = \"=1.14.17\"\n\n\
+            [dev-dependencies]\nsolana-sdk = { version = \"1.14\" }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.path().join("Cargo.lock"),
+            "version = 3\n\n[[package]]\nname = \"solana-program\"\nversion = \"1.14.17\"\n\n\
+            [[package]]\nname = \"solana-sdk\"\nversion = \"1.14.17\"\n",
+        )
+        .unwrap();
+        let uses = workspace_solana_versions(root.path()).unwrap();
+        assert_eq!(uses.len(), 4);
+        assert_eq!(uses[0].source, root.path().join("Cargo.lock"));
+        assert_eq!(uses[0].version, "1.14.17");
+        assert_eq!(uses[3].version, "1.14.0");
+        assert!(version_mismatches(&uses).is_empty());
+        // Stub the install so the test does not read the host one
+        let install = install_with("---\nchannel: stable\n");
+        let installed = || install_version_with(install.path(), |_| Some("1.16.0".to_string()));
+        assert_eq!(
+            workspace_version_with(None, None, root.path(), installed).unwrap(),
+            "1.14.17"
+        );
+        let empty = tempdir().unwrap();
+        std::fs::write(
+            empty.path().join("Cargo.toml"),
+            "[workspace]\nmembers = []\n",
+        )
+        .unwrap();
+        assert_eq!(
+            workspace_version_with(None, None, empty.path(), installed).unwrap(),
+            "1.16.0"
+        );
+        assert_eq!(
+            resolve_workspace_solana_version(Some("1.16.0"), None, root.path()).unwrap(),
+            "1.16.0"
+        );
+        assert_eq!(
+            resolve_workspace_solana_version(None, Some("1.17.3"), root.path()).unwrap(),
+            "1.17.3"
+        );
+        assert_eq!(
+            resolve_workspace_solana_version(Some("1.16.0"), Some("1.17.3"), root.path()).unwrap(),
+            "1.16.0"
+        );
+    }
+
+    #[test]
+    fn version_mismatches_pass() {
+        let uses = vec![
+            VersionUse {
+                source: PathBuf::from("Cargo.lock"),
+                krate: "solana-program".to_string(),
+                version: "1.14.17".to_string(),
+            },
+            VersionUse {
+                source: PathBuf::from("program/Cargo.toml"),
+                krate: "solana-sdk".to_string(),
+                version: "1.16.0".to_string(),
+            },
+        ];
+        assert_eq!(
+            version_mismatches(&uses),
+            vec![
+                "solana-program 1.14.17 in Cargo.lock",
+                "solana-sdk 1.16.0 in program/Cargo.toml"
+            ]
+        );
+    }
+
+    #[test]
+    fn version_mismatches_none_pass() {
+        let uses = vec![
+            VersionUse {
+                source: PathBuf::from("Cargo.lock"),
+                krate: "solana-program".to_string(),
+                version: "1.14.17".to_string(),
+            },
+            VersionUse {
+                source: PathBuf::from("Cargo.toml"),
+                krate: "solana-sdk".to_string(),
+                version: "1.14.17".to_string(),
+            },
+        ];
+        assert!(version_mismatches(&uses).is_empty());
+    }
+}
@@ -0,0 +1,851 @@
+//! Workspace manifest editing
+//!
+//! Edits are made on the manifest text so comments, ordering and
+//! formatting of the original are preserved.
+
+use crate::error::{CargoResult, ProgramError};
+use std::path::{Component, Path, PathBuf};
+use toml_edit::{value, Array, DocumentMut, InlineTable, Item, Table, Value};
+
+/// Manifest tables that must stay with the workspace root
+const WORKSPACE_ROOT_TABLES: [&str; 5] =
+    ["cargo-features", "workspace", "profile", "patch", "replace"];
+/// Target tables that carry a `path` to a source file
+const TARGET_TABLES: [&str; 5] = ["lib", "bin", "example", "test", "bench"];
+/// Package keys that carry a path to a file
+const PACKAGE_PATH_KEYS: [&str; 3] = ["build", "readme", "license-file"];
+/// Dependency tables whose entries may carry a `path`
+const DEPENDENCY_TABLES: [&str; 5] = [
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+/// Program dependency tables whose entries may be hoisted to the workspace
+const HOIST_TABLES: [&str; 2] = ["dependencies", "dev-dependencies"];
+/// Solana crates released together with the validator, under its version.
+/// Other `solana-` crates such as `solana-security-txt` version on their own
+const SOLANA_LOCKSTEP_CRATES: [&str; 24] = [
+    "solana-account-decoder",
+    "solana-banks-client",
+    "solana-banks-interface",
+    "solana-clap-utils",
+    "solana-cli-config",
+    "solana-client",
+    "solana-faucet",
+    "solana-frozen-abi",
+    "solana-frozen-abi-macro",
+    "solana-logger",
+    "solana-program",
+    "solana-program-runtime",
+    "solana-program-test",
+    "solana-pubsub-client",
+    "solana-remote-wallet",
+    "solana-rpc-client",
+    "solana-rpc-client-api",
+    "solana-runtime",
+    "solana-sdk",
+    "solana-sdk-macro",
+    "solana-test-validator",
+    "solana-transaction-status",
+    "solana-version",
+    "solana-zk-token-sdk",
+];
+/// Package folders and files moved along with the package manifest
+pub const PACKAGE_ENTRIES: [&str; 5] = ["src", "build.rs", "benches", "examples", "tests"];
+
+/// Matches a path against a workspace member entry which may
+/// use `*` and `?` wildcards within a path segment
+fn member_matches(pattern: &str, path: &str) -> bool {
+    fn segment(p: &[u8], s: &[u8]) -> bool {
+        match (p.first(), s.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => segment(&p[1..], s) || (!s.is_empty() && segment(p, &s[1..])),
+            (Some(b'?'), Some(_)) => segment(&p[1..], &s[1..]),
+            (Some(a), Some(b)) if a == b => segment(&p[1..], &s[1..]),
+            _ => false,
+        }
+    }
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    let pattern_parts = pattern.split('/').collect::<Vec<_>>();
+    let path_parts = path.split('/').collect::<Vec<_>>();
+    pattern_parts.len() == path_parts.len()
+        && pattern_parts
+            .iter()
+            .zip(path_parts.iter())
+            .all(|(p, s)| segment(p.as_bytes(), s.as_bytes()))
+}
+
+/// True if the program folder is already covered by the workspace members
+pub fn member_listed(members: &[String], path: &str) -> bool {
+    members.iter().any(|m| member_matches(m, path))
+}
+
+/// Expands workspace member entries, including wildcard segments, into the
+/// member folders below `root` that hold a Cargo.toml
+pub fn expand_members(root: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let mut members = vec![];
+    for pattern in patterns {
+        let mut candidates = vec![root.to_path_buf()];
+        for part in pattern.trim_start_matches("./").split('/') {
+            if part.is_empty() || part == "." {
+                continue;
+            }
+            if !part.contains(['*', '?']) {
+                candidates = candidates.into_iter().map(|c| c.join(part)).collect();
+                continue;
+            }
+            let mut expanded = candidates
+                .iter()
+                .filter_map(|c| std::fs::read_dir(c).ok())
+                .flatten()
+                .filter_map(Result::ok)
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.is_dir()
+                        && p.file_name()
+                            .is_some_and(|n| member_matches(part, &n.to_string_lossy()))
+                })
+                .collect::<Vec<_>>();
+            expanded.sort();
+            candidates = expanded;
+        }
+        for candidate in candidates {
+            if candidate.join("Cargo.toml").exists() && !members.contains(&candidate) {
+                members.push(candidate);
+            }
+        }
+    }
+    members
+}
+
+/// Reads `[workspace].members` of the manifest in `root` and expands them
+pub fn workspace_members(root: &Path) -> CargoResult<Vec<PathBuf>> {
+    let manifest_path = root.join("Cargo.toml");
+    if !manifest_path.exists() {
+        return Ok(vec![]);
+    }
+    let doc = std::fs::read_to_string(manifest_path)?.parse::<DocumentMut>()?;
+    let patterns = doc
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(Item::as_array)
+        .map(|a| {
+            a.iter()
+                .filter_map(|m| m.as_str().map(str::to_string))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    Ok(expand_members(root, &patterns))
+}
+
+/// Appends to an array following the layout of the existing entries,
+/// one per line when the array is already laid out that way
+fn push_formatted(array: &mut Array, entry: &str) {
+    let multiline = array
+        .iter()
+        .last()
+        .and_then(|v| v.decor().prefix())
+        .and_then(|p| p.as_str())
+        .is_some_and(|p| p.contains('\n'))
+        || array.trailing().as_str().is_some_and(|t| t.contains('\n'));
+    let mut entry = Value::from(entry);
+    if multiline {
+        let indent = array
+            .iter()
+            .last()
+            .and_then(|v| v.decor().prefix())
+            .and_then(|p| p.as_str())
+            .and_then(|p| p.rsplit('\n').next())
+            .unwrap_or("    ")
+            .to_string();
+        entry.decor_mut().set_prefix(format!("\n{}", indent));
+        if array.trailing().as_str().is_none_or(str::is_empty) {
+            array.set_trailing("\n");
+        }
+        array.set_trailing_comma(true);
+    } else if !array.is_empty() {
+        entry.decor_mut().set_prefix(" ");
+    }
+    array.push_formatted(entry);
+}
+
+/// Adds `member` to `[workspace].members` in the manifest text, creating the
+/// workspace table or members array as needed. Members already covered by an
+/// existing entry are left as is. Returns the updated manifest text
+pub fn add_workspace_member(manifest: &str, member: &str) -> CargoResult<String> {
+    let mut doc = manifest.parse::<DocumentMut>()?;
+    if !doc.contains_key("workspace") {
+        doc["workspace"] = Item::Table(Table::new());
+    }
+    let workspace = doc["workspace"]
+        .as_table_like_mut()
+        .ok_or_else(|| ProgramError::ManifestFormatError("workspace".to_string()))?;
+    if !workspace.contains_key("members") {
+        workspace.insert("members", value(Array::new()));
+    }
+    let members = workspace
+        .get_mut("members")
+        .and_then(Item::as_array_mut)
+        .ok_or_else(|| ProgramError::ManifestFormatError("workspace.members".to_string()))?;
+    let listed = members
+        .iter()
+        .filter_map(|m| m.as_str().map(str::to_string))
+        .collect::<Vec<_>>();
+    if !member_listed(&listed, member) {
+        push_formatted(members, member);
+    }
+    Ok(doc.to_string())
+}
+
+/// Replaces the versions of `crates` in the manifest's dependency tables with
+/// `workspace = true`, returning the crates and versions to declare in the
+/// workspace root. Entries without a version, such as path dependencies,
+/// are left alone
+pub fn hoist_dependencies(doc: &mut DocumentMut, crates: &[&str]) -> Vec<(String, String)> {
+    let mut hoisted = vec![];
+    for table in HOIST_TABLES {
+        let deps = match doc.get_mut(table).and_then(Item::as_table_like_mut) {
+            Some(deps) => deps,
+            None => continue,
+        };
+        for krate in crates {
+            let entry = match deps.get_mut(krate) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if let Some(version) = entry.as_str().map(str::to_string) {
+                let mut inherited = InlineTable::new();
+                inherited.insert("workspace", Value::from(true));
+                *entry = value(inherited);
+                hoisted.push((krate.to_string(), version));
+            } else if let Some(detail) = entry.as_inline_table_mut() {
+                if let Some(version) = detail.remove("version") {
+                    if let Some(version) = version.as_str() {
+                        hoisted.push((krate.to_string(), version.to_string()));
+                    }
+                    // Lead with the inheritance, keeping features and the like
+                    let mut inherited = InlineTable::new();
+                    inherited.insert("workspace", Value::from(true));
+                    for (k, v) in detail.iter() {
+                        inherited.insert(k, v.clone());
+                    }
+                    inherited.fmt();
+                    *entry = value(inherited);
+                }
+            } else if let Some(detail) = entry.as_table_like_mut() {
+                if let Some(version) = detail.remove("version") {
+                    if let Some(version) = version.as_str() {
+                        hoisted.push((krate.to_string(), version.to_string()));
+                    }
+                    detail.insert("workspace", value(true));
+                }
+            }
+        }
+    }
+    hoisted
+}
+
+/// True for an entry inheriting from the workspace, `workspace = true`
+fn inherits(item: &Item) -> bool {
+    item.get("workspace").and_then(Item::as_bool) == Some(true)
+}
+
+/// Replaces the entries of a member manifest inherited with
+/// `workspace = true` by those the `root` manifest declares, the reverse of
+/// `hoist_dependencies`. Package fields are taken from `[workspace.package]`
+/// and dependencies from `[workspace.dependencies]`, merging the features
+/// and other keys of the member entry. Entries the root does not declare
+/// are left alone
+pub fn inline_workspace_entries(doc: &mut DocumentMut, root: &DocumentMut) {
+    let declared = |table: &str, key: &str| {
+        root.get("workspace")
+            .and_then(|w| w.get(table))
+            .and_then(|t| t.get(key))
+    };
+    if let Some(package) = doc.get_mut("package").and_then(Item::as_table_like_mut) {
+        for (key, entry) in package.iter_mut() {
+            match declared("package", key.get()).and_then(Item::as_value) {
+                Some(v) if inherits(entry) => *entry = value(v.clone()),
+                _ => {}
+            }
+        }
+    }
+    for table in HOIST_TABLES {
+        let deps = match doc.get_mut(table).and_then(Item::as_table_like_mut) {
+            Some(deps) => deps,
+            None => continue,
+        };
+        for (krate, entry) in deps.iter_mut() {
+            let root_entry = match declared("dependencies", krate.get()) {
+                Some(root_entry) if inherits(entry) => root_entry,
+                _ => continue,
+            };
+            let mut inlined = InlineTable::new();
+            if let Some(version) = root_entry.as_str() {
+                inlined.insert("version", Value::from(version));
+            } else if let Some(detail) = root_entry.as_table_like() {
+                for (k, v) in detail.iter() {
+                    inlined.extend(v.as_value().map(|v| (k, v.clone())));
+                }
+            }
+            for (k, v) in entry.as_table_like().into_iter().flat_map(|t| t.iter()) {
+                let v = match (k, v.as_value()) {
+                    ("workspace", _) | (_, None) => continue,
+                    // Features add up with those of the root entry
+                    ("features", Some(Value::Array(features))) => match inlined.get(k) {
+                        Some(Value::Array(root_features)) => {
+                            let added = features.iter().filter(|f| {
+                                !root_features.iter().any(|r| r.as_str() == f.as_str())
+                            });
+                            let mut merged = root_features
+                                .iter()
+                                .chain(added)
+                                .cloned()
+                                .collect::<Array>();
+                            merged.fmt();
+                            Value::Array(merged)
+                        }
+                        _ => Value::Array(features.clone()),
+                    },
+                    (_, Some(v)) => v.clone(),
+                };
+                inlined.insert(k, v);
+            }
+            inlined.fmt();
+            *entry = match (
+                inlined.len(),
+                inlined.get("version").and_then(Value::as_str),
+            ) {
+                (1, Some(version)) => value(version),
+                _ => value(inlined),
+            };
+        }
+    }
+}
+
+/// A dependency version requirement rewritten in a manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionChange {
+    pub krate: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Builds the requirement for `version` keeping the operator of `req`,
+/// so `=1.14.17` becomes `=1.16.0`. Compound or wildcard requirements
+/// are replaced by the plain version
+fn requirement_for(req: &str, version: &str) -> String {
+    let op = req
+        .trim()
+        .split(|c: char| c.is_ascii_digit())
+        .next()
+        .unwrap_or("");
+    match req.contains([',', '*']) || op.len() == req.trim().len() {
+        true => version.to_string(),
+        false => format!("{}{}", op.trim(), version),
+    }
+}
+
+/// Replaces a string value keeping its surrounding whitespace and comments
+fn replace_str(v: &mut Value, text: &str) {
+    let decor = v.decor().clone();
+    *v = Value::from(text);
+    *v.decor_mut() = decor;
+}
+
+/// Sets the version requirement of `krate` in the dependency tables,
+/// keeping features and other keys of detailed entries. Entries without
+/// a version, such as path dependencies, are left alone
+pub fn set_dependency_version(doc: &mut DocumentMut, krate: &str, version: &str) {
+    for table in HOIST_TABLES {
+        let entry = match doc
+            .get_mut(table)
+            .and_then(Item::as_table_like_mut)
+            .and_then(|deps| deps.get_mut(krate))
+        {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let req = match entry {
+            Item::Value(Value::String(_)) => entry.as_value_mut(),
+            _ => entry
+                .as_table_like_mut()
+                .and_then(|t| t.get_mut("version"))
+                .and_then(Item::as_value_mut),
+        };
+        if let Some(req) = req.filter(|r| r.is_str()) {
+            replace_str(req, version);
+        }
+    }
+}
+
+/// Sets the version of the Solana crates released with the validator in the
+/// dependency tables, including `[workspace.dependencies]`, to `version`. Entries inheriting
+/// from the workspace or without a version are left alone. Returns the
+/// requirements that changed
+pub fn set_solana_versions(doc: &mut DocumentMut, version: &str) -> Vec<VersionChange> {
+    let mut changes = vec![];
+    let mut tables = vec![];
+    for (name, item) in doc.iter_mut() {
+        if HOIST_TABLES.contains(&name.get()) {
+            tables.extend(item.as_table_like_mut());
+        } else if name == "workspace" {
+            tables.extend(
+                item.get_mut("dependencies")
+                    .and_then(Item::as_table_like_mut),
+            );
+        }
+    }
+    for deps in tables {
+        for (krate, entry) in deps.iter_mut() {
+            if !SOLANA_LOCKSTEP_CRATES.contains(&krate.get()) {
+                continue;
+            }
+            let req = match entry {
+                Item::Value(Value::String(_)) => entry.as_value_mut(),
+                _ => entry
+                    .as_table_like_mut()
+                    .and_then(|t| t.get_mut("version"))
+                    .and_then(Item::as_value_mut),
+            };
+            let req = match req {
+                Some(req) if req.is_str() => req,
+                _ => continue,
+            };
+            let from = req.as_str().unwrap_or_default().to_string();
+            let to = requirement_for(&from, version);
+            if from != to {
+                replace_str(req, &to);
+                changes.push(VersionChange {
+                    krate: krate.to_string(),
+                    from,
+                    to,
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Adds `(crate, version)` entries to `[workspace.dependencies]` in the
+/// manifest text. Entries already declared are kept, with differing
+/// versions reported. Returns the updated manifest text
+pub fn add_workspace_dependencies(
+    manifest: &str,
+    deps: &[(String, String)],
+) -> CargoResult<String> {
+    let mut doc = manifest.parse::<DocumentMut>()?;
+    if !doc.contains_key("workspace") {
+        doc["workspace"] = Item::Table(Table::new());
+    }
+    let workspace = doc["workspace"]
+        .as_table_like_mut()
+        .ok_or_else(|| ProgramError::ManifestFormatError("workspace".to_string()))?;
+    if !workspace.contains_key("dependencies") {
+        workspace.insert("dependencies", Item::Table(Table::new()));
+    }
+    let table = workspace
+        .get_mut("dependencies")
+        .and_then(Item::as_table_like_mut)
+        .ok_or_else(|| ProgramError::ManifestFormatError("workspace.dependencies".to_string()))?;
+    for (krate, version) in deps {
+        match table.get(krate) {
+            Some(existing) => {
+                let existing = existing
+                    .as_str()
+                    .or_else(|| existing.get("version").and_then(Item::as_str));
+                if existing != Some(version.as_str()) {
+                    println!(
+                        "Warning: keeping workspace {} {} instead of {}",
+                        krate,
+                        existing.unwrap_or("without version"),
+                        version
+                    );
+                }
+            }
+            None => {
+                table.insert(krate, value(version.as_str()));
+            }
+        }
+    }
+    Ok(doc.to_string())
+}
+
+/// Rebases a path relative to the old package root so it resolves the same
+/// from `member_dir`, unless it falls under one of the `moved` entries
+fn rebase_path(path: &str, member_dir: &str, moved: &[String]) -> String {
+    let p = Path::new(path);
+    let first = p.components().find(|c| !matches!(c, Component::CurDir));
+    let is_moved = match first {
+        Some(Component::Normal(c)) => moved.iter().any(|m| c == m.as_str()),
+        _ => false,
+    };
+    if p.is_absolute() || is_moved {
+        return path.to_string();
+    }
+    let depth = Path::new(member_dir)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count();
+    format!("{}{}", "../".repeat(depth), path.trim_start_matches("./"))
+}
+
+/// Rebases a string value in place, keeping its decoration
+fn rebase_item(item: &mut Item, member_dir: &str, moved: &[String]) {
+    if let Some(v) = item.as_value_mut() {
+        if let Some(path) = v.as_str() {
+            let decor = v.decor().clone();
+            *v = Value::from(rebase_path(path, member_dir, moved));
+            *v.decor_mut() = decor;
+        }
+    }
+}
+
+/// Rebases the `path` of every entry in a dependency table
+fn rebase_dependencies(deps: &mut dyn toml_edit::TableLike, member_dir: &str, moved: &[String]) {
+    for (_, dep) in deps.iter_mut() {
+        if let Some(path) = dep.as_table_like_mut().and_then(|d| d.get_mut("path")) {
+            rebase_item(path, member_dir, moved);
+        }
+    }
+}
+
+/// Splits a single package root manifest into a virtual workspace root and
+/// a member manifest for the package relocated to `member_dir`. Relative
+/// paths in the member are rebased unless they point into the `moved`
+/// entries, which travel with the package. Returns (root, member) text
+pub fn split_package_manifest(
+    manifest: &str,
+    member_dir: &str,
+    moved: &[String],
+) -> CargoResult<(String, String)> {
+    let mut root = manifest.parse::<DocumentMut>()?;
+    if !root.contains_key("package") {
+        return Err(ProgramError::NoRootPackage);
+    }
+    let mut member = DocumentMut::new();
+    let keys = root
+        .iter()
+        .map(|(k, _)| k.to_string())
+        .filter(|k| !WORKSPACE_ROOT_TABLES.contains(&k.as_str()))
+        .collect::<Vec<_>>();
+    for key in keys {
+        if let Some(item) = root.remove(&key) {
+            member.insert(&key, item);
+        }
+    }
+    // Fix up the paths now relative to the member folder
+    if let Some(package) = member["package"].as_table_like_mut() {
+        // The member belongs to the root workspace now
+        package.remove("workspace");
+        for key in PACKAGE_PATH_KEYS {
+            if let Some(item) = package.get_mut(key) {
+                rebase_item(item, member_dir, moved);
+            }
+        }
+    }
+    for key in TARGET_TABLES {
+        match member.get_mut(key) {
+            Some(Item::Table(t)) => {
+                if let Some(path) = t.get_mut("path") {
+                    rebase_item(path, member_dir, moved);
+                }
+            }
+            Some(Item::ArrayOfTables(targets)) => {
+                for t in targets.iter_mut() {
+                    if let Some(path) = t.get_mut("path") {
+                        rebase_item(path, member_dir, moved);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for key in DEPENDENCY_TABLES {
+        if let Some(deps) = member.get_mut(key).and_then(Item::as_table_like_mut) {
+            rebase_dependencies(deps, member_dir, moved);
+        }
+    }
+    if let Some(targets) = member.get_mut("target").and_then(Item::as_table_like_mut) {
+        for (_, target) in targets.iter_mut() {
+            if let Some(target) = target.as_table_like_mut() {
+                for key in DEPENDENCY_TABLES {
+                    if let Some(deps) = target.get_mut(key).and_then(Item::as_table_like_mut) {
+                        rebase_dependencies(deps, member_dir, moved);
+                    }
+                }
+            }
+        }
+    }
+    // Virtual workspaces do not pick up the resolver from the package edition
+    let edition_2021 = member["package"]
+        .get("edition")
+        .and_then(Item::as_str)
+        .is_some_and(|e| e >= "2021");
+    let mut root_text = add_workspace_member(&root.to_string(), member_dir)?;
+    if edition_2021 {
+        let mut doc = root_text.parse::<DocumentMut>()?;
+        if doc["workspace"].get("resolver").is_none() {
+            doc["workspace"]["resolver"] = value("2");
+        }
+        root_text = doc.to_string();
+    }
+    Ok((root_text.trim_start().to_string(), member.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_listed_pass() {
+        let members = vec!["program".to_string(), "programs/*".to_string()];
+        assert!(member_listed(&members, "program"));
+        assert!(member_listed(&members, "./program/"));
+        assert!(member_listed(&members, "programs/foo"));
+        assert!(!member_listed(&members, "programs/foo/bar"));
+        assert!(!member_listed(&members, "other"));
+    }
+
+    #[test]
+    fn workspace_members_pass() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["cli", "programs/foo", "programs/bar", "programs/empty"] {
+            std::fs::create_dir_all(root.path().join(dir)).unwrap();
+            if dir != "programs/empty" {
+                std::fs::write(root.path().join(dir).join("Cargo.toml"), "").unwrap();
+            }
+        }
+        std::fs::write(
+            root.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"cli\", \"programs/*\", \"missing\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            workspace_members(root.path()).unwrap(),
+            vec![
+                root.path().join("cli"),
+                root.path().join("programs/bar"),
+                root.path().join("programs/foo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn add_member_preserves_format_pass() {
+        let original = "# Our workspace\n\
+            [workspace]\n\
+            # Programs live here\n\
+            members = [\n    \"cli\", # the client\n    \"program-a\",\n]\n\n\
+            [profile.release]\n\
+            lto = true # keep\n";
+        let updated = add_workspace_member(original, "programs/foo").unwrap();
+        assert_eq!(
+            updated,
+            "# Our workspace\n\
+            [workspace]\n\
+            # Programs live here\n\
+            members = [\n    \"cli\", # the client\n    \"program-a\",\n    \"programs/foo\",\n]\n\n\
+            [profile.release]\n\
+            lto = true # keep\n"
+        );
+    }
+
+    #[test]
+    fn add_member_inline_pass() {
+        let updated = add_workspace_member("[workspace]\nmembers = [\"cli\"]\n", "program");
+        assert_eq!(
+            updated.unwrap(),
+            "[workspace]\nmembers = [\"cli\", \"program\"]\n"
+        );
+    }
+
+    #[test]
+    fn add_member_new_workspace_pass() {
+        let original = "[package]\nname = \"foo\" # root\nversion = \"0.1.0\"\n";
+        let updated = add_workspace_member(original, "program").unwrap();
+        assert_eq!(
+            updated,
+            "[package]\nname = \"foo\" # root\nversion = \"0.1.0\"\n\n[workspace]\nmembers = [\"program\"]\n"
+        );
+    }
+
+    #[test]
+    fn add_member_listed_unchanged_pass() {
+        let original = "[workspace]\nmembers = [\"programs/*\"] # all\n";
+        let updated = add_workspace_member(original, "programs/foo").unwrap();
+        assert_eq!(updated, original);
+    }
+
+    #[test]
+    fn hoist_dependencies_pass() {
+        let mut doc = "[dependencies]\n\
+            borsh = \"0.9.3\"\n\
+            solana-program = { version = \"1.10.0\", features = [\"x\"] }\n\
+            local = { path = \"../local\" }\n\n\
+            [dev-dependencies]\n\
+            solana-sdk = \"1.10.0\" # sdk\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let hoisted = hoist_dependencies(
+            &mut doc,
+            &["borsh", "solana-program", "solana-sdk", "local"],
+        );
+        assert_eq!(
+            hoisted,
+            vec![
+                ("borsh".to_string(), "0.9.3".to_string()),
+                ("solana-program".to_string(), "1.10.0".to_string()),
+                ("solana-sdk".to_string(), "1.10.0".to_string()),
+            ]
+        );
+        assert_eq!(
+            doc.to_string(),
+            "[dependencies]\n\
+            borsh = { workspace = true }\n\
+            solana-program = { workspace = true, features = [\"x\"] }\n\
+            local = { path = \"../local\" }\n\n\
+            [dev-dependencies]\n\
+            solana-sdk = { workspace = true }\n"
+        );
+    }
+
+    #[test]
+    fn set_solana_versions_pass() {
+        let mut doc = "[dependencies]\n\
+            borsh = \"0.9.3\"\n\
+            solana-program = \"=1.14.17\" # exact\n\n\
+            [dev-dependencies.solana-sdk]\n\
+            version = \">=1.14, <1.17\"\n\n\
+            [workspace.dependencies]\n\
+            solana-program-test = { version = \"1.16.0\" }\n\
+            solana-local = { path = \"../local\" }\n\
+            solana-security-txt = \"1.1.1\"\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        let changes = set_solana_versions(&mut doc, "1.16.0");
+        assert_eq!(
+            changes,
+            vec![
+                VersionChange {
+                    krate: "solana-program".to_string(),
+                    from: "=1.14.17".to_string(),
+                    to: "=1.16.0".to_string(),
+                },
+                VersionChange {
+                    krate: "solana-sdk".to_string(),
+                    from: ">=1.14, <1.17".to_string(),
+                    to: "1.16.0".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            doc.to_string(),
+            "[dependencies]\n\
+            borsh = \"0.9.3\"\n\
+            solana-program = \"=1.16.0\" # exact\n\n\
+            [dev-dependencies.solana-sdk]\n\
+            version = \"1.16.0\"\n\n\
+            [workspace.dependencies]\n\
+            solana-program-test = { version = \"1.16.0\" }\n\
+            solana-local = { path = \"../local\" }\n\
+            solana-security-txt = \"1.1.1\"\n"
+        );
+    }
+
+    #[test]
+    fn set_dependency_version_pass() {
+        let mut doc = "[dependencies]\nborsh = \"0.9.3\" # serialization\n\
+            spl-token = { version = \"3.5\", features = [\"no-entrypoint\"] }\n\
+            local = { path = \"../local\" }\n"
+            .parse::<DocumentMut>()
+            .unwrap();
+        set_dependency_version(&mut doc, "borsh", "0.10.3");
+        set_dependency_version(&mut doc, "spl-token", "4.0");
+        set_dependency_version(&mut doc, "local", "1.0");
+        set_dependency_version(&mut doc, "missing", "1.0");
+        assert_eq!(
+            doc.to_string(),
+            "[dependencies]\nborsh = \"0.10.3\" # serialization\n\
+            spl-token = { version = \"4.0\", features = [\"no-entrypoint\"] }\n\
+            local = { path = \"../local\" }\n"
+        );
+    }
+
+    #[test]
+    fn add_workspace_dependencies_pass() {
+        let original = "[workspace]\nmembers = [\"program\"]\n\n[workspace.dependencies]\nborsh = \"0.10.0\"\n";
+        let deps = vec![
+            ("borsh".to_string(), "0.9.3".to_string()),
+            ("solana-program".to_string(), "1.10.0".to_string()),
+        ];
+        assert_eq!(
+            add_workspace_dependencies(original, &deps).unwrap(),
+            "[workspace]\nmembers = [\"program\"]\n\n[workspace.dependencies]\nborsh = \"0.10.0\"\nsolana-program = \"1.10.0\"\n"
+        );
+        assert_eq!(
+            add_workspace_dependencies("[workspace]\nmembers = []\n", &deps[1..]).unwrap(),
+            "[workspace]\nmembers = []\n\n[workspace.dependencies]\nsolana-program = \"1.10.0\"\n"
+        );
+    }
+
+    #[test]
+    fn rebase_path_pass() {
+        let moved = vec!["src".to_string(), "tests".to_string()];
+        assert_eq!(rebase_path("src/main.rs", "app", &moved), "src/main.rs");
+        assert_eq!(rebase_path("./src/main.rs", "app", &moved), "./src/main.rs");
+        assert_eq!(rebase_path("README.md", "app", &moved), "../README.md");
+        assert_eq!(
+            rebase_path("../other", "crates/app", &moved),
+            "../../../other"
+        );
+        assert_eq!(rebase_path("/abs/path", "app", &moved), "/abs/path");
+    }
+
+    #[test]
+    fn split_package_manifest_pass() {
+        let original = "# Root package\n\
+            [package]\n\
+            name = \"app\"\n\
+            edition = \"2021\"\n\
+            readme = \"README.md\"\n\n\
+            [[bin]]\n\
+            name = \"app\"\n\
+            path = \"src/main.rs\"\n\n\
+            [dependencies]\n\
+            util = { path = \"util\" } # local\n\n\
+            [profile.release]\n\
+            lto = true\n";
+        let moved = vec!["src".to_string()];
+        let (root, member) = split_package_manifest(original, "app", &moved).unwrap();
+        assert_eq!(
+            root,
+            "[profile.release]\nlto = true\n\n[workspace]\nmembers = [\"app\"]\nresolver = \"2\"\n"
+        );
+        assert_eq!(
+            member,
+            "# Root package\n\
+            [package]\n\
+            name = \"app\"\n\
+            edition = \"2021\"\n\
+            readme = \"../README.md\"\n\n\
+            [[bin]]\n\
+            name = \"app\"\n\
+            path = \"src/main.rs\"\n\n\
+            [dependencies]\n\
+            util = { path = \"../util\" } # local\n"
+        );
+    }
+
+    #[test]
+    fn split_package_manifest_fail() {
+        let original = "[workspace]\nmembers = []\n";
+        assert!(matches!(
+            split_package_manifest(original, "app", &[]),
+            Err(ProgramError::NoRootPackage)
+        ));
+    }
+}